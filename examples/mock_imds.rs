@@ -0,0 +1,32 @@
+//! Serve `MockImds` until killed, for tests that aren't written in Rust,
+//! e.g. `ffi/test.sh`. It prints the endpoint on the first line of stdout,
+//! and requires IMDSv2 tokens.
+//!
+//! Arguments change the default instance: `PATH=BODY` serves `BODY` at
+//! `PATH`, and a bare `PATH` makes it a 404.
+//!
+//! ```text
+//! cargo run --example mock_imds --features test-util -- meta-data/public-hostname
+//! ```
+
+use ec2_instance_metadata::MockImds;
+use std::io::Write;
+
+fn main() {
+    let mock = MockImds::start();
+    mock.require_token(true);
+    for arg in std::env::args().skip(1) {
+        match arg.find('=') {
+            Some(equals) => mock.set(&arg[..equals], &arg[equals + 1..]),
+            None => mock.set_status(&arg, 404),
+        }
+    }
+
+    println!("{}", mock.endpoint());
+    std::io::stdout()
+        .flush()
+        .expect("Couldn't print the endpoint.");
+    loop {
+        std::thread::park();
+    }
+}
@@ -0,0 +1,146 @@
+#ifndef EC2MD_H
+#define EC2MD_H
+
+/* Generated by cbindgen from src/ffi.rs; don't edit by hand. */
+
+/*
+ The instance metadata, as returned by `ec2md_get`.
+
+ The handle owns the strings its accessors return: they stay valid, and
+ unchanged, until the handle is passed to `ec2md_free`. Copy any string
+ that has to outlive the handle.
+ */
+typedef struct Ec2mdMetadata Ec2mdMetadata;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Get the instance metadata from IMDS.
+
+ Returns a handle to free with `ec2md_free`, or NULL on failure, in which
+ case `ec2md_last_error` says why. Calls share one client, so the IMDSv2
+ token is reused between them. Safe to call from any thread.
+ */
+Ec2mdMetadata *ec2md_get(void);
+
+/*
+ Get the instance metadata from the IMDS at `endpoint`, a NUL-terminated
+ URL such as `http://127.0.0.1:1338`, e.g. a local mock. A NULL `endpoint`
+ means the default one, like `ec2md_get`.
+
+ Returns a handle to free with `ec2md_free`, or NULL on failure, in which
+ case `ec2md_last_error` says why. Each call uses a new client. Safe to
+ call from any thread.
+
+ # Safety
+ `endpoint` must be NULL or point to a NUL-terminated string, which is
+ only read during the call.
+ */
+Ec2mdMetadata *ec2md_get_with_endpoint(const char *endpoint);
+
+/*
+ Why the last `ec2md_get` or `ec2md_get_with_endpoint` on this thread
+ failed, as a NUL-terminated UTF-8 message, or NULL if it succeeded or
+ none was made.
+
+ The string is owned by the library: don't free it. It stays valid until
+ the next `ec2md_get` or `ec2md_get_with_endpoint` on the same thread.
+ */
+const char *ec2md_last_error(void);
+
+/*
+ Free a handle from `ec2md_get`, and with it the strings its accessors
+ returned. NULL is ignored.
+
+ # Safety
+ `metadata` must be NULL or a handle from `ec2md_get` or
+ `ec2md_get_with_endpoint` that hasn't been freed yet.
+ */
+void ec2md_free(Ec2mdMetadata *metadata);
+
+/*
+ The instance's region, or NULL if `metadata` is NULL or the region is
+ unknown. Owned by `metadata`.
+
+ # Safety
+ `metadata` must be NULL or a live handle from `ec2md_get`.
+ */
+const char *ec2md_region(const Ec2mdMetadata *metadata);
+
+/*
+ The instance's availability zone, or NULL if `metadata` is NULL. Owned
+ by `metadata`.
+
+ # Safety
+ `metadata` must be NULL or a live handle from `ec2md_get`.
+ */
+const char *ec2md_availability_zone(const Ec2mdMetadata *metadata);
+
+/*
+ The instance's id, or NULL if `metadata` is NULL. Owned by `metadata`.
+
+ # Safety
+ `metadata` must be NULL or a live handle from `ec2md_get`.
+ */
+const char *ec2md_instance_id(const Ec2mdMetadata *metadata);
+
+/*
+ The instance's account id, or NULL if `metadata` is NULL or IMDS didn't
+ report it. Owned by `metadata`.
+
+ # Safety
+ `metadata` must be NULL or a live handle from `ec2md_get`.
+ */
+const char *ec2md_account_id(const Ec2mdMetadata *metadata);
+
+/*
+ The instance's AMI id, or NULL if `metadata` is NULL. Owned by
+ `metadata`.
+
+ # Safety
+ `metadata` must be NULL or a live handle from `ec2md_get`.
+ */
+const char *ec2md_ami_id(const Ec2mdMetadata *metadata);
+
+/*
+ The instance's type, or NULL if `metadata` is NULL. Owned by `metadata`.
+
+ # Safety
+ `metadata` must be NULL or a live handle from `ec2md_get`.
+ */
+const char *ec2md_instance_type(const Ec2mdMetadata *metadata);
+
+/*
+ The instance's private hostname, or NULL if `metadata` is NULL. Owned by
+ `metadata`.
+
+ # Safety
+ `metadata` must be NULL or a live handle from `ec2md_get`.
+ */
+const char *ec2md_local_hostname(const Ec2mdMetadata *metadata);
+
+/*
+ The instance's hostname, or NULL if `metadata` is NULL. Owned by
+ `metadata`.
+
+ # Safety
+ `metadata` must be NULL or a live handle from `ec2md_get`.
+ */
+const char *ec2md_hostname(const Ec2mdMetadata *metadata);
+
+/*
+ The instance's public hostname, or NULL if `metadata` is NULL or the
+ instance has none. Owned by `metadata`.
+
+ # Safety
+ `metadata` must be NULL or a live handle from `ec2md_get`.
+ */
+const char *ec2md_public_hostname(const Ec2mdMetadata *metadata);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* EC2MD_H */
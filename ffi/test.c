@@ -0,0 +1,113 @@
+/*
+ * Tests the C ABI against the mock IMDS at $EC2MD_ENDPOINT, which must
+ * serve the default `MockImds` instance without a public hostname. Run it
+ * through ffi/test.sh.
+ */
+
+#include <assert.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "ec2md.h"
+
+static int failures = 0;
+
+#define CHECK(condition)                                                   \
+    do {                                                                   \
+        if (!(condition)) {                                                \
+            fprintf(stderr, "%s:%d: failed: %s\n", __FILE__, __LINE__,     \
+                    #condition);                                           \
+            failures++;                                                    \
+        }                                                                  \
+    } while (0)
+
+#define CHECK_STR(actual, expected)                                        \
+    CHECK((actual) != NULL && strcmp((actual), (expected)) == 0)
+
+static char *copy(const char *s) {
+    char *copied = malloc(strlen(s) + 1);
+    assert(copied != NULL);
+    strcpy(copied, s);
+    return copied;
+}
+
+static void test_metadata(const char *endpoint) {
+    Ec2mdMetadata *metadata = ec2md_get_with_endpoint(endpoint);
+    CHECK(metadata != NULL);
+    if (metadata == NULL) {
+        fprintf(stderr, "ec2md_get_with_endpoint: %s\n", ec2md_last_error());
+        return;
+    }
+    CHECK(ec2md_last_error() == NULL);
+
+    CHECK_STR(ec2md_instance_id(metadata), "i-0123456789abcdef0");
+    CHECK_STR(ec2md_region(metadata), "us-east-1");
+    CHECK_STR(ec2md_availability_zone(metadata), "us-east-1a");
+    CHECK_STR(ec2md_account_id(metadata), "123456789012");
+    CHECK_STR(ec2md_ami_id(metadata), "ami-0abcdef1234567890");
+    CHECK_STR(ec2md_instance_type(metadata), "m5.large");
+    CHECK_STR(ec2md_local_hostname(metadata), "ip-10-0-0-12.ec2.internal");
+    CHECK_STR(ec2md_hostname(metadata), "ip-10-0-0-12.ec2.internal");
+    /* Unavailable fields are NULL. */
+    CHECK(ec2md_public_hostname(metadata) == NULL);
+
+    /* Strings stay valid, and the same, for as long as their handle... */
+    const char *instance_id = ec2md_instance_id(metadata);
+    CHECK(ec2md_instance_id(metadata) == instance_id);
+    Ec2mdMetadata *other = ec2md_get_with_endpoint(endpoint);
+    CHECK(other != NULL);
+    CHECK_STR(instance_id, "i-0123456789abcdef0");
+    ec2md_free(other);
+    CHECK_STR(instance_id, "i-0123456789abcdef0");
+
+    /* ...so copy them to keep them past ec2md_free. */
+    char *kept = copy(instance_id);
+    ec2md_free(metadata);
+    CHECK_STR(kept, "i-0123456789abcdef0");
+    free(kept);
+}
+
+static void test_errors(const char *endpoint) {
+    /* Nothing listens on the discard port. */
+    Ec2mdMetadata *metadata = ec2md_get_with_endpoint("http://127.0.0.1:9");
+    CHECK(metadata == NULL);
+    const char *error = ec2md_last_error();
+    CHECK(error != NULL && strlen(error) > 0);
+
+    /* The error stays until the next get on this thread... */
+    CHECK(ec2md_instance_id(NULL) == NULL);
+    CHECK(ec2md_last_error() == error);
+    char *kept = copy(error);
+
+    /* ...which clears it if it succeeds. */
+    metadata = ec2md_get_with_endpoint(endpoint);
+    CHECK(metadata != NULL);
+    CHECK(ec2md_last_error() == NULL);
+    ec2md_free(metadata);
+    CHECK(strlen(kept) > 0);
+    free(kept);
+
+    /* NULL handles give NULL, and freeing one is a no-op. */
+    CHECK(ec2md_region(NULL) == NULL);
+    CHECK(ec2md_public_hostname(NULL) == NULL);
+    ec2md_free(NULL);
+}
+
+int main(void) {
+    const char *endpoint = getenv("EC2MD_ENDPOINT");
+    if (endpoint == NULL) {
+        fprintf(stderr, "EC2MD_ENDPOINT isn't set; run ffi/test.sh\n");
+        return 2;
+    }
+
+    test_metadata(endpoint);
+    test_errors(endpoint);
+
+    if (failures > 0) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("ok\n");
+    return 0;
+}
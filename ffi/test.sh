@@ -0,0 +1,35 @@
+#!/bin/sh
+# Build the library with the `ffi` feature as a static library, check that
+# ffi/ec2md.h is up to date if cbindgen is installed, and run ffi/test.c
+# against the mock IMDS from examples/mock_imds.rs.
+#
+# CC and LDLIBS override the compiler and the system libraries to link,
+# which default to those a Linux build needs.
+set -eu
+cd "$(dirname "$0")/.."
+
+out=target/ffi-test
+mkdir -p "$out"
+
+if command -v cbindgen >/dev/null 2>&1; then
+    cbindgen --quiet --config cbindgen.toml --output "$out/ec2md.h"
+    if ! diff -u ffi/ec2md.h "$out/ec2md.h"; then
+        echo "ffi/ec2md.h is out of date; regenerate it with cbindgen" >&2
+        exit 1
+    fi
+fi
+
+cargo rustc --quiet --lib --features ffi --crate-type staticlib
+cargo build --quiet --example mock_imds --features test-util
+${CC:-cc} -std=c99 -Wall -Wextra -Werror -Iffi -o "$out/test" ffi/test.c \
+    target/debug/libec2_instance_metadata.a ${LDLIBS:--lpthread -ldl -lm}
+
+rm -f "$out/endpoint"
+target/debug/examples/mock_imds meta-data/public-hostname >"$out/endpoint" &
+mock=$!
+trap 'kill $mock' EXIT
+while [ ! -s "$out/endpoint" ]; do
+    sleep 0.1
+done
+
+EC2MD_ENDPOINT=$(head -n 1 "$out/endpoint") "$out/test"
@@ -1,7 +1,1336 @@
+extern crate clap;
+extern crate clap_complete;
 extern crate ec2_instance_metadata;
+extern crate json;
+
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use ec2_instance_metadata::{
+    Error, ErrorKind, Field, ImdsV1, InstanceMetadata, InstanceMetadataClient, InterruptionNotice,
+    RebalanceRecommendation,
+};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command as Process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Exit statuses, see `EXIT_STATUS_HELP`.
+const EXIT_OTHER: i32 = 1;
+const EXIT_UNREACHABLE: i32 = 2;
+const EXIT_NOT_FOUND: i32 = 3;
+const EXIT_UNAUTHORIZED: i32 = 4;
+const EXIT_UNEXPECTED_RESPONDER: i32 = 5;
+
+const EXIT_STATUS_HELP: &str = "\
+Exit status:
+  0  success
+  1  any other error, including invalid arguments
+  2  IMDS is unreachable or timed out, e.g. this isn't an EC2 instance
+  3  the field or path isn't available on this instance
+  4  IMDS refused the request, e.g. it's disabled or the token was rejected
+  5  something other than IMDS answered, e.g. a proxy";
+
+/// How long `check` waits for IMDS without a `--timeout`.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Fetch EC2 instance metadata from IMDS.
+#[derive(Parser)]
+#[command(name = "fetcher", version, after_help = EXIT_STATUS_HELP)]
+struct Cli {
+    /// The IMDS endpoint, e.g. http://127.0.0.1:1338 for a local mock
+    #[arg(
+        long,
+        value_name = "URL",
+        env = "AWS_EC2_METADATA_SERVICE_ENDPOINT",
+        global = true
+    )]
+    endpoint: Option<String>,
+
+    /// Use IMDSv1: skip the session token and send plain GETs, for hosts
+    /// where the token PUT is blocked. IMDSv1 is open to SSRF; only use this
+    /// when IMDSv2 can't work
+    #[arg(long, global = true)]
+    insecure_v1: bool,
+
+    /// Keep retrying for up to this many seconds while IMDS isn't ready,
+    /// e.g. early in boot
+    #[arg(long, value_name = "SECONDS", global = true)]
+    wait: Option<u64>,
+
+    /// Limit each request, including its retries, e.g. 500ms or 5s
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, global = true)]
+    timeout: Option<Duration>,
+
+    /// Limit connecting to IMDS, e.g. 200ms; at most the --timeout
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, global = true)]
+    connect_timeout: Option<Duration>,
+
+    /// How to print the metadata
+    #[arg(long, value_enum, default_value_t = Format::Text, global = true)]
+    format: Format,
+
+    /// Print the metadata through a template instead, e.g.
+    /// '{{instance_id}} {{region}}'; see --help for the placeholders
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        value_parser = Template::parse,
+        long_help = TEMPLATE_HELP,
+        global = true
+    )]
+    format_template: Option<Template>,
+
+    /// Print the metadata, and any error, as JSON; short for --format json
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// The prefix of the variables printed by --format shell
+    #[arg(long, default_value = "EC2_", value_parser = parse_prefix, global = true)]
+    prefix: String,
+
+    /// The labels of --format prometheus's info metric, comma-separated
+    #[arg(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        value_parser = PossibleValuesParser::new(LABEL_NAMES),
+        default_value = "instance_id,region,instance_type,availability_zone",
+        global = true
+    )]
+    labels: Vec<String>,
+
+    /// Leave out --format csv's header row
+    #[arg(long, global = true)]
+    no_header: bool,
+
+    /// Only get this field; repeat to get several, printed in order
+    #[arg(
+        long = "field",
+        value_name = "NAME",
+        value_parser = field_parser(),
+        global = true
+    )]
+    fields: Vec<Field>,
+
+    /// What to put between the --field values, e.g. '\t'
+    #[arg(long, value_name = "SEP", default_value = "\\n", value_parser = parse_separator, global = true)]
+    separator: String,
+
+    /// Don't fail if a --field is unavailable, just leave its value empty
+    #[arg(long, global = true)]
+    ignore_missing: bool,
+
+    /// Write the output to this file instead of stdout, atomically, keeping
+    /// its permissions; a new file is created owner-only (0600)
+    #[arg(long, value_name = "PATH", global = true)]
+    output_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// One aligned `key: value` line per field
+    Text,
+
+    /// One JSON object; errors are printed to stderr as JSON too
+    Json,
+
+    /// `export` lines for `eval`, e.g. EC2_INSTANCE_ID='i-0abc'
+    Shell,
+
+    /// An info metric for node_exporter's textfile collector
+    Prometheus,
+
+    /// A YAML mapping, with values quoted where YAML would misread them
+    Yaml,
+
+    /// A header row and a row of values, quoted per RFC 4180
+    Csv,
+}
+
+impl Cli {
+    fn format(&self) -> Format {
+        if self.json {
+            Format::Json
+        } else {
+            self.format
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the instance metadata (the default)
+    Get,
+
+    /// Print the value of one field
+    Field {
+        #[arg(value_parser = field_parser())]
+        name: Field,
+    },
+
+    /// Print the body at a path verbatim
+    ///
+    /// The path is under meta-data/ or dynamic/, e.g.
+    /// meta-data/placement/group-name or dynamic/instance-identity/document.
+    /// Paths not starting with either are taken to be under meta-data/.
+    Raw { path: String },
+
+    /// Print a fresh IMDSv2 session token
+    Token,
+
+    /// Run a command with the metadata in its environment
+    ///
+    /// The variables are those of --format shell, e.g. EC2_INSTANCE_ID and
+    /// AWS_REGION. On Unix fetcher replaces itself with the command, so
+    /// signals reach it directly and its exit status is fetcher's. The IMDS
+    /// session token is never passed on.
+    Exec {
+        /// Run the command even if some of the metadata couldn't be fetched,
+        /// with whatever was
+        #[arg(long)]
+        best_effort: bool,
+
+        /// The command and its arguments, after --
+        #[arg(required = true, last = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+
+    /// Check that IMDS is reachable and sane, printing nothing if it is
+    ///
+    /// Gets a session token and the instance id, by default within 1s; see
+    /// --timeout. The exit status says what's wrong, if anything.
+    Check,
+
+    /// Print every path and value in the metadata tree, or a subtree
+    ///
+    /// Paths are relative to meta-data/. Values that fail to fetch are
+    /// annotated with the error in place of the value. Credentials are
+    /// redacted; use raw to see them.
+    Dump {
+        /// Only dump the tree under this path, e.g. network/
+        path: Option<String>,
+
+        /// The same as PATH, for scripts that prefer a flag
+        #[arg(long = "path", value_name = "PATH", conflicts_with = "path")]
+        path_flag: Option<String>,
+    },
+
+    /// Print the IAM role credentials for an SDK's credential_process
+    ///
+    /// The output is the credential_process JSON document, whatever the
+    /// --format. The secrets only ever go to stdout.
+    Credentials,
+
+    /// Wait for a Spot interruption notice, print it, and exit
+    ///
+    /// With --exec, the command is run through `sh -c` instead, with the
+    /// notice in its environment: PREFIX_NOTICE is "interruption" or
+    /// "rebalance", and PREFIX_ACTION and PREFIX_TIME, or
+    /// PREFIX_NOTICE_TIME, hold the notice's fields. fetcher then exits with
+    /// the command's status.
+    WatchInterruption {
+        /// How often to poll IMDS, e.g. 500ms or 5s
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "5s")]
+        interval: Duration,
+
+        /// Also stop at a rebalance recommendation, the earlier warning
+        #[arg(long)]
+        rebalance: bool,
+
+        /// Run this shell command when a notice appears
+        #[arg(long, value_name = "COMMAND")]
+        exec: Option<String>,
+    },
+
+    /// Keep --output-file up to date with the metadata until terminated
+    ///
+    /// Every --interval, the metadata is fetched along with the Spot
+    /// interruption notice, rebalance recommendation, and instance tags, as
+    /// extras, and the file is replaced per --format if any of it changed.
+    /// Failed refreshes are reported on stderr and leave the file alone.
+    /// SIGTERM and SIGINT stop the daemon once any write in progress is done.
+    Daemon {
+        /// How often to refresh the metadata, e.g. 30s or 5m
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "30s")]
+        interval: Duration,
+
+        /// Only report failures on stderr, not each refresh
+        #[arg(long)]
+        quiet: bool,
+    },
+
+    /// Print a shell completion script
+    ///
+    /// The script completes the commands, options, and values such as field
+    /// names, e.g. `fetcher completions bash >
+    /// /etc/bash_completion.d/fetcher`.
+    Completions { shell: clap_complete::Shell },
+}
+
+const TEMPLATE_HELP: &str = "\
+Print the metadata through a template instead of --format.
+
+{{name}} is replaced by the value named name, or nothing if it's unavailable. \
+The names are those of the --format json keys: instance_id, account_id, \
+ami_id, availability_zone, region, instance_type, hostname, local_hostname, \
+public_hostname, imds_version, and fetched_at. \\{ and \\} are literal braces, \
+\\\\ a backslash, and \\n and \\t a newline and a tab.
+
+For example: --format-template '{{instance_id}} in {{region}}'";
+
+/// A parsed `--format-template`.
+#[derive(Clone)]
+struct Template(Vec<Piece>);
+
+#[derive(Clone)]
+enum Piece {
+    Literal(String),
+    Placeholder(String),
+}
+
+impl Template {
+    /// Parse `template`, failing on unknown names with a list of the valid
+    /// ones, so typos are caught before anything is fetched.
+    fn parse(template: &str) -> Result<Template, String> {
+        let names = template_names();
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => literal.push(match chars.next() {
+                    Some(escaped @ ('{' | '}' | '\\')) => escaped,
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some(other) => return Err(format!("unknown escape \\{}", other)),
+                    None => return Err("trailing \\".to_string()),
+                }),
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') if chars.peek() == Some(&'}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err("unclosed {{".to_string()),
+                        }
+                    }
+                    chars.next();
+
+                    let name = name.trim();
+                    if !names.contains(&name.to_string()) {
+                        return Err(format!(
+                            "unknown placeholder {{{{{}}}}}, expected one of: {}",
+                            name,
+                            names.join(", ")
+                        ));
+                    }
+                    pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    pieces.push(Piece::Placeholder(name.to_string()));
+                }
+                c => literal.push(c),
+            }
+        }
+        pieces.push(Piece::Literal(literal));
+
+        Ok(Template(pieces))
+    }
+
+    fn render(&self, metadata: &InstanceMetadata) -> String {
+        let values = json::parse(&metadata.to_json()).expect("metadata dumps valid JSON");
+        self.0
+            .iter()
+            .map(|piece| match piece {
+                Piece::Literal(literal) => literal.as_str(),
+                Piece::Placeholder(name) => values[name.as_str()].as_str().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// The placeholders a template can use: the `--format json` keys.
+fn template_names() -> Vec<String> {
+    LABEL_NAMES
+        .iter()
+        .chain(&["fetched_at"])
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// The `--format json` keys that can label the Prometheus info metric.
+/// `fetched_at` is left out, as it would start a new series on every run.
+const LABEL_NAMES: [&str; 10] = [
+    "instance_id",
+    "account_id",
+    "ami_id",
+    "availability_zone",
+    "region",
+    "instance_type",
+    "hostname",
+    "local_hostname",
+    "public_hostname",
+    "imds_version",
+];
+
+/// The `ec2_instance_metadata_info` gauge, labelled with the values of
+/// `labels`. Unavailable values are left out.
+fn prometheus_info(metadata: &InstanceMetadata, labels: &[String]) -> String {
+    let values = json::parse(&metadata.to_json()).expect("metadata dumps valid JSON");
+    let labels: Vec<String> = labels
+        .iter()
+        .filter_map(|name| {
+            let value = values[name.as_str()].as_str()?;
+            Some(format!("{}=\"{}\"", name, prometheus_escape(value)))
+        })
+        .collect();
+
+    format!(
+        "# HELP ec2_instance_metadata_info EC2 instance metadata from IMDS.\n\
+         # TYPE ec2_instance_metadata_info gauge\n\
+         ec2_instance_metadata_info{{{}}} 1\n",
+        labels.join(",")
+    )
+}
+
+/// `value` escaped for a Prometheus label value.
+fn prometheus_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Replace `path` with `contents` in one step, by writing a temporary file
+/// next to it and renaming that over it, so readers never see a partial
+/// file. The temporary file is created owner-only, and never through an
+/// existing file or symlink; an existing file's permissions are then kept.
+/// The temporary file is removed if anything fails.
+fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a file path"))?;
+    let (temporary, mut file) = create_temporary(path, name)?;
+
+    let mut write = || -> std::io::Result<()> {
+        // Before the contents go in, so that only widens what 0600 allows.
+        if let Ok(existing) = std::fs::metadata(path) {
+            file.set_permissions(existing.permissions())?;
+        }
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temporary, path)
+    };
+    write().inspect_err(|_| {
+        let _ = std::fs::remove_file(&temporary);
+    })
+}
+
+/// Create a new, owner-only `.<name>.<pid>.<nanos>.tmp` next to `path`,
+/// trying other names if one is taken.
+fn create_temporary(
+    path: &Path,
+    name: &std::ffi::OsStr,
+) -> std::io::Result<(PathBuf, std::fs::File)> {
+    let mut attempts = 0;
+    loop {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let mut temporary_name = std::ffi::OsString::from(".");
+        temporary_name.push(name);
+        temporary_name.push(format!(".{}.{}.tmp", std::process::id(), nanos));
+        let temporary = path.with_file_name(temporary_name);
+
+        match create_owner_only(&temporary) {
+            Ok(file) => return Ok((temporary, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempts < 10 => {
+                attempts += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn create_owner_only(path: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_owner_only(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+}
+
+/// Accepts the names of `Field`s, and lists them in `--help`.
+fn field_parser() -> impl TypedValueParser<Value = Field> {
+    PossibleValuesParser::new(Field::ALL.iter().map(|field| field.name()))
+        .map(|name| Field::from_name(&name).expect("parser only accepts field names"))
+}
+
+/// The stable snake_case name of `kind`, for JSON errors.
+fn kind_name(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::NotFound => "not_found",
+        ErrorKind::Unauthorized => "unauthorized",
+        ErrorKind::Throttled => "throttled",
+        ErrorKind::Timeout => "timeout",
+        ErrorKind::Transport => "transport",
+        ErrorKind::Parse => "parse",
+        ErrorKind::Disabled => "disabled",
+        _ => "other",
+    }
+}
+
+/// The exit status for `error`, see `EXIT_STATUS_HELP`.
+fn exit_status(error: &Error) -> i32 {
+    match cause(error) {
+        Error::UnexpectedResponse(_) | Error::UnexpectedRedirect(_) => {
+            return EXIT_UNEXPECTED_RESPONDER
+        }
+        _ => {}
+    }
+    match error.kind() {
+        ErrorKind::Transport | ErrorKind::Timeout => EXIT_UNREACHABLE,
+        ErrorKind::NotFound => EXIT_NOT_FOUND,
+        ErrorKind::Unauthorized | ErrorKind::Disabled => EXIT_UNAUTHORIZED,
+        _ => EXIT_OTHER,
+    }
+}
+
+/// `error` without the `Token` and `Fetch` wrappers saying what failed.
+fn cause(error: &Error) -> &Error {
+    match error {
+        Error::Token(source) | Error::Fetch { source, .. } => cause(source),
+        error => error,
+    }
+}
+
+/// Report `error` on stderr, as a JSON object if `json` is set, and exit.
+fn fail(error: &Error, json: bool) -> ! {
+    fail_with(
+        &error.full_message(),
+        kind_name(error.kind()),
+        exit_status(error),
+        json,
+    )
+}
+
+fn fail_with(message: &str, kind: &str, status: i32, json: bool) -> ! {
+    if json {
+        let object = json::object! { "error" => message, "kind" => kind };
+        eprintln!("{}", object.dump());
+    } else {
+        eprintln!("fetcher: {}", message);
+    }
+    std::process::exit(status);
+}
+
+/// Write `output` to `--output-file`, or stdout. Failing to is reported
+/// here rather than as an `Error`, which would blame IMDS.
+fn write_output(cli: &Cli, output: &str) {
+    let result = match &cli.output_file {
+        Some(path) => write_atomically(path, output).map_err(|e| (path.display().to_string(), e)),
+        None => std::io::stdout()
+            .write_all(output.as_bytes())
+            .map_err(|e| ("stdout".to_string(), e)),
+    };
+    if let Err((target, e)) = result {
+        let message = format!("couldn't write {}: {}", target, e);
+        fail_with(&message, "other", EXIT_OTHER, cli.format() == Format::Json);
+    }
+}
+
+/// `export` lines for every available field, named after the field with
+/// `prefix`, e.g. `EC2_INSTANCE_ID`, plus the SDKs' `AWS_REGION` and
+/// `AWS_DEFAULT_REGION`.
+fn shell_exports(metadata: &InstanceMetadata, prefix: &str) -> String {
+    let values = Field::ALL
+        .iter()
+        .filter_map(|field| Some((*field, metadata.value(*field)?)));
+    field_exports(values, prefix)
+}
+
+/// `export` lines for `values`, as for `shell_exports`.
+fn field_exports<'a, I>(values: I, prefix: &str) -> String
+where
+    I: IntoIterator<Item = (Field, &'a str)>,
+{
+    field_env(values, prefix)
+        .iter()
+        .map(|(name, value)| format!("export {}={}\n", name, shell_quote(value)))
+        .collect()
+}
+
+/// Environment variables for `values`, named after the field with `prefix`,
+/// plus `AWS_REGION` and `AWS_DEFAULT_REGION` if the region is among them.
+fn field_env<'a, I>(values: I, prefix: &str) -> Vec<(String, String)>
+where
+    I: IntoIterator<Item = (Field, &'a str)>,
+{
+    let mut env = Vec::new();
+    let mut region = None;
+    for (field, value) in values {
+        let name = field.name().to_ascii_uppercase().replace('-', "_");
+        env.push((format!("{}{}", prefix, name), value.to_string()));
+        if field == Field::Region {
+            region = Some(value);
+        }
+    }
+    if let Some(region) = region {
+        env.push(("AWS_REGION".to_string(), region.to_string()));
+        env.push(("AWS_DEFAULT_REGION".to_string(), region.to_string()));
+    }
+    env
+}
+
+/// Get each of `fields`, in order, with `None` for those the instance has no
+/// value for. Nothing else is fetched.
+fn get_fields(
+    client: &InstanceMetadataClient,
+    fields: &[Field],
+) -> Result<Vec<(Field, Option<String>)>, Error> {
+    fields
+        .iter()
+        .map(|field| match client.get_field(*field) {
+            Ok(value) => Ok((*field, value)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok((*field, None)),
+            Err(e) => Err(e),
+        })
+        .collect()
+}
+
+/// `values` of the `--field`s, printed per `--format`. Text is the values
+/// joined by `separator`, with unavailable ones empty.
+fn selected_fields(values: &[(Field, Option<String>)], cli: &Cli) -> String {
+    let object = || {
+        let mut object = json::JsonValue::new_object();
+        for (field, value) in values {
+            object[field.name().replace('-', "_").as_str()] = value.as_deref().into();
+        }
+        object
+    };
+
+    match cli.format() {
+        Format::Json => format!("{}\n", object().dump()),
+        Format::Yaml => yaml(&object()),
+        Format::Csv => csv_object(&object(), !cli.no_header),
+        Format::Shell => field_exports(
+            values
+                .iter()
+                .filter_map(|(field, value)| Some((*field, value.as_deref()?))),
+            &cli.prefix,
+        ),
+        Format::Text | Format::Prometheus => {
+            let values: Vec<&str> = values
+                .iter()
+                .map(|(_, value)| value.as_deref().unwrap_or_default())
+                .collect();
+            format!("{}\n", values.join(&cli.separator))
+        }
+    }
+}
+
+/// `metadata` printed per `--format-template` or `--format`.
+fn render(metadata: &InstanceMetadata, cli: &Cli) -> String {
+    match cli.format() {
+        _ if cli.format_template.is_some() => {
+            let template = cli.format_template.as_ref().expect("checked above");
+            format!("{}\n", template.render(metadata))
+        }
+        Format::Text => format!("{}\n", metadata),
+        Format::Json => format!("{}\n", metadata.to_json()),
+        Format::Shell => shell_exports(metadata, &cli.prefix),
+        Format::Prometheus => prometheus_info(metadata, &cli.labels),
+        Format::Yaml => yaml(&json::parse(&metadata.to_json()).expect("valid JSON")),
+        Format::Csv => csv_object(
+            &json::parse(&metadata.to_json()).expect("valid JSON"),
+            !cli.no_header,
+        ),
+    }
+}
+
+/// Only allow prefixes that keep the variable names valid, and the output
+/// safe to `eval`.
+fn parse_prefix(prefix: &str) -> Result<String, String> {
+    if prefix
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !prefix.starts_with(|c: char| c.is_ascii_digit())
+    {
+        Ok(prefix.to_string())
+    } else {
+        Err("expected letters, digits, and underscores, not starting with a digit".to_string())
+    }
+}
+
+/// A positive number of milliseconds, seconds, or minutes, e.g. `500ms`,
+/// `5s`, or `1m`. A bare number is seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| "expected a number with a unit, e.g. 500ms or 5s".to_string())?;
+    let duration = match unit {
+        "ms" => Duration::from_millis(number),
+        "" | "s" => Duration::from_secs(number),
+        "m" => Duration::from_secs(number.saturating_mul(60)),
+        _ if !unit.chars().all(|c| c.is_ascii_alphabetic()) => {
+            return Err("expected a whole number with a unit, e.g. 500ms or 5s".to_string())
+        }
+        _ => return Err(format!("unknown unit {:?}, expected ms, s, or m", unit)),
+    };
+    if duration.is_zero() {
+        return Err("must be greater than zero".to_string());
+    }
+    Ok(duration)
+}
+
+/// `separator` with `\\t`, `\\n`, and `\\\\` unescaped, since a literal tab is awkward
+/// to type.
+fn parse_separator(separator: &str) -> Result<String, String> {
+    let mut unescaped = String::new();
+    let mut chars = separator.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(match c {
+            '\\' => match chars.next() {
+                Some('t') => '\t',
+                Some('n') => '\n',
+                Some('\\') => '\\',
+                Some(other) => return Err(format!("unknown escape \\{}", other)),
+                None => return Err("trailing \\".to_string()),
+            },
+            c => c,
+        });
+    }
+    Ok(unescaped)
+}
+
+/// `value` in single quotes, which the shell takes literally, with any
+/// single quotes in it closed, escaped, and reopened.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// The `get_all_under` leaves holding secrets, which `dump` redacts.
+fn is_secret(path: &str) -> bool {
+    path.starts_with("iam/security-credentials/")
+        || path.starts_with("identity-credentials/ec2/security-credentials/")
+}
+
+/// `tree` as one `path: value` line per value, with any further lines of a
+/// value indented under it, or as a JSON object keyed by path where failures
+/// are `{"error", "kind"}` objects.
+fn dump(tree: &BTreeMap<String, Result<String, Error>>, format: Format, header: bool) -> String {
+    const REDACTED: &str = "<redacted>";
+
+    if format == Format::Csv {
+        let mut rows = Vec::new();
+        if header {
+            rows.push(vec![
+                "path".to_string(),
+                "value".to_string(),
+                "error".to_string(),
+            ]);
+        }
+        for (path, value) in tree {
+            let (value, error) = match value {
+                Ok(_) if is_secret(path) => (REDACTED.to_string(), String::new()),
+                Ok(value) => (value.clone(), String::new()),
+                Err(e) => (String::new(), e.full_message()),
+            };
+            rows.push(vec![path.clone(), value, error]);
+        }
+        return csv(&rows);
+    }
+
+    if let Format::Json | Format::Yaml = format {
+        let mut object = json::JsonValue::new_object();
+        for (path, value) in tree {
+            object[path.as_str()] = match value {
+                Ok(_) if is_secret(path) => REDACTED.into(),
+                Ok(value) => value.as_str().into(),
+                Err(e) => json::object! {
+                    "error" => e.full_message(),
+                    "kind" => kind_name(e.kind()),
+                },
+            };
+        }
+        return match format {
+            Format::Yaml => yaml(&object),
+            _ => format!("{}\n", object.dump()),
+        };
+    }
+
+    let mut lines = String::new();
+    for (path, value) in tree {
+        let value = match value {
+            Ok(_) if is_secret(path) => REDACTED.to_string(),
+            Ok(value) => value.trim_end().replace('\n', "\n  "),
+            Err(e) => format!("<error: {}>", e.full_message()),
+        };
+        lines.push_str(&format!("{}: {}\n", path, value));
+    }
+    lines
+}
+
+/// `object`, a JSON object of strings and `null`s, as a CSV header row of
+/// its keys and a row of its values, with `null`s empty. Nested objects,
+/// i.e. `extras`, are left out.
+fn csv_object(object: &json::JsonValue, header: bool) -> String {
+    let entries: Vec<_> = object
+        .entries()
+        .filter(|(_, value)| !value.is_object())
+        .collect();
+    let mut rows = Vec::new();
+    if header {
+        rows.push(entries.iter().map(|(key, _)| key.to_string()).collect());
+    }
+    rows.push(
+        entries
+            .iter()
+            .map(|(_, value)| value.as_str().unwrap_or_default().to_string())
+            .collect(),
+    );
+    csv(&rows)
+}
+
+/// `rows` as CSV, with fields quoted per RFC 4180 where they contain a
+/// comma, quote, or line break.
+fn csv(rows: &[Vec<String>]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        let fields: Vec<String> = row
+            .iter()
+            .map(|field| {
+                if field.contains([',', '"', '\r', '\n']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field.clone()
+                }
+            })
+            .collect();
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// `object`, a JSON object of strings, `null`s, and nested objects, as a
+/// YAML block mapping.
+fn yaml(object: &json::JsonValue) -> String {
+    fn write(object: &json::JsonValue, indent: usize, out: &mut String) {
+        for (key, value) in object.entries() {
+            out.push_str(&format!(
+                "{:indent$}{}:",
+                "",
+                yaml_scalar(key),
+                indent = indent
+            ));
+            match value {
+                json::JsonValue::Object(_) if value.is_empty() => out.push_str(" {}\n"),
+                json::JsonValue::Object(_) => {
+                    out.push('\n');
+                    write(value, indent + 2, out);
+                }
+                json::JsonValue::Null => out.push_str(" null\n"),
+                _ => out.push_str(&format!(" {}\n", yaml_scalar(&value.to_string()))),
+            }
+        }
+    }
+
+    let mut out = String::new();
+    write(object, 0, &mut out);
+    out
+}
+
+/// `value` as a YAML string: plain if YAML would read it back as the same
+/// string, double-quoted otherwise. That quotes numbers like account ids,
+/// words YAML reads as booleans or null, and anything with `:`, `#`, or
+/// other indicator characters.
+fn yaml_scalar(value: &str) -> String {
+    const RESERVED: &[&str] = &["y", "n", "yes", "no", "true", "false", "on", "off", "null"];
+
+    let plain = value.starts_with(|c: char| c.is_ascii_alphabetic() || c == '/')
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._/-".contains(c))
+        && !RESERVED.contains(&value.to_ascii_lowercase().as_str());
+    if plain {
+        value.to_string()
+    } else {
+        // A JSON string is also a valid YAML double-quoted scalar.
+        json::stringify(value)
+    }
+}
+
+/// Get the body at `path` under `meta-data/` or `dynamic/`.
+fn raw(client: &InstanceMetadataClient, path: &str) -> Result<Option<String>, Error> {
+    let relative = path.strip_prefix('/').unwrap_or(path);
+    match relative.strip_prefix("dynamic/") {
+        Some(dynamic) => client.get_dynamic(dynamic),
+        None => client.get_raw(relative.strip_prefix("meta-data/").unwrap_or(relative)),
+    }
+}
+
+/// A notice `watch-interruption` stops at.
+enum Notice {
+    Interruption(InterruptionNotice),
+    Rebalance(RebalanceRecommendation),
+}
+
+impl Notice {
+    /// The notice's kind plus its fields, named as in the variables passed
+    /// to `--exec`.
+    fn json(&self) -> json::JsonValue {
+        let (kind, body) = match self {
+            Notice::Interruption(notice) => ("interruption", notice.to_json()),
+            Notice::Rebalance(recommendation) => ("rebalance", recommendation.to_json()),
+        };
+        let mut object = json::object! { "notice" => kind };
+        for (key, value) in json::parse(&body)
+            .expect("notices dump valid JSON")
+            .entries()
+        {
+            object[env_name(key).as_str()] = value.clone();
+        }
+        object
+    }
+
+    /// The notice's fields as variables, e.g. `EC2_NOTICE=interruption`.
+    fn env(&self, prefix: &str) -> Vec<(String, String)> {
+        self.json()
+            .entries()
+            .map(|(key, value)| {
+                let name = format!("{}{}", prefix, key.to_ascii_uppercase());
+                (name, value.as_str().unwrap_or_default().to_string())
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Notice {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Notice::Interruption(notice) => write!(f, "interruption: {}", notice),
+            Notice::Rebalance(recommendation) => write!(f, "{}", recommendation),
+        }
+    }
+}
+
+/// `noticeTime` as `notice_time`.
+fn env_name(key: &str) -> String {
+    key.chars().fold(String::new(), |mut name, c| {
+        if c.is_ascii_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_lowercase());
+        name
+    })
+}
+
+/// Poll every `interval` until a notice appears. Polls that fail while IMDS
+/// isn't ready are reported on stderr and retried at the next interval.
+fn watch_interruption(
+    client: &InstanceMetadataClient,
+    interval: Duration,
+    rebalance: bool,
+) -> Result<Notice, Error> {
+    let poll = || -> Result<Option<Notice>, Error> {
+        if let Some(notice) = client.get_spot_interruption()? {
+            return Ok(Some(Notice::Interruption(notice)));
+        }
+        if rebalance {
+            if let Some(recommendation) = client.get_rebalance_recommendation()? {
+                return Ok(Some(Notice::Rebalance(recommendation)));
+            }
+        }
+        Ok(None)
+    };
+
+    loop {
+        match poll() {
+            Ok(Some(notice)) => return Ok(notice),
+            Ok(None) => {}
+            Err(e) if not_ready(&e) => {
+                eprintln!("fetcher: polling IMDS failed: {}", e.full_message())
+            }
+            Err(e) => return Err(e),
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Run `command` through `sh -c` with `notice` in its environment, and exit
+/// with its status.
+fn exec(command: &str, notice: &Notice, prefix: &str) -> Result<(), Error> {
+    let status = Process::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(notice.env(prefix))
+        .status()
+        .map_err(|e| Error::IoError(e.into()))?;
+    std::process::exit(status.code().unwrap_or(EXIT_OTHER));
+}
+
+/// The environment for `exec`: the metadata's variables, or with
+/// `best_effort`, whichever fields could be fetched if not all of them
+/// could.
+fn exec_env(
+    client: &InstanceMetadataClient,
+    prefix: &str,
+    best_effort: bool,
+) -> Result<Vec<(String, String)>, Error> {
+    let error = match client.get() {
+        Ok(metadata) => {
+            let values = Field::ALL
+                .iter()
+                .filter_map(|field| Some((*field, metadata.value(*field)?)));
+            return Ok(field_env(values, prefix));
+        }
+        Err(e) if best_effort => e,
+        Err(e) => return Err(e),
+    };
+
+    eprintln!(
+        "fetcher: running the command anyway: {}",
+        error.full_message()
+    );
+    let diagnostics = client.get_diagnostics().unwrap_or_default();
+    let values = diagnostics
+        .values
+        .iter()
+        .map(|(field, value)| (*field, value.as_str()));
+    Ok(field_env(values, prefix))
+}
+
+/// Replace this process with `command`, or on other platforms run it and
+/// exit with its status. Failing to start it exits with 127 if it wasn't
+/// found and 126 otherwise, as shells do.
+fn exec_command(command: &[String], env: Vec<(String, String)>) -> ! {
+    let mut process = Process::new(&command[0]);
+    process.args(&command[1..]).envs(env);
+
+    #[cfg(unix)]
+    let error = {
+        use std::os::unix::process::CommandExt;
+        process.exec()
+    };
+    #[cfg(not(unix))]
+    let error = match process.status() {
+        Ok(status) => std::process::exit(status.code().unwrap_or(EXIT_OTHER)),
+        Err(e) => e,
+    };
+
+    let status = match error.kind() {
+        std::io::ErrorKind::NotFound => 127,
+        _ => 126,
+    };
+    eprintln!("fetcher: couldn't run {}: {}", command[0], error);
+    std::process::exit(status);
+}
+
+/// Set by `SIGTERM` or `SIGINT` once `daemon` has installed its handler.
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_terminate(_signal: libc::c_int) {
+    TERMINATED.store(true, Ordering::SeqCst);
+}
+
+/// Have `SIGTERM` and `SIGINT` set `TERMINATED` instead of killing the
+/// process, so `daemon` can finish a write and exit cleanly.
+fn handle_termination() {
+    #[cfg(unix)]
+    for signal in [libc::SIGTERM, libc::SIGINT] {
+        // The handler only stores to an atomic, which is async-signal-safe.
+        unsafe {
+            libc::signal(
+                signal,
+                on_terminate as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+    }
+}
+
+/// The metadata `daemon` writes: `get`'s, plus the Spot notices and instance
+/// tags that can change while the instance runs, as extras.
+fn daemon_snapshot(client: &InstanceMetadataClient) -> Result<InstanceMetadata, Error> {
+    const NOTICE_PATHS: [&str; 2] = ["spot/instance-action", "events/recommendations/rebalance"];
+
+    let mut metadata = client.get()?;
+    for path in NOTICE_PATHS {
+        if let Some(value) = client.get_raw(path)? {
+            metadata
+                .extras
+                .insert(path.to_string(), value.trim().to_string());
+        }
+    }
+    // Tags are only listed if the instance allows tags in its metadata.
+    for (path, value) in client.get_all_under("tags/instance/")? {
+        metadata.extras.insert(path, value?);
+    }
+    Ok(metadata)
+}
+
+/// Refresh `--output-file` every `interval` until terminated, rewriting it
+/// only when the metadata changed. `fetched_at` doesn't count as a change,
+/// so it says when the current contents were first seen.
+fn daemon(
+    client: &InstanceMetadataClient,
+    cli: &Cli,
+    interval: Duration,
+    quiet: bool,
+) -> Result<(), Error> {
+    const TICK: Duration = Duration::from_millis(100);
+
+    let path = cli.output_file.as_ref().expect("checked in main");
+    let log = |message: String| {
+        if !quiet {
+            eprintln!("fetcher: {}", message);
+        }
+    };
+
+    handle_termination();
+    log(format!(
+        "refreshing {} every {}ms",
+        path.display(),
+        interval.as_millis()
+    ));
+
+    let mut written: Option<InstanceMetadata> = None;
+    while !TERMINATED.load(Ordering::SeqCst) {
+        match daemon_snapshot(client) {
+            Ok(metadata) if written.as_ref() == Some(&metadata) => {}
+            Ok(metadata) => match write_atomically(path, &render(&metadata, cli)) {
+                Ok(()) => {
+                    log(format!("wrote {}", path.display()));
+                    written = Some(metadata);
+                }
+                Err(e) => eprintln!("fetcher: couldn't write {}: {}", path.display(), e),
+            },
+            Err(e) => eprintln!(
+                "fetcher: refreshing the metadata failed: {}",
+                e.full_message()
+            ),
+        }
+
+        // Sleep in ticks, since a signal doesn't cut a sleep short.
+        let wake = Instant::now() + interval;
+        while !TERMINATED.load(Ordering::SeqCst) && Instant::now() < wake {
+            std::thread::sleep(TICK.min(wake.saturating_duration_since(Instant::now())));
+        }
+    }
+
+    log("terminated, exiting".to_string());
+    Ok(())
+}
+
+/// Run the command, retrying with backoff for up to `--wait` seconds while
+/// IMDS isn't ready. Progress goes to stderr.
+fn run(client: &InstanceMetadataClient, cli: &Cli) -> Result<(), Error> {
+    const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+    const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+    let deadline = Instant::now() + Duration::from_secs(cli.wait.unwrap_or(0));
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let error = match run_command(client, cli) {
+            Err(e) if not_ready(&e) => e,
+            result => return result,
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.as_millis() == 0 {
+            return Err(error);
+        }
+
+        let sleep = backoff.min(remaining);
+        eprintln!(
+            "fetcher: waiting for IMDS, retrying in {}ms: {}",
+            sleep.as_millis(),
+            error.full_message()
+        );
+        std::thread::sleep(sleep);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Whether `error` may just mean IMDS isn't up yet, as early in boot.
+fn not_ready(error: &Error) -> bool {
+    error.is_retryable() || matches!(error.kind(), ErrorKind::Transport | ErrorKind::Timeout)
+}
+
+fn run_command(client: &InstanceMetadataClient, cli: &Cli) -> Result<(), Error> {
+    match cli.command.as_ref().unwrap_or(&Command::Get) {
+        Command::Get if !cli.fields.is_empty() => {
+            let values = get_fields(client, &cli.fields)?;
+            let missing: Vec<&str> = values
+                .iter()
+                .filter(|(_, value)| value.is_none())
+                .map(|(field, _)| field.name())
+                .collect();
+            let failed = !missing.is_empty() && !cli.ignore_missing;
+
+            // A failed run leaves the --output-file alone.
+            if !failed || cli.output_file.is_none() {
+                write_output(cli, &selected_fields(&values, cli));
+            }
+            if failed {
+                let message = format!("not available: {}", missing.join(", "));
+                let json = cli.format() == Format::Json;
+                fail_with(&message, "not_found", EXIT_NOT_FOUND, json);
+            }
+        }
+        Command::Get => write_output(cli, &render(&client.get()?, cli)),
+        Command::Field { name } => {
+            let metadata = client.get()?;
+            let value = metadata.value(*name).ok_or(Error::NotFound(*name))?;
+            write_output(cli, &format!("{}\n", value));
+        }
+        Command::Raw { path } => {
+            let value = raw(client, path)?.ok_or_else(|| Error::HttpStatus {
+                status: 404,
+                path: path.clone(),
+                body_excerpt: String::new(),
+            })?;
+            write_output(cli, &value);
+        }
+        Command::Token => write_output(cli, &format!("{}\n", client.fetch_token()?.as_str())),
+        Command::Exec {
+            best_effort,
+            command,
+        } => {
+            let env = exec_env(client, &cli.prefix, *best_effort)?;
+            exec_command(command, env);
+        }
+        Command::Check => {
+            let instance_id = client.get_raw(Field::InstanceId.name())?;
+            if !instance_id.is_some_and(|id| id.starts_with("i-")) {
+                return Err(Error::UnexpectedResponse(
+                    "instance-id isn't an instance id".to_string(),
+                ));
+            }
+        }
+        Command::Dump { path, path_flag } => {
+            let path = path.as_deref().or(path_flag.as_deref()).unwrap_or("");
+            let tree = client.get_all_under(path)?;
+            if tree.is_empty() && !path.is_empty() {
+                return Err(Error::HttpStatus {
+                    status: 404,
+                    path: path.to_string(),
+                    body_excerpt: String::new(),
+                });
+            }
+            write_output(cli, &dump(&tree, cli.format(), !cli.no_header));
+        }
+        Command::Credentials => {
+            let credentials = client.get_credentials()?;
+            write_output(
+                cli,
+                &format!("{}\n", credentials.to_credential_process_json()),
+            );
+        }
+        Command::WatchInterruption {
+            interval,
+            rebalance,
+            exec: command,
+        } => {
+            let notice = watch_interruption(client, *interval, *rebalance)?;
+            if let Some(command) = command {
+                return exec(command, &notice, &cli.prefix);
+            }
+            let output = match cli.format() {
+                // There's no metric for a notice; print it as text.
+                Format::Text | Format::Prometheus => format!("{}\n", notice),
+                Format::Json => format!("{}\n", notice.json().dump()),
+                Format::Yaml => yaml(&notice.json()),
+                Format::Csv => csv_object(&notice.json(), !cli.no_header),
+                Format::Shell => notice
+                    .env(&cli.prefix)
+                    .iter()
+                    .map(|(name, value)| format!("export {}={}\n", name, shell_quote(value)))
+                    .collect(),
+            };
+            write_output(cli, &output);
+        }
+        Command::Completions { shell } => {
+            let mut script = Vec::new();
+            clap_complete::generate(*shell, &mut Cli::command(), "fetcher", &mut script);
+            write_output(cli, &String::from_utf8_lossy(&script));
+        }
+        Command::Daemon { interval, quiet } => return daemon(client, cli, *interval, *quiet),
+    }
+
+    Ok(())
+}
 
 fn main() {
-    let client = ec2_instance_metadata::InstanceMetadataClient::new();
-    let metadata = client.get();
-    println!("metadata:\n{:?}", metadata.unwrap());
+    // clap exits with 2 on invalid arguments, which is taken to mean IMDS is
+    // unreachable here.
+    let cli = Cli::try_parse().unwrap_or_else(|e| {
+        let _ = e.print();
+        std::process::exit(if e.use_stderr() { EXIT_OTHER } else { 0 });
+    });
+    if !cli.fields.is_empty()
+        && (cli.format_template.is_some() || cli.format() == Format::Prometheus)
+    {
+        let e = Cli::command().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            "--field can't be combined with --format-template or --format prometheus",
+        );
+        let _ = e.print();
+        std::process::exit(EXIT_OTHER);
+    }
+    if let (Some(Command::Daemon { .. }), None) = (&cli.command, &cli.output_file) {
+        let e = Cli::command().error(
+            clap::error::ErrorKind::MissingRequiredArgument,
+            "daemon needs --output-file",
+        );
+        let _ = e.print();
+        std::process::exit(EXIT_OTHER);
+    }
+    let mut builder = InstanceMetadataClient::builder();
+    if let Some(endpoint) = &cli.endpoint {
+        builder = builder.endpoint(endpoint.as_str());
+    }
+    // Health checks run often, and mustn't hang around when IMDS is down.
+    let default_timeout = match cli.command {
+        Some(Command::Check) => Some(CHECK_TIMEOUT),
+        _ => None,
+    };
+    if let Some(timeout) = cli.timeout.or(default_timeout) {
+        builder = builder.request_timeout(timeout).overall_timeout(timeout);
+    }
+    if let Some(connect_timeout) = cli.connect_timeout {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+    if cli.insecure_v1 {
+        eprintln!("fetcher: warning: using IMDSv1, without a session token (--insecure-v1)");
+        builder = builder.imds_v1(ImdsV1::Only);
+    }
+    let client = builder.build();
+
+    if let Err(e) = run(&client, &cli) {
+        fail(&e, cli.format() == Format::Json);
+    }
 }
@@ -0,0 +1,368 @@
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+/// `BindToLocalAddress` sends requests from a specific source address.
+///
+/// ureq can't bind the sockets it opens, so this performs the request itself
+/// over a bound socket and hands the response to ureq with its body as it
+/// was sent, dechunked. Only what this crate needs is supported: bodiless
+/// requests over plain HTTP/1.1, one connection per request.
+#[derive(Debug)]
+pub(crate) struct BindToLocalAddress {
+    pub(crate) local_address: IpAddr,
+    pub(crate) connect_timeout: Duration,
+    pub(crate) max_response_size: u64,
+}
+
+impl BindToLocalAddress {
+    /// Send `request`, giving up once `limit` has passed, as ureq does with a
+    /// request's `timeout`. Error statuses are returned as
+    /// `ureq::Error::Status`, like ureq.
+    // The error is ureq's, as `Request::call` returns it.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn send(
+        &self,
+        request: &ureq::Request,
+        limit: Duration,
+    ) -> Result<ureq::Response, ureq::Error> {
+        let deadline = Instant::now() + limit;
+        let url = request.request_url()?;
+        let host = url.host().to_string();
+        let port = url.port().unwrap_or(80);
+
+        let remote = (host.as_str(), port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for host"))?;
+        let mut stream = self.connect(remote, deadline)?;
+
+        let host_header = match port {
+            80 => host,
+            _ => format!("{}:{}", host, port),
+        };
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: 0\r\n",
+            request.method(),
+            path_and_query(&url),
+            host_header
+        );
+        for name in request.header_names() {
+            for value in request.all(&name) {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str("\r\n");
+        stream.set_write_timeout(Some(remaining(deadline)?))?;
+        stream.write_all(head.as_bytes())?;
+        stream.flush()?;
+
+        // Leave headroom for the status line and headers; the caller enforces
+        // the actual body limit.
+        let cap = self.max_response_size.saturating_add(64 * 1024);
+        let mut raw = Vec::new();
+        let mut buf = [0; 8 * 1024];
+        while (raw.len() as u64) < cap {
+            stream.set_read_timeout(Some(remaining(deadline)?))?;
+            match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => raw.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let capped = raw.len() as u64 >= cap;
+
+        let response: ureq::Response = parse_response(&raw, capped)?.into();
+        match response.status() {
+            status @ 400..=599 => Err(ureq::Error::Status(status, response)),
+            _ => Ok(response),
+        }
+    }
+
+    fn connect(&self, remote: SocketAddr, deadline: Instant) -> io::Result<TcpStream> {
+        let socket = Socket::new(
+            Domain::for_address(remote),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        socket.bind(&SockAddr::from(SocketAddr::new(self.local_address, 0)))?;
+        let timeout = remaining(deadline)?.min(self.connect_timeout);
+        socket.connect_timeout(&SockAddr::from(remote), timeout)?;
+        Ok(socket.into())
+    }
+}
+
+/// The time left until `deadline`, or a timeout error if there's none.
+fn remaining(deadline: Instant) -> io::Result<Duration> {
+    match deadline.checked_duration_since(Instant::now()) {
+        Some(remaining) if !remaining.is_zero() => Ok(remaining),
+        _ => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "the request timed out",
+        )),
+    }
+}
+
+fn path_and_query(url: &ureq::RequestUrl) -> String {
+    match url.as_url().query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Parse a raw HTTP/1.x response, decoding a chunked body. If the read was
+/// `capped` the body may be cut short, which the caller's body limit then
+/// catches; otherwise a short body is an error.
+fn parse_response(raw: &[u8], capped: bool) -> io::Result<http::Response<Vec<u8>>> {
+    let end = find(raw, b"\r\n\r\n").ok_or_else(|| invalid("the response ended in its headers"))?;
+    let head = std::str::from_utf8(&raw[..end])
+        .map_err(|_| invalid("the response's headers aren't UTF-8"))?;
+    let mut lines = head.split("\r\n");
+
+    let mut status_line = lines.next().unwrap_or_default().splitn(3, ' ');
+    if !status_line
+        .next()
+        .is_some_and(|version| version.starts_with("HTTP/1."))
+    {
+        return Err(invalid("the response isn't HTTP/1.x"));
+    }
+    let status = status_line
+        .next()
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| invalid("the response has no status"))?;
+
+    let mut response = http::Response::builder().status(status);
+    let mut chunked = false;
+    let mut content_length = None;
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid("malformed response header"))?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            if !value.eq_ignore_ascii_case("chunked") {
+                return Err(invalid("unsupported Transfer-Encoding"));
+            }
+            // The body is handed over dechunked.
+            chunked = true;
+            continue;
+        }
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(
+                value
+                    .parse::<usize>()
+                    .map_err(|_| invalid("malformed Content-Length"))?,
+            );
+        }
+        response = response.header(name, value);
+    }
+
+    let body = &raw[end + 4..];
+    let body = match (chunked, content_length) {
+        (true, _) => dechunk(body, capped)?,
+        (false, Some(length)) if length <= body.len() => body[..length].to_vec(),
+        (false, Some(_)) if !capped => return Err(invalid("the response body was cut short")),
+        (false, _) => body.to_vec(),
+    };
+    response
+        .body(body)
+        .map_err(|e| invalid(&format!("malformed response: {}", e)))
+}
+
+/// Decode a chunked body, ignoring chunk extensions and trailers.
+fn dechunk(mut chunks: &[u8], capped: bool) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = match find(chunks, b"\r\n") {
+            Some(line_end) => line_end,
+            None if capped => return Ok(body),
+            None => return Err(invalid("the chunked body was cut short")),
+        };
+        let size = std::str::from_utf8(&chunks[..line_end])
+            .ok()
+            .and_then(|line| line.split(';').next())
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+            .ok_or_else(|| invalid("malformed chunk size"))?;
+        if size == 0 {
+            return Ok(body);
+        }
+
+        let data = &chunks[line_end + 2..];
+        if data.len() < size.saturating_add(2) {
+            if capped {
+                body.extend_from_slice(&data[..size.min(data.len())]);
+                return Ok(body);
+            }
+            return Err(invalid("the chunked body was cut short"));
+        }
+        if &data[size..size + 2] != b"\r\n" {
+            return Err(invalid("malformed chunk"));
+        }
+        body.extend_from_slice(&data[..size]);
+        chunks = &data[size + 2..];
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Deadline, Error, ErrorKind, InstanceMetadataClient, MockImds};
+
+    fn bound_client(mock: &MockImds, local_address: &str) -> InstanceMetadataClient {
+        InstanceMetadataClient::builder()
+            .endpoint(mock.endpoint())
+            .local_address(local_address.parse().unwrap())
+            .build()
+    }
+
+    #[test]
+    fn requests_come_from_the_local_address() {
+        let mock = MockImds::start();
+        let metadata = bound_client(&mock, "127.0.0.1").get().unwrap();
+        assert_eq!(metadata.instance_id, MockImds::INSTANCE_ID);
+
+        let requests = mock.requests();
+        assert!(!requests.is_empty());
+        assert!(requests
+            .iter()
+            .all(|request| request.peer_address.ip() == IpAddr::from([127, 0, 0, 1])));
+    }
+
+    // All of 127.0.0.0/8 is local on Linux, so a source address other than
+    // the one the OS would pick can be bound.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn requests_come_from_a_non_default_local_address() {
+        let mock = MockImds::start();
+        bound_client(&mock, "127.0.0.2").get().unwrap();
+
+        let requests = mock.requests();
+        assert!(!requests.is_empty());
+        assert!(requests
+            .iter()
+            .all(|request| request.peer_address.ip() == IpAddr::from([127, 0, 0, 2])));
+    }
+
+    #[test]
+    fn binding_an_address_not_on_the_host_fails_fast() {
+        let mock = MockImds::start();
+        // TEST-NET-1 is never assigned to a host.
+        let client = bound_client(&mock, "192.0.2.1");
+
+        let started = Instant::now();
+        let error = client.get().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Transport);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(mock.requests().is_empty());
+    }
+
+    #[test]
+    fn bodies_are_passed_through_untouched() {
+        let mock = MockImds::start();
+        let user_data = vec![0x1f, 0x8b, 0x08, 0x00, 0xff, 0xfe, b'\r', b'\n', 0x00];
+        mock.set_bytes("user-data", user_data.clone());
+        mock.set_bytes("meta-data/tags/instance/Name", vec![b'a', 0xff]);
+
+        let client = bound_client(&mock, "127.0.0.1");
+        assert_eq!(client.get_user_data().unwrap(), Some(user_data));
+        assert!(matches!(
+            client.get_raw("tags/instance/Name"),
+            Err(Error::InvalidUtf8 { .. })
+        ));
+    }
+
+    #[test]
+    fn redirects_are_not_followed() {
+        let mock = MockImds::start();
+        let target = MockImds::start();
+        let location = format!("{}/latest/meta-data/instance-id", target.endpoint());
+        mock.set_redirect("meta-data/instance-id", 302, location.clone());
+
+        assert_eq!(
+            bound_client(&mock, "127.0.0.1").get_raw("instance-id"),
+            Err(Error::UnexpectedRedirect(location))
+        );
+        assert!(target.requests().is_empty());
+    }
+
+    #[test]
+    fn attempts_stop_at_the_overall_timeout() {
+        let mock = MockImds::start();
+        mock.set_delay(Duration::from_secs(2));
+        let client = InstanceMetadataClient::builder()
+            .endpoint(mock.endpoint())
+            .local_address("127.0.0.1".parse().unwrap())
+            .request_timeout(Duration::from_secs(5))
+            .overall_timeout(Duration::from_millis(200))
+            .build();
+
+        let started = Instant::now();
+        let error = client.fetch_token().unwrap_err();
+        assert!(
+            started.elapsed() < Duration::from_secs(1),
+            "{:?}",
+            started.elapsed()
+        );
+        match error {
+            Error::Token(e) => assert!(matches!(
+                *e,
+                Error::Timeout {
+                    deadline: Deadline::Overall,
+                    ..
+                }
+            )),
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn parses_a_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello";
+        let response = parse_response(raw, false).unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(response.body(), b"hello");
+    }
+
+    #[test]
+    fn dechunks_a_chunked_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+            4\r\nWiki\r\n6;ext=1\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nTrailer: x\r\n\r\n";
+        let response = parse_response(raw, false).unwrap();
+        assert_eq!(response.body(), b"Wikipedia in \r\n\r\nchunks.");
+        assert!(response.headers().get("transfer-encoding").is_none());
+    }
+
+    #[test]
+    fn rejects_short_responses() {
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhel", false).is_err());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 5", false).is_err());
+        assert!(parse_response(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel",
+            false
+        )
+        .is_err());
+        assert!(parse_response(b"<html>\r\n\r\n", false).is_err());
+        assert!(parse_response(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\nx",
+            false
+        )
+        .is_err());
+
+        // Past the read cap, the caller's body limit decides.
+        let capped = parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhel", true);
+        assert_eq!(capped.unwrap().body(), b"hel");
+    }
+}
@@ -0,0 +1,64 @@
+use crate::coalesce::lock;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// `CancellationToken` stops the client's polling and retry loops, e.g. when
+/// a service is shutting down. Clones share the same cancellation state.
+///
+/// Loops check the token between requests and wake from backoff sleeps as soon
+/// as it is cancelled, returning `Error::Cancelled`. A request that is already
+/// on the wire still runs to completion or its timeout.
+///
+/// # Examples:
+/// ```no_run
+/// use std::time::Duration;
+/// use ec2_instance_metadata::{CancellationToken, InstanceMetadataClient};
+///
+/// let cancel = CancellationToken::new();
+/// let client = InstanceMetadataClient::builder()
+///     .cancellation_token(cancel.clone())
+///     .build();
+///
+/// let waiter = std::thread::spawn(move || client.wait_until_ready(Duration::from_secs(600)));
+/// cancel.cancel();
+/// assert!(waiter.join().unwrap().is_err());
+/// ```
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every operation watching this token.
+    pub fn cancel(&self) {
+        let (cancelled, wakeup) = &*self.inner;
+        *lock(cancelled) = true;
+        wakeup.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *lock(&self.inner.0)
+    }
+
+    /// Sleep for `duration`, returning early with `false` if cancelled.
+    pub(crate) fn sleep(&self, duration: Duration) -> bool {
+        let (cancelled, wakeup) = &*self.inner;
+        let guard = lock(cancelled);
+        let (guard, _) = wakeup
+            .wait_timeout_while(guard, duration, |cancelled| !*cancelled)
+            .unwrap_or_else(|e| e.into_inner());
+        !*guard
+    }
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
@@ -0,0 +1,191 @@
+use crate::coalesce::lock;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Tracks consecutive transport failures and short-circuits requests for a
+/// cooldown period once `failure_threshold` is reached. After the cooldown the
+/// next request is let through as a probe, and the others keep being
+/// short-circuited until it's done: success closes the circuit, failure opens
+/// it again straight away. A probe that never reports back, e.g. because its
+/// thread panicked, is given up on after another cooldown.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug)]
+enum BreakerState {
+    Closed { consecutive_failures: u32 },
+    Open { until: Instant },
+    HalfOpen { probe_sent: Instant },
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::new(BreakerState::Closed {
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    /// Whether a request may be attempted right now. A `true` after the
+    /// cooldown makes the caller the probe, which must report its outcome.
+    pub(crate) fn allow(&self) -> bool {
+        let mut state = lock(&self.state);
+        let now = Instant::now();
+        let probe = match *state {
+            BreakerState::Closed { .. } => return true,
+            BreakerState::Open { until } => now >= until,
+            BreakerState::HalfOpen { probe_sent } => now >= probe_sent + self.cooldown,
+        };
+        if probe {
+            *state = BreakerState::HalfOpen { probe_sent: now };
+        }
+        probe
+    }
+
+    pub(crate) fn record_success(&self) {
+        *lock(&self.state) = BreakerState::Closed {
+            consecutive_failures: 0,
+        };
+    }
+
+    pub(crate) fn record_failure(&self) {
+        let mut state = lock(&self.state);
+        let consecutive_failures = match *state {
+            BreakerState::Closed {
+                consecutive_failures,
+            } => consecutive_failures.saturating_add(1),
+            // A failed probe, or a request that was let through before the
+            // circuit opened, opens it again.
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => self.failure_threshold,
+        };
+        *state = if consecutive_failures >= self.failure_threshold {
+            BreakerState::Open {
+                until: Instant::now() + self.cooldown,
+            }
+        } else {
+            BreakerState::Closed {
+                consecutive_failures,
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, ErrorKind, ImdsV1, InstanceMetadataClient, MockImds, NoRetry};
+    use std::thread::sleep;
+
+    const COOLDOWN: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn opens_at_the_threshold() {
+        let breaker = CircuitBreaker::new(3, COOLDOWN);
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.allow());
+
+        breaker.record_failure();
+        assert!(!breaker.allow());
+    }
+
+    #[test]
+    fn success_resets_the_count() {
+        let breaker = CircuitBreaker::new(2, COOLDOWN);
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn lets_a_single_probe_through_after_the_cooldown() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        breaker.record_failure();
+        assert!(!breaker.allow());
+
+        sleep(COOLDOWN);
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        assert!(!breaker.allow());
+
+        breaker.record_success();
+        assert!(breaker.allow());
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let breaker = CircuitBreaker::new(3, COOLDOWN);
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        sleep(COOLDOWN);
+        assert!(breaker.allow());
+
+        // One failure is enough once the circuit has opened.
+        breaker.record_failure();
+        assert!(!breaker.allow());
+        sleep(COOLDOWN);
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn abandoned_probe_is_replaced_after_a_cooldown() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        breaker.record_failure();
+        sleep(COOLDOWN);
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+
+        sleep(COOLDOWN);
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn state_is_shared_by_clones() {
+        let mock = MockImds::start();
+        // Every attempt times out, which counts as a transport failure.
+        mock.set_delay(Duration::from_millis(300));
+        let client = InstanceMetadataClient::builder()
+            .endpoint(mock.endpoint())
+            .imds_v1(ImdsV1::Only)
+            .retry_policy(NoRetry)
+            .request_timeout(Duration::from_millis(50))
+            .circuit_breaker(2, Duration::from_millis(200))
+            .build();
+        let clone = client.clone();
+
+        for _ in 0..2 {
+            assert_eq!(
+                client.get_raw("instance-id").unwrap_err().kind(),
+                ErrorKind::Timeout
+            );
+        }
+
+        let started = Instant::now();
+        assert_eq!(clone.get_raw("instance-id"), Err(Error::CircuitOpen));
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        // The probe, from another clone, closes the circuit for all of them.
+        mock.set_delay(Duration::ZERO);
+        sleep(Duration::from_millis(200));
+        let requests = mock.requests().len();
+        assert_eq!(
+            client.clone().get_raw("instance-id").unwrap().as_deref(),
+            Some(MockImds::INSTANCE_ID)
+        );
+        assert_eq!(
+            clone.get_raw("instance-id").unwrap().as_deref(),
+            Some(MockImds::INSTANCE_ID)
+        );
+        assert_eq!(mock.requests().len(), requests + 2);
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+enum CallState<T> {
+    Pending,
+    Done(T),
+    Abandoned, // The leading caller panicked before producing a value.
+}
+
+struct Call<T> {
+    state: Mutex<CallState<T>>,
+    done: Condvar,
+}
+
+/// `Coalescer` lets concurrent callers asking for the same key share a single
+/// in-flight computation. The first caller runs the work; everyone arriving
+/// while it is underway blocks and receives a clone of its result. Nothing is
+/// cached once the call completes.
+pub(crate) struct Coalescer<K, T> {
+    calls: Mutex<HashMap<K, Arc<Call<T>>>>,
+}
+
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl<K: Eq + Hash + Clone, T: Clone> Coalescer<K, T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn run<F: FnOnce() -> T>(&self, key: K, f: F) -> T {
+        let (call, leader) = {
+            let mut calls = lock(&self.calls);
+            match calls.get(&key) {
+                Some(call) => (call.clone(), false),
+                None => {
+                    let call = Arc::new(Call {
+                        state: Mutex::new(CallState::Pending),
+                        done: Condvar::new(),
+                    });
+                    calls.insert(key.clone(), call.clone());
+                    (call, true)
+                }
+            }
+        };
+
+        if !leader {
+            let mut state = lock(&call.state);
+            loop {
+                match &*state {
+                    CallState::Pending => {
+                        state = call.done.wait(state).unwrap_or_else(|e| e.into_inner())
+                    }
+                    CallState::Done(value) => return value.clone(),
+                    CallState::Abandoned => break,
+                }
+            }
+            drop(state);
+            return f();
+        }
+
+        let guard = LeaderGuard {
+            coalescer: self,
+            key,
+            call,
+            finished: false,
+        };
+        let value = f();
+        guard.finish(value.clone());
+        value
+    }
+}
+
+impl<K, T> std::fmt::Debug for Coalescer<K, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Coalescer {{ in_flight: {} }}", lock(&self.calls).len())
+    }
+}
+
+/// Removes the call from the in-flight table and wakes any waiters, even if
+/// the leading caller unwinds.
+struct LeaderGuard<'a, K: Eq + Hash, T> {
+    coalescer: &'a Coalescer<K, T>,
+    key: K,
+    call: Arc<Call<T>>,
+    finished: bool,
+}
+
+impl<'a, K: Eq + Hash, T> LeaderGuard<'a, K, T> {
+    fn finish(mut self, value: T) {
+        self.complete(CallState::Done(value));
+        self.finished = true;
+    }
+
+    fn complete(&self, state: CallState<T>) {
+        lock(&self.coalescer.calls).remove(&self.key);
+        *lock(&self.call.state) = state;
+        self.call.done.notify_all();
+    }
+}
+
+impl<'a, K: Eq + Hash, T> Drop for LeaderGuard<'a, K, T> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.complete(CallState::Abandoned);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Barrier};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn concurrent_callers_share_one_call() {
+        const CALLERS: usize = 8;
+
+        let coalescer = Arc::new(Coalescer::<&str, usize>::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(CALLERS));
+
+        let callers: Vec<_> = (0..CALLERS)
+            .map(|_| {
+                let (coalescer, calls, barrier) =
+                    (coalescer.clone(), calls.clone(), barrier.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    coalescer.run("key", || {
+                        thread::sleep(Duration::from_millis(100));
+                        calls.fetch_add(1, Ordering::SeqCst) + 1
+                    })
+                })
+            })
+            .collect();
+        for caller in callers {
+            assert_eq!(caller.join().unwrap(), 1);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Nothing is cached once the call completes.
+        assert_eq!(coalescer.run("key", || 2), 2);
+    }
+
+    #[test]
+    fn panicking_leader_releases_waiters() {
+        let coalescer = Arc::new(Coalescer::<(), u32>::new());
+        let (started, leader_started) = mpsc::channel();
+
+        let leader = {
+            let coalescer = coalescer.clone();
+            thread::spawn(move || {
+                coalescer.run((), || {
+                    started.send(()).unwrap();
+                    thread::sleep(Duration::from_millis(100));
+                    panic!("the leader failed");
+                })
+            })
+        };
+        leader_started.recv().unwrap();
+
+        // The waiter joins the in-flight call, then runs its own work once
+        // the leader abandons it.
+        let (done, waiter_done) = mpsc::channel();
+        {
+            let coalescer = coalescer.clone();
+            thread::spawn(move || done.send(coalescer.run((), || 7)).unwrap());
+        }
+        assert_eq!(waiter_done.recv_timeout(Duration::from_secs(5)), Ok(7));
+        assert!(leader.join().is_err());
+
+        assert!(lock(&coalescer.calls).is_empty());
+        assert_eq!(coalescer.run((), || 8), 8);
+    }
+}
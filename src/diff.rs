@@ -0,0 +1,91 @@
+use crate::InstanceMetadata;
+use std::collections::BTreeSet;
+
+/// `FieldChange` is a value that differs between two metadata snapshots, as
+/// reported by `InstanceMetadata::diff`.
+///
+/// Its `Display` output is one line, e.g. `public_hostname: - -> ec2-...`,
+/// with `-` for a missing value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct FieldChange {
+    /// The key, as used by `InstanceMetadata::iter`, or `extras.<path>` for
+    /// extras
+    pub key: String,
+
+    /// The value in the older snapshot
+    pub old: Option<String>,
+
+    /// The value in the newer snapshot
+    pub new: Option<String>,
+}
+
+impl std::fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.key,
+            self.old.as_deref().unwrap_or("-"),
+            self.new.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+impl InstanceMetadata {
+    /// The values that differ between `self`, the older snapshot, and `other`,
+    /// in `iter` order followed by extras. `fetched_at` is ignored.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::InstanceMetadata;
+    ///
+    /// let provisioned = InstanceMetadata::builder().extra("kernel-id", "aki-1").build();
+    /// let current = InstanceMetadata::builder()
+    ///     .public_hostname(Some("ec2-1-2-3-4.compute-1.amazonaws.com"))
+    ///     .build();
+    ///
+    /// let changes: Vec<String> = provisioned
+    ///     .diff(&current)
+    ///     .iter()
+    ///     .map(ToString::to_string)
+    ///     .collect();
+    /// assert_eq!(
+    ///     changes,
+    ///     [
+    ///         "public_hostname: - -> ec2-1-2-3-4.compute-1.amazonaws.com",
+    ///         "extras.kernel-id: aki-1 -> -",
+    ///     ]
+    /// );
+    /// assert!(current.diff(&current).is_empty());
+    /// ```
+    pub fn diff(&self, other: &InstanceMetadata) -> Vec<FieldChange> {
+        let owned = |value: Option<&str>| value.map(str::to_string);
+
+        let mut changes: Vec<FieldChange> = self
+            .iter()
+            .zip(other.iter())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((key, old), (_, new))| FieldChange {
+                key: key.to_string(),
+                old: owned(old),
+                new: owned(new),
+            })
+            .collect();
+
+        let paths: BTreeSet<&String> = self.extras.keys().chain(other.extras.keys()).collect();
+        for path in paths {
+            let old = self.extras.get(path).map(String::as_str);
+            let new = other.extras.get(path).map(String::as_str);
+            if old != new {
+                changes.push(FieldChange {
+                    key: format!("extras.{}", path),
+                    old: owned(old),
+                    new: owned(new),
+                });
+            }
+        }
+
+        changes
+    }
+}
@@ -0,0 +1,281 @@
+use crate::{Error, ImdsV1, InstanceMetadataClient, InstanceMetadataClientBuilder, Result};
+use std::collections::BTreeMap;
+
+/// `EcsMetadataClient` fetches the ECS task metadata (version 4), which
+/// containers on ECS, including Fargate, get from the URI in
+/// `ECS_CONTAINER_METADATA_URI_V4` rather than from IMDS.
+///
+/// Requests go through an `InstanceMetadataClient` pointed at the URI, so
+/// they get the same timeouts, retries, and errors. The task metadata
+/// endpoint takes no token.
+///
+/// # Examples:
+/// ```no_run
+/// use ec2_instance_metadata::EcsMetadataClient;
+/// let client = EcsMetadataClient::from_env().expect("Not running on ECS.");
+/// let task = client.get_task().expect("Couldn't get the task metadata.");
+/// println!("{} in {}", task.task_arn, task.cluster);
+/// ```
+#[derive(Debug, Clone)]
+pub struct EcsMetadataClient {
+    client: InstanceMetadataClient,
+}
+
+impl EcsMetadataClient {
+    /// The environment variable ECS sets to the task metadata URI.
+    pub const ENDPOINT_ENV_VAR: &'static str = "ECS_CONTAINER_METADATA_URI_V4";
+
+    /// A client for the URI in `ECS_CONTAINER_METADATA_URI_V4`, or `None` if
+    /// it isn't set, e.g. because the process isn't running on ECS.
+    pub fn from_env() -> Option<EcsMetadataClient> {
+        match std::env::var(Self::ENDPOINT_ENV_VAR) {
+            Ok(endpoint) if !endpoint.is_empty() => {
+                Some(EcsMetadataClient::with_endpoint(endpoint))
+            }
+            _ => None,
+        }
+    }
+
+    /// A client for the task metadata at `endpoint`, e.g. a local mock.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::{EcsMetadataClient, MockImds};
+    ///
+    /// let mock = MockImds::empty();
+    /// mock.set(
+    ///     "/v4/abc/task",
+    ///     r#"{"Cluster": "default", "TaskARN": "arn:aws:ecs:us-west-2:111122223333:task/default/158d",
+    ///         "Family": "web", "Revision": "3", "LaunchType": "EC2"}"#,
+    /// );
+    ///
+    /// let client = EcsMetadataClient::with_endpoint(format!("{}/v4/abc", mock.endpoint()));
+    /// let task = client.get_task().unwrap();
+    /// assert_eq!(task.launch_type.as_deref(), Some("EC2"));
+    /// assert!(client.get_container().is_err());
+    /// ```
+    pub fn with_endpoint<S: Into<String>>(endpoint: S) -> EcsMetadataClient {
+        EcsMetadataClient::with_builder(InstanceMetadataClient::builder().endpoint(endpoint))
+    }
+
+    /// A client configured by `builder`, whose endpoint is the task metadata
+    /// URI, e.g. to set timeouts or a retry policy. Token requests are turned
+    /// off.
+    pub fn with_builder(builder: InstanceMetadataClientBuilder) -> EcsMetadataClient {
+        EcsMetadataClient {
+            client: builder.imds_v1(ImdsV1::Only).build(),
+        }
+    }
+
+    /// Get the metadata of the task this container belongs to.
+    pub fn get_task(&self) -> Result<TaskMetadata> {
+        TaskMetadata::from_json(&self.fetch("/task")?)
+    }
+
+    /// Get the metadata of this container.
+    pub fn get_container(&self) -> Result<ContainerMetadata> {
+        ContainerMetadata::from_json(&self.fetch("")?)
+    }
+
+    fn fetch(&self, path: &str) -> Result<String> {
+        self.client
+            .fetch_absolute("", path)?
+            .ok_or_else(|| Error::HttpStatus {
+                status: 404,
+                path: path.to_string(),
+                body_excerpt: String::new(),
+            })
+    }
+}
+
+/// `TaskMetadata` is the ECS task metadata document.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::{Error, TaskMetadata};
+///
+/// let task = TaskMetadata::from_json(
+///     r#"{
+///         "Cluster": "arn:aws:ecs:us-west-2:111122223333:cluster/default",
+///         "TaskARN": "arn:aws:ecs:us-west-2:111122223333:task/default/158d1c8083dd",
+///         "Family": "web",
+///         "Revision": "26",
+///         "DesiredStatus": "RUNNING",
+///         "KnownStatus": "RUNNING",
+///         "Limits": {"CPU": 0.25, "Memory": 512},
+///         "AvailabilityZone": "us-west-2d",
+///         "LaunchType": "FARGATE",
+///         "Containers": [{
+///             "DockerId": "cd189a933e5849daa93386466019ab50-2495160603",
+///             "Name": "web",
+///             "Image": "nginx:latest",
+///             "Labels": {"com.amazonaws.ecs.task-definition-family": "web"},
+///             "DesiredStatus": "RUNNING",
+///             "KnownStatus": "RUNNING",
+///             "Limits": {"CPU": 2},
+///             "Type": "NORMAL"
+///         }]
+///     }"#,
+/// )
+/// .unwrap();
+/// assert_eq!(task.family, "web");
+/// assert_eq!(task.limits.cpu, Some(0.25));
+/// assert_eq!(task.limits.memory, Some(512));
+/// assert_eq!(task.launch_type.as_deref(), Some("FARGATE"));
+/// assert_eq!(task.containers[0].image, "nginx:latest");
+/// assert_eq!(task.containers[0].limits.memory, None);
+///
+/// assert_eq!(
+///     TaskMetadata::from_json(r#"{"Cluster": "default"}"#),
+///     Err(Error::MissingField("TaskARN"))
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskMetadata {
+    /// The cluster's name or ARN
+    pub cluster: String,
+
+    /// The task's ARN
+    pub task_arn: String,
+
+    /// The task definition's family
+    pub family: String,
+
+    /// The task definition's revision
+    pub revision: String,
+
+    /// The status ECS is moving the task to, e.g. `"RUNNING"`
+    pub desired_status: String,
+
+    /// The task's last known status
+    pub known_status: String,
+
+    /// The task's resource limits - unset if only its containers have any
+    pub limits: Limits,
+
+    /// The availability zone the task runs in - `None` on older agents
+    pub availability_zone: Option<String>,
+
+    /// `"EC2"` or `"FARGATE"` - `None` on older agents
+    pub launch_type: Option<String>,
+
+    /// The task's containers
+    pub containers: Vec<ContainerMetadata>,
+}
+
+impl TaskMetadata {
+    /// Parse the task metadata document.
+    pub fn from_json(json: &str) -> Result<TaskMetadata> {
+        let parsed = json::parse(json)?;
+        let containers = parsed["Containers"]
+            .members()
+            .map(ContainerMetadata::from_parsed)
+            .collect::<Result<_>>()?;
+
+        Ok(TaskMetadata {
+            cluster: required(&parsed, "Cluster")?,
+            task_arn: required(&parsed, "TaskARN")?,
+            family: required(&parsed, "Family")?,
+            revision: required(&parsed, "Revision")?,
+            desired_status: optional(&parsed, "DesiredStatus").unwrap_or_default(),
+            known_status: optional(&parsed, "KnownStatus").unwrap_or_default(),
+            limits: Limits::from_parsed(&parsed["Limits"]),
+            availability_zone: optional(&parsed, "AvailabilityZone"),
+            launch_type: optional(&parsed, "LaunchType"),
+            containers,
+        })
+    }
+}
+
+/// `ContainerMetadata` describes one container of an ECS task.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerMetadata {
+    /// The Docker container id
+    pub docker_id: String,
+
+    /// The container's name in the task definition
+    pub name: String,
+
+    /// The image the container runs
+    pub image: String,
+
+    /// The image's digest - `None` if the agent doesn't report it
+    pub image_id: Option<String>,
+
+    /// The container's Docker labels
+    pub labels: BTreeMap<String, String>,
+
+    /// The status ECS is moving the container to, e.g. `"RUNNING"`
+    pub desired_status: String,
+
+    /// The container's last known status
+    pub known_status: String,
+
+    /// The container's resource limits
+    pub limits: Limits,
+
+    /// The container's ARN - `None` on older agents
+    pub container_arn: Option<String>,
+
+    /// `"NORMAL"` for the task's own containers, other values for those ECS
+    /// adds - `None` on older agents
+    pub container_type: Option<String>,
+}
+
+impl ContainerMetadata {
+    /// Parse a container metadata document, as served at the root of the
+    /// task metadata URI.
+    pub fn from_json(json: &str) -> Result<ContainerMetadata> {
+        ContainerMetadata::from_parsed(&json::parse(json)?)
+    }
+
+    fn from_parsed(parsed: &json::JsonValue) -> Result<ContainerMetadata> {
+        let labels = parsed["Labels"]
+            .entries()
+            .filter_map(|(key, value)| Some((key.to_string(), value.as_str()?.to_string())))
+            .collect();
+
+        Ok(ContainerMetadata {
+            docker_id: required(parsed, "DockerId")?,
+            name: required(parsed, "Name")?,
+            image: required(parsed, "Image")?,
+            image_id: optional(parsed, "ImageID"),
+            labels,
+            desired_status: optional(parsed, "DesiredStatus").unwrap_or_default(),
+            known_status: optional(parsed, "KnownStatus").unwrap_or_default(),
+            limits: Limits::from_parsed(&parsed["Limits"]),
+            container_arn: optional(parsed, "ContainerARN"),
+            container_type: optional(parsed, "Type"),
+        })
+    }
+}
+
+/// Resource limits of an ECS task or container.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// CPU, in vCPUs for a task and CPU units (1024 to a vCPU) for a container
+    pub cpu: Option<f64>,
+
+    /// Memory, in MiB
+    pub memory: Option<u64>,
+}
+
+impl Limits {
+    fn from_parsed(parsed: &json::JsonValue) -> Limits {
+        Limits {
+            cpu: parsed["CPU"].as_f64(),
+            memory: parsed["Memory"].as_u64(),
+        }
+    }
+}
+
+fn required(parsed: &json::JsonValue, name: &'static str) -> Result<String> {
+    optional(parsed, name).ok_or(Error::MissingField(name))
+}
+
+fn optional(parsed: &json::JsonValue, name: &str) -> Option<String> {
+    match parsed[name].as_str() {
+        Some(value) if !value.is_empty() => Some(value.to_string()),
+        _ => None,
+    }
+}
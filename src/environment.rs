@@ -0,0 +1,274 @@
+use crate::{EcsMetadataClient, Error, Field, InstanceMetadataClient, NoRetry};
+use crate::{InstanceMetadataClientBuilder, TaskMetadata};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// How long `detect_environment` waits for IMDS or the ECS task metadata.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The environment variable Lambda sets to the function's name.
+const LAMBDA_FUNCTION_VAR: &str = "AWS_LAMBDA_FUNCTION_NAME";
+
+/// The environment variable Lambda and ECS set to the runtime, e.g.
+/// `AWS_Lambda_python3.12` or `AWS_ECS_FARGATE`.
+const EXECUTION_ENV_VAR: &str = "AWS_EXECUTION_ENV";
+
+/// Where the process is running, as `detect_environment` tells it, along
+/// with the evidence for it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Environment {
+    /// On an EC2 instance, outside ECS, with IMDS handing out an instance id
+    Ec2(Evidence),
+
+    /// In an ECS task on an EC2 instance
+    EcsOnEc2(Evidence),
+
+    /// In an ECS task on Fargate, where there's no IMDS
+    Fargate(Evidence),
+
+    /// In a Lambda function, where there's no IMDS
+    Lambda(Evidence),
+
+    /// Not on AWS: nothing says otherwise, and connecting to IMDS was refused
+    NotAws(Evidence),
+
+    /// Undecided: the evidence is missing, e.g. because nothing was probed or
+    /// IMDS timed out, or contradicts itself
+    Unknown(Evidence),
+}
+
+impl Environment {
+    /// What the environment was told by.
+    pub fn evidence(&self) -> &Evidence {
+        match self {
+            Environment::Ec2(evidence)
+            | Environment::EcsOnEc2(evidence)
+            | Environment::Fargate(evidence)
+            | Environment::Lambda(evidence)
+            | Environment::NotAws(evidence)
+            | Environment::Unknown(evidence) => evidence,
+        }
+    }
+}
+
+/// `Evidence` is what `detect_environment` found while telling the
+/// environment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Evidence {
+    /// Those of `ECS_CONTAINER_METADATA_URI_V4`, `AWS_LAMBDA_FUNCTION_NAME`,
+    /// and `AWS_EXECUTION_ENV` that are set, with their values
+    pub env_vars: BTreeMap<&'static str, String>,
+
+    /// Whether IMDS or the ECS task metadata was probed
+    pub probed: bool,
+
+    /// The instance id IMDS handed out, if probed
+    pub instance_id: Option<String>,
+
+    /// The launch type in the ECS task metadata, if probed
+    pub launch_type: Option<String>,
+
+    /// Why the probe failed, if it did
+    pub probe_error: Option<Error>,
+}
+
+/// Tell where the process is running: on plain EC2, in an ECS task on EC2
+/// or Fargate, in a Lambda function, or not on AWS, so callers can pick the
+/// client to use.
+///
+/// The environment variables `ECS_CONTAINER_METADATA_URI_V4`,
+/// `AWS_LAMBDA_FUNCTION_NAME`, and `AWS_EXECUTION_ENV` come first; then, where
+/// they don't settle it, a probe with a 1 second timeout and no retries:
+/// the ECS task metadata's launch type in a task, and IMDS's instance id
+/// elsewhere. Lambda needs no probe. Evidence that's missing or contradicts
+/// itself gives `Environment::Unknown` rather than a guess, so e.g. an IMDS
+/// timeout is `Unknown`, and only a refused connection is `NotAws`.
+///
+/// # Examples:
+/// ```no_run
+/// use ec2_instance_metadata::{detect_environment, EcsMetadataClient, Environment};
+/// use ec2_instance_metadata::InstanceMetadataClient;
+///
+/// match detect_environment() {
+///     Environment::EcsOnEc2(_) | Environment::Fargate(_) => {
+///         let task = EcsMetadataClient::from_env().unwrap().get_task().unwrap();
+///         println!("task {}", task.task_arn);
+///     }
+///     Environment::Ec2(_) => {
+///         let metadata = InstanceMetadataClient::new().get().unwrap();
+///         println!("instance {}", metadata.instance_id);
+///     }
+///     Environment::Lambda(evidence) => {
+///         println!("function {}", evidence.env_vars["AWS_LAMBDA_FUNCTION_NAME"]);
+///     }
+///     other => println!("not enriching: {:?}", other.evidence()),
+/// }
+/// ```
+pub fn detect_environment() -> Environment {
+    detect_environment_with(Some(&probe_builder().build()))
+}
+
+/// Tell where the process is running, like `detect_environment`, probing
+/// IMDS with `probe`, e.g. to point it at a mock, or not probing at all if
+/// it's `None`.
+///
+/// Without a probe, ECS tasks are only told apart by `AWS_EXECUTION_ENV`, and
+/// anything else that isn't Lambda is `Environment::Unknown`.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::{detect_environment_with, Environment};
+///
+/// std::env::set_var("AWS_LAMBDA_FUNCTION_NAME", "enricher");
+/// std::env::set_var("AWS_EXECUTION_ENV", "AWS_Lambda_rust");
+/// let environment = detect_environment_with(None);
+/// assert!(matches!(environment, Environment::Lambda(_)));
+/// assert!(!environment.evidence().probed);
+///
+/// // A task on ECS wouldn't also be a Lambda function.
+/// std::env::set_var("ECS_CONTAINER_METADATA_URI_V4", "http://169.254.170.2/v4/abc");
+/// assert!(matches!(detect_environment_with(None), Environment::Unknown(_)));
+///
+/// std::env::remove_var("AWS_LAMBDA_FUNCTION_NAME");
+/// std::env::set_var("AWS_EXECUTION_ENV", "AWS_ECS_FARGATE");
+/// assert!(matches!(detect_environment_with(None), Environment::Fargate(_)));
+///
+/// std::env::remove_var("ECS_CONTAINER_METADATA_URI_V4");
+/// std::env::remove_var("AWS_EXECUTION_ENV");
+/// assert!(matches!(detect_environment_with(None), Environment::Unknown(_)));
+///
+/// let mock = ec2_instance_metadata::MockImds::start();
+/// let environment = detect_environment_with(Some(&mock.client()));
+/// assert!(matches!(environment, Environment::Ec2(_)));
+/// assert_eq!(
+///     environment.evidence().instance_id.as_deref(),
+///     Some(ec2_instance_metadata::MockImds::INSTANCE_ID)
+/// );
+/// ```
+pub fn detect_environment_with(probe: Option<&InstanceMetadataClient>) -> Environment {
+    let mut evidence = Evidence::default();
+    for name in [
+        EcsMetadataClient::ENDPOINT_ENV_VAR,
+        LAMBDA_FUNCTION_VAR,
+        EXECUTION_ENV_VAR,
+    ]
+    .iter()
+    {
+        match std::env::var(name) {
+            Ok(value) if !value.is_empty() => {
+                evidence.env_vars.insert(name, value);
+            }
+            _ => {}
+        }
+    }
+
+    let execution_env = evidence.env_vars.get(EXECUTION_ENV_VAR).cloned();
+    let ecs_endpoint = evidence
+        .env_vars
+        .get(EcsMetadataClient::ENDPOINT_ENV_VAR)
+        .cloned();
+    let lambda = evidence.env_vars.contains_key(LAMBDA_FUNCTION_VAR);
+
+    match (ecs_endpoint, lambda) {
+        (Some(_), true) => Environment::Unknown(evidence),
+        (None, true) => match execution_env {
+            Some(runtime) if !runtime.starts_with("AWS_Lambda_") => Environment::Unknown(evidence),
+            _ => Environment::Lambda(evidence),
+        },
+        (Some(endpoint), false) => detect_ecs(
+            &endpoint,
+            execution_env.as_deref(),
+            probe.is_some(),
+            evidence,
+        ),
+        (None, false) => match (execution_env, probe) {
+            // An ECS or Lambda runtime without the rest of its variables.
+            (Some(runtime), _)
+                if runtime.starts_with("AWS_ECS_") || runtime.starts_with("AWS_Lambda_") =>
+            {
+                Environment::Unknown(evidence)
+            }
+            (_, Some(client)) => probe_imds(client, evidence),
+            (_, None) => Environment::Unknown(evidence),
+        },
+    }
+}
+
+/// Tell an ECS task on EC2 from one on Fargate, going by `AWS_EXECUTION_ENV`
+/// and, if `probe`, the launch type in the task metadata at `endpoint`,
+/// which have to agree.
+fn detect_ecs(
+    endpoint: &str,
+    execution_env: Option<&str>,
+    probe: bool,
+    mut evidence: Evidence,
+) -> Environment {
+    let claimed = match execution_env {
+        None => None,
+        Some("AWS_ECS_EC2") => Some("EC2"),
+        Some("AWS_ECS_FARGATE") => Some("FARGATE"),
+        // E.g. AWS_ECS_EXTERNAL, for ECS Anywhere off AWS.
+        Some(_) => return Environment::Unknown(evidence),
+    };
+
+    if probe {
+        evidence.probed = true;
+        let client = EcsMetadataClient::with_builder(probe_builder().endpoint(endpoint));
+        match client.get_task() {
+            Ok(TaskMetadata { launch_type, .. }) => evidence.launch_type = launch_type,
+            Err(e) => {
+                evidence.probe_error = Some(e);
+                return Environment::Unknown(evidence);
+            }
+        }
+    }
+
+    let launch_type = match (evidence.launch_type.as_deref(), claimed) {
+        (Some(found), Some(claimed)) if found != claimed => None,
+        (found, claimed) => found.or(claimed),
+    };
+    match launch_type {
+        Some("EC2") => Environment::EcsOnEc2(evidence),
+        Some("FARGATE") => Environment::Fargate(evidence),
+        _ => Environment::Unknown(evidence),
+    }
+}
+
+/// Tell EC2 by IMDS handing out an instance id, and not AWS by the
+/// connection being refused.
+fn probe_imds(client: &InstanceMetadataClient, mut evidence: Evidence) -> Environment {
+    evidence.probed = true;
+    match client.get_raw(Field::InstanceId.name()) {
+        Ok(Some(instance_id)) if instance_id.trim().starts_with("i-") => {
+            evidence.instance_id = Some(instance_id.trim().to_string());
+            Environment::Ec2(evidence)
+        }
+        // Something answered, but not with an instance id.
+        Ok(_) => Environment::Unknown(evidence),
+        Err(e) => {
+            let refused = is_refused(&e);
+            evidence.probe_error = Some(e);
+            if refused {
+                Environment::NotAws(evidence)
+            } else {
+                Environment::Unknown(evidence)
+            }
+        }
+    }
+}
+
+fn is_refused(error: &Error) -> bool {
+    match error {
+        Error::NotEc2(_) => true,
+        Error::Token(e) | Error::Fetch { source: e, .. } => is_refused(e),
+        _ => false,
+    }
+}
+
+/// A client that gives up after `PROBE_TIMEOUT`, without retrying.
+fn probe_builder() -> InstanceMetadataClientBuilder {
+    InstanceMetadataClient::builder()
+        .request_timeout(PROBE_TIMEOUT)
+        .overall_timeout(PROBE_TIMEOUT)
+        .retry_policy(NoRetry)
+}
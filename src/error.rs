@@ -0,0 +1,581 @@
+use crate::{FailureKind, Field};
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// `Error` is returned by every fallible client method.
+///
+/// New variants are added as the client learns to report new failures, so
+/// matches need a wildcard arm. Use `Error::kind` for a stable
+/// classification.
+///
+/// Underlying errors are reported through `source()`, not in the `Display`
+/// output; `full_message` has both. They're held in `Arc`s
+/// so `Error` stays `Clone`, since coalesced requests hand the same result to
+/// every waiting caller.
+#[derive(Clone, Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("Http request failed")]
+    HttpRequest(#[source] Arc<ureq::Transport>), // The request failed before a response was received.
+
+    #[error("IMDS is unreachable, this host is probably not on EC2")]
+    NotEc2(#[source] Arc<ureq::Transport>), // The connection was refused or had no route.
+
+    #[error("IMDS request timed out after {elapsed:?} ({deadline} of {limit:?})")]
+    Timeout {
+        elapsed: Duration,
+        limit: Duration,
+        deadline: Deadline, // Which limit ran out.
+    },
+
+    #[error("HTTP {status} from {path}{}", excerpt_suffix(.body_excerpt))]
+    HttpStatus {
+        status: u16,
+        path: String,
+        body_excerpt: String, // The start of the response body, possibly empty.
+    }, // IMDS answered with an error status.
+
+    #[error("IO error")]
+    IoError(#[source] Arc<std::io::Error>),
+
+    #[error("Unknown AvailabilityZone: {0}")]
+    UnknownAvailabilityZone(String), // The AZ name isn't shaped like any kind of zone.
+
+    #[error("JSON parsing failed")]
+    JsonError(#[source] Arc<json::Error>),
+
+    #[error("Not found: {0}")]
+    NotFound(Field), // IMDS answered 404 for a field.
+
+    #[error("IMDS not ready after {0:?}")]
+    NotReady(Duration), // IMDS didn't answer within the wait deadline.
+
+    #[error("Response body exceeded limit of {0} bytes")]
+    ResponseTooLarge(u64), // Body exceeded the configured limit, in bytes.
+
+    #[error("Credentials unavailable: {0}")]
+    CredentialsUnavailable(String), // IMDS didn't hand out usable role credentials.
+
+    #[error("IMDS circuit breaker is open")]
+    CircuitOpen, // Requests are short-circuited after repeated failures.
+
+    #[error("Operation cancelled")]
+    Cancelled, // The client's CancellationToken was cancelled.
+
+    #[error("Unexpected redirect to: {0}")]
+    UnexpectedRedirect(String), // Redirects are never followed; holds the Location.
+
+    #[error("Invalid metadata path: {0:?}")]
+    InvalidPath(String), // Rejected by raw getters before any request.
+
+    #[error("Couldn't get an IMDSv2 session token")]
+    Token(#[source] Box<Error>), // Acquiring the IMDSv2 session token failed.
+
+    #[error("Missing or empty field: {0}")]
+    MissingField(&'static str), // A JSON document lacked a required string field.
+
+    #[error("Empty response for: {0}")]
+    EmptyResponse(Field), // A required field came back empty.
+
+    #[error("Response from {path} isn't valid UTF-8: {lossy_preview:?}")]
+    InvalidUtf8 { path: String, lossy_preview: String }, // Holds the start of the body.
+
+    #[error("Response doesn't look like it came from IMDS: {0}")]
+    UnexpectedResponse(String), // e.g. an HTML page from a proxy; holds why.
+
+    #[error("Not a region name: {0:?}")]
+    InvalidRegionName(String), // Rejected by `register_regions`.
+
+    #[error("Unknown partition for region: {0}")]
+    UnknownPartition(String), // The region's partition couldn't be determined.
+
+    #[error("Region {region} doesn't match IMDS's partition {partition:?} and domain {domain:?}")]
+    RegionMismatch {
+        region: String,
+        partition: String,
+        domain: String, // Empty if IMDS didn't serve it.
+    }, // Found by the opt-in `verify_region` check.
+
+    #[error("Failed fetching {field}")]
+    Fetch { field: Field, source: Box<Error> }, // Fetching or interpreting a field failed.
+}
+
+impl Error {
+    /// The category this error falls into.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// use ec2_instance_metadata::{ErrorKind, InstanceMetadataClient};
+    ///
+    /// let client = InstanceMetadataClient::new();
+    /// match client.get_raw("placement/group-name") {
+    ///     Ok(group) => println!("placement group: {:?}", group),
+    ///     Err(e) if e.kind() == ErrorKind::Timeout => println!("IMDS is slow, try later"),
+    ///     Err(e) => panic!("{}", e),
+    /// }
+    /// ```
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Timeout { .. } => ErrorKind::Timeout,
+            Error::HttpRequest(_) | Error::NotEc2(_) | Error::IoError(_) => {
+                match self.failure_kind() {
+                    Some(FailureKind::Timeout) => ErrorKind::Timeout,
+                    _ => ErrorKind::Transport,
+                }
+            }
+            Error::HttpStatus { status: 404, .. } => ErrorKind::NotFound,
+            Error::HttpStatus { status: 401, .. } => ErrorKind::Unauthorized,
+            // IMDS answers 403 when its endpoint is turned off for the instance.
+            Error::HttpStatus { status: 403, .. } => ErrorKind::Disabled,
+            Error::HttpStatus { status: 429, .. } => ErrorKind::Throttled,
+            Error::HttpStatus { .. } => ErrorKind::Other,
+            Error::UnknownAvailabilityZone(_)
+            | Error::UnknownPartition(_)
+            | Error::JsonError(_)
+            | Error::MissingField(_)
+            | Error::EmptyResponse(_)
+            | Error::InvalidUtf8 { .. } => ErrorKind::Parse,
+            Error::NotFound(_) => ErrorKind::NotFound,
+            Error::NotReady(_) => ErrorKind::Timeout,
+            Error::CircuitOpen => ErrorKind::Transport,
+            Error::Token(e) => e.kind(),
+            Error::Fetch { source, .. } => source.kind(),
+            Error::ResponseTooLarge(_)
+            | Error::CredentialsUnavailable(_)
+            | Error::UnexpectedRedirect(_)
+            | Error::UnexpectedResponse(_)
+            | Error::InvalidPath(_)
+            | Error::InvalidRegionName(_)
+            | Error::RegionMismatch { .. }
+            | Error::Cancelled => ErrorKind::Other,
+        }
+    }
+
+    /// Whether the failure is likely transient, so repeating the call may
+    /// succeed: timeouts, connection resets, 5xx and 429 responses, and an
+    /// open circuit breaker. Missing values, invalid paths, unparseable
+    /// responses, and refused connections aren't.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// let client = ec2_instance_metadata::InstanceMetadataClient::new();
+    /// let metadata = loop {
+    ///     match client.get() {
+    ///         Ok(metadata) => break metadata,
+    ///         Err(e) if e.is_retryable() => std::thread::sleep(std::time::Duration::from_secs(1)),
+    ///         Err(e) => panic!("{}", e),
+    ///     }
+    /// };
+    /// ```
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::NotReady(_) | Error::CircuitOpen => true,
+            Error::Token(e) | Error::Fetch { source: e, .. } => e.is_retryable(),
+            _ => matches!(
+                self.failure_kind(),
+                Some(FailureKind::Timeout)
+                    | Some(FailureKind::ConnectionReset)
+                    | Some(FailureKind::ServerError(_))
+                    | Some(FailureKind::Throttled)
+            ),
+        }
+    }
+
+    /// Whether IMDS reported that there's nothing at the requested path.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Error::NotFound(_) => true,
+            Error::HttpStatus { status, .. } => *status == 404,
+            Error::Token(e) | Error::Fetch { source: e, .. } => e.is_not_found(),
+            _ => false,
+        }
+    }
+
+    /// Whether IMDS rejected the request with 429 Too Many Requests.
+    pub fn is_throttled(&self) -> bool {
+        match self {
+            Error::Token(e) | Error::Fetch { source: e, .. } => e.is_throttled(),
+            _ => self.failure_kind() == Some(FailureKind::Throttled),
+        }
+    }
+
+    /// Classify a transport or status failure the way the retry policy sees it.
+    fn failure_kind(&self) -> Option<FailureKind> {
+        match self {
+            Error::HttpRequest(transport) | Error::NotEc2(transport) => {
+                Some(FailureKind::from_transport(transport))
+            }
+            Error::Timeout { .. } => Some(FailureKind::Timeout),
+            Error::IoError(e) => Some(FailureKind::from_io(e)),
+            Error::HttpStatus { status, .. } => FailureKind::from_status(*status),
+            _ => None,
+        }
+    }
+
+    /// The io error kind behind a transport failure, e.g. to tell a reset
+    /// connection from a refused one.
+    pub fn io_error_kind(&self) -> Option<std::io::ErrorKind> {
+        use std::error::Error as _;
+
+        match self {
+            Error::HttpRequest(transport) | Error::NotEc2(transport) => transport
+                .source()
+                .and_then(|source| source.downcast_ref::<std::io::Error>())
+                .map(std::io::Error::kind),
+            Error::Timeout { .. } => Some(std::io::ErrorKind::TimedOut),
+            Error::IoError(e) => Some(e.kind()),
+            Error::Token(e) | Error::Fetch { source: e, .. } => e.io_error_kind(),
+            _ => None,
+        }
+    }
+
+    /// The error followed by each of its sources, e.g. for logs.
+    ///
+    /// # Examples:
+    /// ```
+    /// use std::time::Duration;
+    /// use ec2_instance_metadata::{Deadline, Error, Field};
+    ///
+    /// let error = Error::Fetch {
+    ///     field: Field::InstanceType,
+    ///     source: Box::new(Error::Timeout {
+    ///         elapsed: Duration::from_secs(2),
+    ///         limit: Duration::from_secs(2),
+    ///         deadline: Deadline::Request,
+    ///     }),
+    /// };
+    /// assert_eq!(error.to_string(), "Failed fetching instance-type");
+    /// assert_eq!(
+    ///     error.full_message(),
+    ///     "Failed fetching instance-type: IMDS request timed out after 2s (per-request limit of 2s)"
+    /// );
+    /// ```
+    pub fn full_message(&self) -> String {
+        use std::error::Error as _;
+
+        let mut message = self.to_string();
+        let mut source = self.source();
+        while let Some(error) = source {
+            // Some errors, like ureq's, already end with their source.
+            let error_message = error.to_string();
+            if !message.ends_with(&error_message) {
+                message.push_str(": ");
+                message.push_str(&error_message);
+            }
+            source = error.source();
+        }
+        message
+    }
+
+    /// Build the error for a response with an error status, keeping the
+    /// start of its body since IMDS and proxies often explain themselves
+    /// there.
+    pub(crate) fn from_status(resp: ureq::Response, path: &str) -> Error {
+        const EXCERPT_LEN: u64 = 256;
+
+        let status = resp.status();
+        let mut body = Vec::new();
+        let _ = resp.into_reader().take(EXCERPT_LEN).read_to_end(&mut body);
+
+        Error::HttpStatus {
+            status,
+            path: path.to_string(),
+            body_excerpt: String::from_utf8_lossy(&body).trim().to_string(),
+        }
+    }
+
+    /// Classify a request that failed without a response. Refused and
+    /// unroutable connections mean there's no IMDS to talk to, which callers
+    /// usually want to handle differently from a slow one.
+    pub(crate) fn from_transport(
+        transport: ureq::Transport,
+        elapsed: Duration,
+        limit: Duration,
+        deadline: Deadline,
+    ) -> Error {
+        match FailureKind::from_transport(&transport) {
+            FailureKind::Timeout => Error::Timeout {
+                elapsed,
+                limit,
+                deadline,
+            },
+            FailureKind::ConnectionRefused => Error::NotEc2(Arc::new(transport)),
+            _ => Error::HttpRequest(Arc::new(transport)),
+        }
+    }
+
+    /// Attribute an error to the field being fetched when it occurred.
+    /// Errors that already name a field, or that aren't about any particular
+    /// field, are left alone.
+    pub(crate) fn for_field(self, field: Field) -> Error {
+        match self {
+            Error::NotFound(_)
+            | Error::EmptyResponse(_)
+            | Error::Fetch { .. }
+            | Error::Token(_)
+            | Error::CircuitOpen
+            | Error::NotEc2(_)
+            | Error::Cancelled => self,
+            source => Error::Fetch {
+                field,
+                source: Box::new(source),
+            },
+        }
+    }
+}
+
+/// `Deadline` says which limit an `Error::Timeout` ran into.
+///
+/// # Examples:
+/// ```
+/// use std::time::Duration;
+/// use ec2_instance_metadata::{Deadline, Error};
+///
+/// let error = Error::Timeout {
+///     elapsed: Duration::from_millis(2003),
+///     limit: Duration::from_secs(2),
+///     deadline: Deadline::Request,
+/// };
+/// assert_eq!(
+///     error.to_string(),
+///     "IMDS request timed out after 2.003s (per-request limit of 2s)"
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Deadline {
+    /// The limit on a single attempt.
+    Request,
+
+    /// The client's overall budget for a request, including retries and
+    /// the sleeps between them. See `InstanceMetadataClientBuilder::overall_timeout`.
+    Overall,
+}
+
+impl std::fmt::Display for Deadline {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Deadline::Request => "per-request limit",
+            Deadline::Overall => "overall budget",
+        })
+    }
+}
+
+/// `ErrorKind` is a small, stable classification of an `Error`. The kind
+/// a given failure reports won't change as `Error`'s variants evolve.
+///
+/// # Examples:
+/// ```
+/// use std::time::Duration;
+/// use ec2_instance_metadata::{Deadline, Error, ErrorKind, Field};
+///
+/// let status = |status| Error::HttpStatus {
+///     status,
+///     path: "/latest/meta-data/instance-id".to_string(),
+///     body_excerpt: String::new(),
+/// };
+///
+/// let cases = vec![
+///     (Error::NotFound(Field::PublicHostname), ErrorKind::NotFound),
+///     (status(404), ErrorKind::NotFound),
+///     (status(401), ErrorKind::Unauthorized),
+///     (status(403), ErrorKind::Disabled),
+///     (status(429), ErrorKind::Throttled),
+///     (status(500), ErrorKind::Other),
+///     (
+///         Error::Timeout {
+///             elapsed: Duration::from_secs(2),
+///             limit: Duration::from_secs(2),
+///             deadline: Deadline::Request,
+///         },
+///         ErrorKind::Timeout,
+///     ),
+///     (Error::NotReady(Duration::from_secs(30)), ErrorKind::Timeout),
+///     (Error::CircuitOpen, ErrorKind::Transport),
+///     (Error::MissingField("AccountId"), ErrorKind::Parse),
+///     (Error::EmptyResponse(Field::InstanceId), ErrorKind::Parse),
+///     (Error::UnknownAvailabilityZone("xx-1a".to_string()), ErrorKind::Parse),
+///     (Error::InvalidPath("../x".to_string()), ErrorKind::Other),
+///     (Error::Cancelled, ErrorKind::Other),
+///     (
+///         Error::RegionMismatch {
+///             region: "cn-north-1".to_string(),
+///             partition: "aws".to_string(),
+///             domain: "amazonaws.com".to_string(),
+///         },
+///         ErrorKind::Other,
+///     ),
+///     (Error::Token(Box::new(status(403))), ErrorKind::Disabled),
+///     (
+///         Error::Fetch { field: Field::AmiId, source: Box::new(status(429)) },
+///         ErrorKind::Throttled,
+///     ),
+/// ];
+///
+/// for (error, kind) in cases {
+///     assert_eq!(error.kind(), kind, "{}", error);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// IMDS has nothing at the requested path.
+    NotFound,
+
+    /// IMDS rejected the session token (401).
+    Unauthorized,
+
+    /// IMDS is rate limiting requests (429).
+    Throttled,
+
+    /// A request or wait didn't complete in time.
+    Timeout,
+
+    /// IMDS couldn't be reached, or the connection failed mid-request.
+    Transport,
+
+    /// IMDS returned something that couldn't be interpreted.
+    Parse,
+
+    /// IMDS is turned off for this instance (403).
+    Disabled,
+
+    /// Anything else, including other error statuses and cancellation.
+    Other,
+}
+
+/// Errors compare structurally. Wrapped transport and io errors can't be
+/// compared directly, so they're equal when they failed the same way, i.e.
+/// have the same kind.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::{Error, Field};
+///
+/// assert_eq!(Error::NotFound(Field::PublicHostname), Error::NotFound(Field::PublicHostname));
+/// assert_ne!(Error::NotFound(Field::PublicHostname), Error::EmptyResponse(Field::PublicHostname));
+/// ```
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        match (self, other) {
+            (Error::HttpRequest(a), Error::HttpRequest(b))
+            | (Error::NotEc2(a), Error::NotEc2(b)) => {
+                a.kind() == b.kind()
+                    && FailureKind::from_transport(a) == FailureKind::from_transport(b)
+            }
+            (
+                Error::Timeout {
+                    elapsed,
+                    limit,
+                    deadline,
+                },
+                Error::Timeout {
+                    elapsed: other_elapsed,
+                    limit: other_limit,
+                    deadline: other_deadline,
+                },
+            ) => elapsed == other_elapsed && limit == other_limit && deadline == other_deadline,
+            (
+                Error::HttpStatus {
+                    status,
+                    path,
+                    body_excerpt,
+                },
+                Error::HttpStatus {
+                    status: other_status,
+                    path: other_path,
+                    body_excerpt: other_excerpt,
+                },
+            ) => status == other_status && path == other_path && body_excerpt == other_excerpt,
+            (Error::IoError(a), Error::IoError(b)) => a.kind() == b.kind(),
+            (Error::UnknownAvailabilityZone(a), Error::UnknownAvailabilityZone(b)) => a == b,
+            (Error::UnknownPartition(a), Error::UnknownPartition(b)) => a == b,
+            (Error::JsonError(a), Error::JsonError(b)) => a == b,
+            (Error::NotFound(a), Error::NotFound(b)) => a == b,
+            (Error::NotReady(a), Error::NotReady(b)) => a == b,
+            (Error::ResponseTooLarge(a), Error::ResponseTooLarge(b)) => a == b,
+            (Error::CredentialsUnavailable(a), Error::CredentialsUnavailable(b)) => a == b,
+            (Error::CircuitOpen, Error::CircuitOpen) => true,
+            (Error::Cancelled, Error::Cancelled) => true,
+            (Error::UnexpectedRedirect(a), Error::UnexpectedRedirect(b)) => a == b,
+            (Error::UnexpectedResponse(a), Error::UnexpectedResponse(b)) => a == b,
+            (Error::InvalidPath(a), Error::InvalidPath(b)) => a == b,
+            (Error::InvalidRegionName(a), Error::InvalidRegionName(b)) => a == b,
+            (Error::Token(a), Error::Token(b)) => a == b,
+            (Error::MissingField(a), Error::MissingField(b)) => a == b,
+            (Error::EmptyResponse(a), Error::EmptyResponse(b)) => a == b,
+            (
+                Error::InvalidUtf8 {
+                    path,
+                    lossy_preview,
+                },
+                Error::InvalidUtf8 {
+                    path: other_path,
+                    lossy_preview: other_preview,
+                },
+            ) => path == other_path && lossy_preview == other_preview,
+            (
+                Error::RegionMismatch {
+                    region,
+                    partition,
+                    domain,
+                },
+                Error::RegionMismatch {
+                    region: other_region,
+                    partition: other_partition,
+                    domain: other_domain,
+                },
+            ) => region == other_region && partition == other_partition && domain == other_domain,
+            (
+                Error::Fetch { field, source },
+                Error::Fetch {
+                    field: other_field,
+                    source: other_source,
+                },
+            ) => field == other_field && source == other_source,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Error {}
+
+impl From<ureq::Error> for Error {
+    fn from(error: ureq::Error) -> Error {
+        match error {
+            ureq::Error::Status(_, resp) => {
+                let path = url_path(resp.get_url()).to_string();
+                Error::from_status(resp, &path)
+            }
+            ureq::Error::Transport(transport) => Error::HttpRequest(Arc::new(transport)),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Error {
+        Error::IoError(Arc::new(error))
+    }
+}
+
+impl From<json::Error> for Error {
+    fn from(error: json::Error) -> Error {
+        Error::JsonError(Arc::new(error))
+    }
+}
+
+fn excerpt_suffix(body_excerpt: &str) -> String {
+    if body_excerpt.is_empty() {
+        String::new()
+    } else {
+        format!(": {:?}", body_excerpt)
+    }
+}
+
+/// The path component of an absolute URL, e.g. `/latest/api/token`.
+fn url_path(url: &str) -> &str {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    without_scheme
+        .find('/')
+        .map_or("/", |index| &without_scheme[index..])
+}
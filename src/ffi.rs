@@ -0,0 +1,259 @@
+use crate::{InstanceMetadata, InstanceMetadataClient};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::sync::OnceLock;
+
+thread_local! {
+    // The error of the last `ec2md_get` or `ec2md_get_with_endpoint` on this
+    // thread, if it failed.
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// The client `ec2md_get` shares between calls, so its token is reused.
+static CLIENT: OnceLock<InstanceMetadataClient> = OnceLock::new();
+
+/// The instance metadata, as returned by `ec2md_get`.
+///
+/// The handle owns the strings its accessors return: they stay valid, and
+/// unchanged, until the handle is passed to `ec2md_free`. Copy any string
+/// that has to outlive the handle.
+pub struct Ec2mdMetadata {
+    region: Option<CString>,
+    availability_zone: CString,
+    instance_id: CString,
+    account_id: Option<CString>,
+    ami_id: CString,
+    instance_type: CString,
+    local_hostname: CString,
+    hostname: CString,
+    public_hostname: Option<CString>,
+}
+
+impl Ec2mdMetadata {
+    fn new(metadata: InstanceMetadata) -> Result<Ec2mdMetadata, String> {
+        let string = |name: &str, value: String| {
+            CString::new(value).map_err(|_| format!("{} contains a NUL byte", name))
+        };
+        let optional =
+            |name: &str, value: Option<String>| value.map(|v| string(name, v)).transpose();
+
+        Ok(Ec2mdMetadata {
+            region: optional("region", metadata.region)?,
+            availability_zone: string("availability-zone", metadata.availability_zone)?,
+            instance_id: string("instance-id", metadata.instance_id)?,
+            account_id: optional("account-id", metadata.account_id)?,
+            ami_id: string("ami-id", metadata.ami_id)?,
+            instance_type: string("instance-type", metadata.instance_type)?,
+            local_hostname: string("local-hostname", metadata.local_hostname)?,
+            hostname: string("hostname", metadata.hostname)?,
+            public_hostname: optional("public-hostname", metadata.public_hostname)?,
+        })
+    }
+}
+
+/// Get the instance metadata from IMDS.
+///
+/// Returns a handle to free with `ec2md_free`, or NULL on failure, in which
+/// case `ec2md_last_error` says why. Calls share one client, so the IMDSv2
+/// token is reused between them. Safe to call from any thread.
+#[no_mangle]
+pub extern "C" fn ec2md_get() -> *mut Ec2mdMetadata {
+    get(|| Ok(CLIENT.get_or_init(InstanceMetadataClient::new).clone()))
+}
+
+/// Get the instance metadata from the IMDS at `endpoint`, a NUL-terminated
+/// URL such as `http://127.0.0.1:1338`, e.g. a local mock. A NULL `endpoint`
+/// means the default one, like `ec2md_get`.
+///
+/// Returns a handle to free with `ec2md_free`, or NULL on failure, in which
+/// case `ec2md_last_error` says why. Each call uses a new client. Safe to
+/// call from any thread.
+///
+/// # Safety
+/// `endpoint` must be NULL or point to a NUL-terminated string, which is
+/// only read during the call.
+#[no_mangle]
+pub unsafe extern "C" fn ec2md_get_with_endpoint(endpoint: *const c_char) -> *mut Ec2mdMetadata {
+    if endpoint.is_null() {
+        return ec2md_get();
+    }
+    let endpoint = CStr::from_ptr(endpoint).to_str().map(str::to_string);
+    get(move || {
+        let endpoint = endpoint.map_err(|_| "the endpoint isn't UTF-8".to_string())?;
+        Ok(InstanceMetadataClient::builder().endpoint(endpoint).build())
+    })
+}
+
+/// Fetch the metadata with the client `client` makes, turning errors and
+/// panics into NULL and the last error.
+fn get<F>(client: F) -> *mut Ec2mdMetadata
+where
+    F: FnOnce() -> Result<InstanceMetadataClient, String>,
+{
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let metadata = client()?.get().map_err(|e| e.full_message())?;
+        Ec2mdMetadata::new(metadata)
+    }));
+    let result = result.unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(format!("panicked: {}", message))
+    });
+
+    match result {
+        Ok(metadata) => {
+            set_last_error(None);
+            Box::into_raw(Box::new(metadata))
+        }
+        Err(e) => {
+            set_last_error(Some(e));
+            ptr::null_mut()
+        }
+    }
+}
+
+fn set_last_error(error: Option<String>) {
+    let error = error.map(|e| CString::new(e.replace('\0', " ")).unwrap_or_default());
+    LAST_ERROR.with(|last| *last.borrow_mut() = error);
+}
+
+/// Why the last `ec2md_get` or `ec2md_get_with_endpoint` on this thread
+/// failed, as a NUL-terminated UTF-8 message, or NULL if it succeeded or
+/// none was made.
+///
+/// The string is owned by the library: don't free it. It stays valid until
+/// the next `ec2md_get` or `ec2md_get_with_endpoint` on the same thread.
+#[no_mangle]
+pub extern "C" fn ec2md_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Free a handle from `ec2md_get`, and with it the strings its accessors
+/// returned. NULL is ignored.
+///
+/// # Safety
+/// `metadata` must be NULL or a handle from `ec2md_get` or
+/// `ec2md_get_with_endpoint` that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn ec2md_free(metadata: *mut Ec2mdMetadata) {
+    if !metadata.is_null() {
+        drop(Box::from_raw(metadata));
+    }
+}
+
+/// The instance's region, or NULL if `metadata` is NULL or the region is
+/// unknown. Owned by `metadata`.
+///
+/// # Safety
+/// `metadata` must be NULL or a live handle from `ec2md_get`.
+#[no_mangle]
+pub unsafe extern "C" fn ec2md_region(metadata: *const Ec2mdMetadata) -> *const c_char {
+    optional(metadata, |m| &m.region)
+}
+
+/// The instance's availability zone, or NULL if `metadata` is NULL. Owned
+/// by `metadata`.
+///
+/// # Safety
+/// `metadata` must be NULL or a live handle from `ec2md_get`.
+#[no_mangle]
+pub unsafe extern "C" fn ec2md_availability_zone(metadata: *const Ec2mdMetadata) -> *const c_char {
+    required(metadata, |m| &m.availability_zone)
+}
+
+/// The instance's id, or NULL if `metadata` is NULL. Owned by `metadata`.
+///
+/// # Safety
+/// `metadata` must be NULL or a live handle from `ec2md_get`.
+#[no_mangle]
+pub unsafe extern "C" fn ec2md_instance_id(metadata: *const Ec2mdMetadata) -> *const c_char {
+    required(metadata, |m| &m.instance_id)
+}
+
+/// The instance's account id, or NULL if `metadata` is NULL or IMDS didn't
+/// report it. Owned by `metadata`.
+///
+/// # Safety
+/// `metadata` must be NULL or a live handle from `ec2md_get`.
+#[no_mangle]
+pub unsafe extern "C" fn ec2md_account_id(metadata: *const Ec2mdMetadata) -> *const c_char {
+    optional(metadata, |m| &m.account_id)
+}
+
+/// The instance's AMI id, or NULL if `metadata` is NULL. Owned by
+/// `metadata`.
+///
+/// # Safety
+/// `metadata` must be NULL or a live handle from `ec2md_get`.
+#[no_mangle]
+pub unsafe extern "C" fn ec2md_ami_id(metadata: *const Ec2mdMetadata) -> *const c_char {
+    required(metadata, |m| &m.ami_id)
+}
+
+/// The instance's type, or NULL if `metadata` is NULL. Owned by `metadata`.
+///
+/// # Safety
+/// `metadata` must be NULL or a live handle from `ec2md_get`.
+#[no_mangle]
+pub unsafe extern "C" fn ec2md_instance_type(metadata: *const Ec2mdMetadata) -> *const c_char {
+    required(metadata, |m| &m.instance_type)
+}
+
+/// The instance's private hostname, or NULL if `metadata` is NULL. Owned by
+/// `metadata`.
+///
+/// # Safety
+/// `metadata` must be NULL or a live handle from `ec2md_get`.
+#[no_mangle]
+pub unsafe extern "C" fn ec2md_local_hostname(metadata: *const Ec2mdMetadata) -> *const c_char {
+    required(metadata, |m| &m.local_hostname)
+}
+
+/// The instance's hostname, or NULL if `metadata` is NULL. Owned by
+/// `metadata`.
+///
+/// # Safety
+/// `metadata` must be NULL or a live handle from `ec2md_get`.
+#[no_mangle]
+pub unsafe extern "C" fn ec2md_hostname(metadata: *const Ec2mdMetadata) -> *const c_char {
+    required(metadata, |m| &m.hostname)
+}
+
+/// The instance's public hostname, or NULL if `metadata` is NULL or the
+/// instance has none. Owned by `metadata`.
+///
+/// # Safety
+/// `metadata` must be NULL or a live handle from `ec2md_get`.
+#[no_mangle]
+pub unsafe extern "C" fn ec2md_public_hostname(metadata: *const Ec2mdMetadata) -> *const c_char {
+    optional(metadata, |m| &m.public_hostname)
+}
+
+unsafe fn required<F>(metadata: *const Ec2mdMetadata, field: F) -> *const c_char
+where
+    F: FnOnce(&Ec2mdMetadata) -> &CString,
+{
+    match metadata.as_ref() {
+        Some(metadata) => field(metadata).as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+unsafe fn optional<F>(metadata: *const Ec2mdMetadata, field: F) -> *const c_char
+where
+    F: FnOnce(&Ec2mdMetadata) -> &Option<CString>,
+{
+    match metadata
+        .as_ref()
+        .and_then(|metadata| field(metadata).as_ref())
+    {
+        Some(value) => value.as_ptr(),
+        None => ptr::null(),
+    }
+}
@@ -0,0 +1,85 @@
+/// `Field` names one of the values in `InstanceMetadata`. Errors from `get`
+/// report the field that was being fetched when they occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    Region,
+    AvailabilityZone,
+    InstanceId,
+    AccountId,
+    AmiId,
+    InstanceType,
+    LocalHostname,
+    Hostname,
+    PublicHostname,
+}
+
+impl Field {
+    /// Every field, in the order `InstanceMetadataClient::get` fetches them.
+    pub const ALL: &'static [Field] = &[
+        Field::InstanceId,
+        Field::AccountId,
+        Field::AmiId,
+        Field::AvailabilityZone,
+        Field::Region,
+        Field::InstanceType,
+        Field::Hostname,
+        Field::LocalHostname,
+        Field::PublicHostname,
+    ];
+
+    /// The field named `name`, as returned by `name()`.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::Field;
+    ///
+    /// assert_eq!(Field::from_name("instance-id"), Some(Field::InstanceId));
+    /// assert_eq!(Field::from_name("instance_id"), None);
+    /// for field in Field::ALL {
+    ///     assert_eq!(Field::from_name(field.name()), Some(*field));
+    /// }
+    /// ```
+    pub fn from_name(name: &str) -> Option<Field> {
+        Field::ALL
+            .iter()
+            .copied()
+            .find(|field| field.name() == name)
+    }
+
+    /// The field's name, matching the IMDS path it's read from, e.g.
+    /// `"instance-id"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Region => "region",
+            Field::AvailabilityZone => "availability-zone",
+            Field::InstanceId => "instance-id",
+            Field::AccountId => "account-id",
+            Field::AmiId => "ami-id",
+            Field::InstanceType => "instance-type",
+            Field::LocalHostname => "local-hostname",
+            Field::Hostname => "hostname",
+            Field::PublicHostname => "public-hostname",
+        }
+    }
+
+    /// The path, relative to the API version, the field is read from.
+    pub(crate) fn path(self) -> &'static str {
+        match self {
+            Field::Region => "meta-data/placement/region",
+            Field::AvailabilityZone => "meta-data/placement/availability-zone",
+            Field::InstanceId => "meta-data/instance-id",
+            Field::AccountId => "meta-data/identity-credentials/ec2/info",
+            Field::AmiId => "meta-data/ami-id",
+            Field::InstanceType => "meta-data/instance-type",
+            Field::LocalHostname => "meta-data/local-hostname",
+            Field::Hostname => "meta-data/hostname",
+            Field::PublicHostname => "meta-data/public-hostname",
+        }
+    }
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -0,0 +1,99 @@
+/// `InstanceTypeInfo` is an instance type split into its family and size,
+/// with the attributes its family name encodes.
+///
+/// Family names are a series (`m`, `c`, `mac`, ...), a generation number, and
+/// optional attribute letters, e.g. `c7gn` or `m5dn`, sometimes followed by a
+/// `-` suffix like `-flex`. Attribute flags are only set for families of that
+/// shape; anything else, such as `u-6tb1`, is still split into family and size.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::InstanceTypeInfo;
+///
+/// let cases = [
+///     // (instance type, family, size, graviton, local NVMe, network optimized)
+///     ("m5.large", "m5", "large", false, false, false),
+///     ("c7g.24xlarge", "c7g", "24xlarge", true, false, false),
+///     ("r6gd.xlarge", "r6gd", "xlarge", true, true, false),
+///     ("m5dn.2xlarge", "m5dn", "2xlarge", false, true, true),
+///     ("c6gn.16xlarge", "c6gn", "16xlarge", true, false, true),
+///     ("x2iedn.32xlarge", "x2iedn", "32xlarge", false, true, true),
+///     ("m7i-flex.large", "m7i-flex", "large", false, false, false),
+///     ("g5g.metal", "g5g", "metal", true, false, false),
+///     ("mac2.metal", "mac2", "metal", false, false, false),
+///     ("u-6tb1.metal", "u-6tb1", "metal", false, false, false),
+///     ("t2.nano", "t2", "nano", false, false, false),
+///     ("unknown", "unknown", "", false, false, false),
+/// ];
+///
+/// for &(instance_type, family, size, graviton, local_nvme, network_optimized) in &cases {
+///     let info = InstanceTypeInfo::parse(instance_type);
+///     assert_eq!(info.family, family, "{}", instance_type);
+///     assert_eq!(info.size, size, "{}", instance_type);
+///     assert_eq!(info.graviton, graviton, "{}", instance_type);
+///     assert_eq!(info.local_nvme, local_nvme, "{}", instance_type);
+///     assert_eq!(info.network_optimized, network_optimized, "{}", instance_type);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct InstanceTypeInfo {
+    /// The family, e.g. `"m5"` or `"c7g"`
+    pub family: String,
+
+    /// The size, e.g. `"xlarge"` or `"metal"` - empty if the instance type
+    /// has none
+    pub size: String,
+
+    /// The family has a Graviton (`g`) processor
+    pub graviton: bool,
+
+    /// The family has local NVMe (`d`) instance storage
+    pub local_nvme: bool,
+
+    /// The family is network optimized (`n`)
+    pub network_optimized: bool,
+}
+
+impl InstanceTypeInfo {
+    /// Parse an instance type such as `"m5.large"`. This never fails; see the
+    /// type's docs for how unusual names are handled.
+    pub fn parse(instance_type: &str) -> InstanceTypeInfo {
+        let (family, size) = match instance_type.split_once('.') {
+            Some((family, size)) => (family, size),
+            None => (instance_type, ""),
+        };
+
+        let attributes = family_attributes(family).unwrap_or("");
+
+        InstanceTypeInfo {
+            family: family.to_string(),
+            size: size.to_string(),
+            graviton: attributes.contains('g'),
+            local_nvme: attributes.contains('d'),
+            network_optimized: attributes.contains('n'),
+        }
+    }
+}
+
+/// The attribute letters after the generation number in `family`, or `None`
+/// if it isn't shaped like series letters, a generation, and attributes.
+fn family_attributes(family: &str) -> Option<&str> {
+    let family = family.split('-').next().unwrap_or(family);
+
+    let series_len = family.find(|c: char| !c.is_ascii_lowercase())?;
+    let rest = &family[series_len..];
+    let generation_len = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    if series_len == 0 || generation_len == 0 {
+        return None;
+    }
+
+    let attributes = &rest[generation_len..];
+    if attributes.chars().all(|c| c.is_ascii_lowercase()) {
+        Some(attributes)
+    } else {
+        None
+    }
+}
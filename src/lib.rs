@@ -1,251 +1,594 @@
 extern crate json;
+extern crate thiserror;
 extern crate ureq;
 
-#[derive(Clone, Copy)]
-enum MetadataUrls {
-    InstanceId,
-    AmiId,
-    AccountId,
-    AvailabilityZone,
-    InstanceType,
-    Hostname,
-    LocalHostname,
-    PublicHostname,
-}
+mod bind;
+mod cancel;
+mod circuit_breaker;
+mod coalesce;
+mod credentials;
+mod diff;
+mod ecs;
+mod environment;
+mod error;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod field;
+mod instance_type;
+mod meter;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "test-util")]
+mod mock;
+mod otel;
+mod raw;
+mod redact;
+mod region;
+mod retry;
+mod rfc3339;
+#[cfg(any(
+    feature = "aws-config",
+    feature = "aws-credential-types",
+    feature = "rusoto"
+))]
+mod sdk;
+mod spot;
+mod token;
+mod trace;
+mod zone;
 
-impl Into<&'static str> for MetadataUrls {
-    fn into(self) -> &'static str {
-        match self {
-            MetadataUrls::InstanceId => "http://169.254.169.254/latest/meta-data/instance-id",
-            MetadataUrls::AmiId => "http://169.254.169.254/latest/meta-data/ami-id",
-            MetadataUrls::AccountId => {
-                "http://169.254.169.254/latest/meta-data/identity-credentials/ec2/info"
-            }
-            MetadataUrls::AvailabilityZone => {
-                "http://169.254.169.254/latest/meta-data/placement/availability-zone"
-            }
-            MetadataUrls::InstanceType => "http://169.254.169.254/latest/meta-data/instance-type",
-            MetadataUrls::Hostname => "http://169.254.169.254/latest/meta-data/hostname",
-            MetadataUrls::LocalHostname => "http://169.254.169.254/latest/meta-data/local-hostname",
-            MetadataUrls::PublicHostname => {
-                "http://169.254.169.254/latest/meta-data/public-hostname"
-            }
-        }
+pub use cancel::CancellationToken;
+use circuit_breaker::CircuitBreaker;
+use coalesce::{lock, Coalescer};
+pub use credentials::{Credentials, CredentialsRefresher, RefreshEvent};
+pub use diff::FieldChange;
+pub use ecs::{ContainerMetadata, EcsMetadataClient, Limits, TaskMetadata};
+pub use environment::{detect_environment, detect_environment_with, Environment, Evidence};
+pub use error::{Deadline, Error, ErrorKind};
+pub use field::Field;
+pub use instance_type::InstanceTypeInfo;
+#[cfg(feature = "test-util")]
+pub use mock::{MockImds, MockRequest};
+#[cfg(feature = "opentelemetry")]
+pub use otel::Ec2MetadataResourceDetector;
+pub use otel::ResourceAttribute;
+pub use raw::WithExtras;
+pub use redact::{RedactedInstanceMetadata, RedactionOptions};
+pub use region::{Partition, Region};
+use retry::SharedRetryPolicy;
+pub use retry::{ExponentialBackoff, FailureKind, NoRetry, RetryDecision, RetryPolicy};
+#[cfg(feature = "aws-credential-types")]
+pub use sdk::Ec2MetadataCredentialsProvider;
+#[cfg(feature = "aws-config")]
+pub use sdk::Ec2MetadataRegionProvider;
+#[cfg(feature = "rusoto")]
+pub use sdk::Ec2MetadataRusotoProvider;
+pub use spot::{InterruptionNotice, RebalanceRecommendation};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+pub use token::{Token, TokenPersistence};
+pub use zone::{local_zone, parse_availability_zone, zone_kind, zone_letter, ZoneInfo, ZoneKind};
+
+/// Read the account id from a JSON document, under `key`: `"AccountId"` in
+/// the identity credentials info, `"accountId"` in the instance identity
+/// document.
+fn parse_account_id(document: &str, key: &'static str) -> Result<String> {
+    let parsed = json::parse(document)?;
+    match parsed[key].as_str() {
+        Some(account_id) if !account_id.is_empty() => Ok(account_id.to_string()),
+        _ => Err(Error::MissingField(key)),
     }
 }
 
-fn identity_credentials_to_account_id(ident_creds: &str) -> Result<String> {
-    let parsed = json::parse(ident_creds)?;
-    Ok(parsed["AccountId"].to_string())
+/// Whether `region` is one of the regions this crate knows about, built in
+/// or added with `register_regions`. Regions launched after the crate was
+/// released aren't, though `get()` still reports them.
+///
+/// # Examples:
+/// ```
+/// assert!(ec2_instance_metadata::is_known_region("eu-west-1"));
+/// assert!(!ec2_instance_metadata::is_known_region("eu-west-1a"));
+/// ```
+pub fn is_known_region(region: &str) -> bool {
+    Region::KNOWN.iter().any(|known| known.as_str() == region) || region::is_registered(region)
 }
 
-fn availability_zone_to_region(availability_zone: &str) -> Result<&'static str> {
-    const REGIONS: &[&str] = &[
-        "ap-south-1",
-        "eu-west-3",
-        "eu-north-1",
-        "eu-west-2",
-        "eu-west-1",
-        "ap-northeast-3",
-        "ap-northeast-2",
-        "ap-northeast-1",
-        "sa-east-1",
-        "ca-central-1",
-        "ap-southeast-1",
-        "ap-southeast-2",
-        "eu-central-1",
-        "us-east-1",
-        "us-east-2",
-        "us-west-1",
-        "us-west-2",
-        "cn-north-1",
-        "cn-northwest-1",
-    ];
-
-    for region in REGIONS {
-        if availability_zone.starts_with(region) {
-            return Ok(region);
-        }
+/// Add regions to the ones this crate knows about, for regions that aren't
+/// in its built-in list yet. Registered regions are honored by
+/// `is_known_region` and availability zone parsing process-wide, from any
+/// thread, once this returns. They parse as `Region::Other`, with the
+/// partition guessed from their name.
+///
+/// Registrations are never removed. The built-in list takes precedence, so
+/// registering a region it already has, or one already registered, does
+/// nothing. Every name is checked before any is registered; a name that
+/// isn't shaped like a region, e.g. `"us-east"`, fails with
+/// `Error::InvalidRegionName` and nothing is registered.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::{is_known_region, register_regions, Error, Region};
+///
+/// assert!(!is_known_region("xx-example-1"));
+/// assert_eq!(Region::from_availability_zone("xx-example-1-abc"), None);
+///
+/// std::thread::spawn(|| register_regions(&["xx-example-1", "us-east-1"]))
+///     .join()
+///     .unwrap()
+///     .unwrap();
+///
+/// assert!(is_known_region("xx-example-1"));
+/// assert_eq!(
+///     Region::from_availability_zone("xx-example-1-abc"),
+///     Some(Region::Other("xx-example-1".to_string()))
+/// );
+/// // Built-in regions keep their variants.
+/// assert_eq!(Region::from_availability_zone("us-east-1a"), Some(Region::UsEast1));
+///
+/// assert_eq!(
+///     register_regions(&["xx-example-2", "us-east"]),
+///     Err(Error::InvalidRegionName("us-east".to_string()))
+/// );
+/// assert!(!is_known_region("xx-example-2"));
+/// ```
+pub fn register_regions(regions: &[&str]) -> Result<()> {
+    if let Some(invalid) = regions.iter().find(|region| !zone::is_region_name(region)) {
+        return Err(Error::InvalidRegionName(invalid.to_string()));
     }
+    region::register(regions);
+    Ok(())
+}
 
-    Err(Error::UnknownAvailabilityZone(
-        availability_zone.to_string(),
-    ))
+/// The partition `region` is in, e.g. `Partition::AwsCn` for `cn-north-1`.
+/// Regions this crate doesn't know about are placed by their name's prefix,
+/// and are `Partition::Unknown` if there's no telling; see
+/// `Region::partition`.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::{partition_for_region, Partition, Region};
+///
+/// let regions = [
+///     ("ap-south-1", Partition::Aws),
+///     ("eu-west-3", Partition::Aws),
+///     ("eu-north-1", Partition::Aws),
+///     ("eu-west-2", Partition::Aws),
+///     ("eu-west-1", Partition::Aws),
+///     ("ap-northeast-3", Partition::Aws),
+///     ("ap-northeast-2", Partition::Aws),
+///     ("ap-northeast-1", Partition::Aws),
+///     ("sa-east-1", Partition::Aws),
+///     ("ca-central-1", Partition::Aws),
+///     ("ap-southeast-1", Partition::Aws),
+///     ("ap-southeast-2", Partition::Aws),
+///     ("eu-central-1", Partition::Aws),
+///     ("us-east-1", Partition::Aws),
+///     ("us-east-2", Partition::Aws),
+///     ("us-west-1", Partition::Aws),
+///     ("us-west-2", Partition::Aws),
+///     ("cn-north-1", Partition::AwsCn),
+///     ("cn-northwest-1", Partition::AwsCn),
+///     ("af-south-1", Partition::Aws),
+///     ("ap-east-1", Partition::Aws),
+///     ("ap-east-2", Partition::Aws),
+///     ("ap-south-2", Partition::Aws),
+///     ("ap-southeast-3", Partition::Aws),
+///     ("ap-southeast-4", Partition::Aws),
+///     ("ap-southeast-5", Partition::Aws),
+///     ("ap-southeast-7", Partition::Aws),
+///     ("ca-west-1", Partition::Aws),
+///     ("eu-central-2", Partition::Aws),
+///     ("eu-south-1", Partition::Aws),
+///     ("eu-south-2", Partition::Aws),
+///     ("il-central-1", Partition::Aws),
+///     ("me-central-1", Partition::Aws),
+///     ("me-south-1", Partition::Aws),
+///     ("mx-central-1", Partition::Aws),
+///     ("us-gov-west-1", Partition::AwsUsGov),
+///     ("us-gov-east-1", Partition::AwsUsGov),
+///     ("us-iso-east-1", Partition::AwsIso),
+///     ("us-iso-west-1", Partition::AwsIso),
+///     ("us-isob-east-1", Partition::AwsIsoB),
+/// ];
+/// for &(region, partition) in &regions {
+///     assert_eq!(partition_for_region(region), partition, "{}", region);
+/// }
+///
+/// // Every known region is covered above.
+/// assert_eq!(regions.len(), Region::KNOWN.len());
+///
+/// assert_eq!(partition_for_region("cn-south-9"), Partition::AwsCn);
+/// assert_eq!(partition_for_region("us-gov-central-1"), Partition::AwsUsGov);
+/// assert_eq!(partition_for_region("us-iso-south-1"), Partition::AwsIso);
+/// assert_eq!(partition_for_region("us-isob-west-1"), Partition::AwsIsoB);
+/// assert_eq!(partition_for_region("xx-example-1"), Partition::Unknown);
+/// assert_eq!(partition_for_region(""), Partition::Unknown);
+/// ```
+pub fn partition_for_region(region: &str) -> Partition {
+    match region.parse::<Region>() {
+        Ok(region) => region.partition(),
+        Err(never) => match never {},
+    }
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Clone, Debug)]
-pub enum Error {
-    HttpRequest(String),
-    IoError(String),
-    UnknownAvailabilityZone(String),
-    JsonError(String),
-    NotFound(&'static str), // Reported for static URIs we fetch.
+const INSTANCE_IDENTITY_DOCUMENT_PATH: &str = "dynamic/instance-identity/document";
+const SERVICES_PARTITION_PATH: &str = "meta-data/services/partition";
+const SERVICES_DOMAIN_PATH: &str = "meta-data/services/domain";
+
+/// `InstanceMetadataClient` provides an API for fetching common fields
+/// from the EC2 Instance Metadata API: https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/ec2-instance-metadata.html
+///
+/// Clones of a client share its token cache and in-flight requests, so a single
+/// client can be handed to many threads without multiplying IMDS traffic.
+///
+/// # Examples:
+/// ```no_run
+/// use ec2_instance_metadata::InstanceMetadataClient;
+/// let client = ec2_instance_metadata::InstanceMetadataClient::new();
+/// let instance_metadata = client.get().expect("Couldn't get the instance metadata.");
+/// ```
+#[derive(Debug, Clone)]
+pub struct InstanceMetadataClient {
+    agent: ureq::Agent,
+    bind: Option<Arc<bind::BindToLocalAddress>>,
+    endpoint: String,
+    api_version: String,
+    max_body_size: u64,
+    max_user_data_size: u64,
+    retry_policy: SharedRetryPolicy,
+    request_timeout: Duration,
+    overall_timeout: Option<Duration>,
+    cancellation_token: Option<CancellationToken>,
+    verify_region: bool,
+    imds_v1: ImdsV1,
+    state: Arc<ClientState>,
 }
 
-impl From<ureq::Error> for Error {
-    fn from(error: ureq::Error) -> Error {
-        Error::HttpRequest(format!("{:?}", error))
-    }
+#[derive(Debug)]
+struct ClientState {
+    token: Mutex<Option<Token>>,
+    token_requests: Coalescer<(), Result<Token>>,
+    requests: Coalescer<String, Result<Option<Vec<u8>>>>,
+    gets: Coalescer<(), Result<InstanceMetadata>>,
+    circuit_breaker: Option<CircuitBreaker>,
+    token_persistence: TokenPersistence,
+    metadata: Mutex<Option<InstanceMetadata>>,
+    region: Mutex<Option<String>>,
+    // Bumped each time a change of instance discards the cached state, so
+    // credentials cached outside the client can tell they're stale.
+    identity_generation: AtomicU64,
+    // Set once `ImdsV1::Fallback` has given up on getting a token.
+    fell_back_to_v1: Mutex<bool>,
 }
 
-impl From<std::io::Error> for Error {
-    fn from(error: std::io::Error) -> Error {
-        Error::IoError(format!("{:?}", error))
+impl ClientState {
+    fn new(circuit_breaker: Option<CircuitBreaker>, token_persistence: TokenPersistence) -> Self {
+        Self {
+            circuit_breaker,
+            token_persistence,
+            token: Mutex::new(None),
+            token_requests: Coalescer::new(),
+            requests: Coalescer::new(),
+            gets: Coalescer::new(),
+            metadata: Mutex::new(None),
+            region: Mutex::new(None),
+            identity_generation: AtomicU64::new(0),
+            fell_back_to_v1: Mutex::new(false),
+        }
     }
 }
 
-impl From<json::Error> for Error {
-    fn from(error: json::Error) -> Error {
-        Error::JsonError(format!("{:?}", error))
+impl Default for InstanceMetadataClient {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            Error::HttpRequest(s) => write!(f, "Http Request Error: {}", s),
-            Error::IoError(s) => write!(f, "IO Error: {}", s),
-            Error::UnknownAvailabilityZone(s) => write!(f, "Unknown AvailabilityZone: {}", s),
-            Error::JsonError(s) => write!(f, "JSON parsing error: {}", s),
-            Error::NotFound(s) => write!(f, "Not found: {}", s),
-        }
+impl InstanceMetadataClient {
+    // The token API is only served under "latest", whatever version the
+    // client is pinned to.
+    const TOKEN_API_PATH: &'static str = "/latest/api/token";
+    const TOKEN_TTL_SECONDS: u64 = 21600; // 6 hours
+    const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+    pub fn new() -> Self {
+        InstanceMetadataClientBuilder::new().build()
     }
-}
 
-impl std::error::Error for Error {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
+    /// Create a builder for configuring a client.
+    pub fn builder() -> InstanceMetadataClientBuilder {
+        InstanceMetadataClientBuilder::new()
     }
-}
 
-/// `InstanceMetadataClient` provides an API for fetching common fields
-/// from the EC2 Instance Metadata API: https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/ec2-instance-metadata.html
-///
-/// # Examples:
-/// ```
-/// use ec2_instance_metadata::InstanceMetadataClient;
-/// let client = ec2_instance_metadata::InstanceMetadataClient::new();
-/// let instance_metadata = client.get().expect("Couldn't get the instance metadata.");
-/// ````
-#[derive(Debug, Default)]
-pub struct InstanceMetadataClient;
+    /// Get an IMDSv2 session token.
+    ///
+    /// Failures to obtain one are reported as `Error::Token`, wrapping the
+    /// status or transport error from the token request. They usually mean
+    /// IMDSv2 is disabled, the PUT is blocked, or the response hop limit is
+    /// too low for this process (e.g. inside a container).
+    ///
+    /// The client caches its token and only requests a new one from IMDS when
+    /// the cached token is close to expiring, so this is cheap to call. The
+    /// returned token can be shared with other clients via `with_token`.
+    pub fn fetch_token(&self) -> Result<Token> {
+        if let Some(cached) = lock(&self.state.token).as_ref() {
+            if !cached.expires_within(Self::TOKEN_REFRESH_MARGIN) {
+                meter::token_cache_hit();
+                return Ok(cached.clone());
+            }
+        }
+        meter::token_cache_miss();
 
-impl InstanceMetadataClient {
-    const REQUEST_TIMEOUT_MS: u64 = 2000; // 2 seconds
+        self.state.token_requests.run((), || {
+            if let Some(persisted) = self
+                .state
+                .token_persistence
+                .load(Self::TOKEN_REFRESH_MARGIN)
+            {
+                *lock(&self.state.token) = Some(persisted.clone());
+                return Ok(persisted);
+            }
 
-    pub fn new() -> Self {
-        Self {}
+            let token = trace::in_span(trace::token_span(), || {
+                meter::measure(Self::TOKEN_API_PATH, || self.request_token())
+            });
+            let token = token.map_err(|e| match e {
+                Error::Cancelled | Error::CircuitOpen | Error::NotEc2(_) => e,
+                e => Error::Token(Box::new(e)),
+            })?;
+            meter::token_refreshed();
+            *lock(&self.state.token) = Some(token.clone());
+            // Persistence is only an optimization; failing to save the
+            // token shouldn't fail the request that needed it.
+            if let Err(e) = self.state.token_persistence.store(&token) {
+                log::warn!("couldn't persist the IMDS token: {}", e);
+            }
+
+            Ok(token)
+        })
     }
 
-    fn get_token(&self) -> Result<String> {
-        const TOKEN_API_URL: &str = "http://169.254.169.254/latest/api/token";
+    fn request_token(&self) -> Result<Token> {
+        let requested_at = SystemTime::now();
+        let resp = self.call(self.agent.put(&self.token_api_url()).set(
+            "X-aws-ec2-metadata-token-ttl-seconds",
+            &Self::TOKEN_TTL_SECONDS.to_string(),
+        ))?;
+        trace::record_status(resp.status());
+        meter::record_status(resp.status());
+
+        if !(200..300).contains(&resp.status()) {
+            return Err(Error::from_status(resp, Self::TOKEN_API_PATH));
+        }
+
+        check_content_type(&resp, Self::TOKEN_API_PATH)?;
+        let value = decode_utf8(
+            self.read_body(resp, self.max_body_size)?,
+            Self::TOKEN_API_PATH,
+        )?;
+
+        // The token is the first thing fetched, so this is where a proxy or
+        // captive portal answering in place of IMDS gets caught. Real tokens
+        // are a single base64 string.
+        if value.is_empty() || !value.chars().all(|c| c.is_ascii_graphic()) {
+            return Err(Error::UnexpectedResponse(
+                "the token response isn't a plausible IMDSv2 token".to_string(),
+            ));
+        }
 
-        let resp = ureq::put(TOKEN_API_URL)
-            .set("X-aws-ec2-metadata-token-ttl-seconds", "21600")
-            .timeout_connect(Self::REQUEST_TIMEOUT_MS)
-            .call();
+        Ok(Token::new(
+            value,
+            requested_at + Duration::from_secs(Self::TOKEN_TTL_SECONDS),
+        ))
+    }
 
-        let token = resp.into_string()?;
-        Ok(token)
+    /// Use an externally supplied token instead of requesting one, e.g. to
+    /// share a single token between several clients. Once it nears expiry the
+    /// client falls back to requesting its own.
+    pub fn with_token(self, token: Token) -> Self {
+        *lock(&self.state.token) = Some(token);
+        self
     }
 
-    /// Get the instance metadata for the machine.
-    pub fn get(&self) -> Result<InstanceMetadata> {
-        let token = self.get_token()?;
-        let instance_id_resp = ureq::get(MetadataUrls::InstanceId.into())
-            .set("X-aws-ec2-metadata-token", &token)
-            .timeout_connect(Self::REQUEST_TIMEOUT_MS)
-            .call();
+    fn token_api_url(&self) -> String {
+        format!("{}{}", self.endpoint, Self::TOKEN_API_PATH)
+    }
 
-        let instance_id = if instance_id_resp.ok() {
-            instance_id_resp.into_string()?
-        } else {
-            return Err(Error::NotFound(MetadataUrls::InstanceId.into()));
+    /// The token to send with metadata requests, or an empty string to send
+    /// none and use IMDSv1, as `ImdsV1` allows.
+    fn get_token(&self) -> Result<String> {
+        let token = match self.imds_v1 {
+            ImdsV1::Disabled => self.fetch_token()?,
+            ImdsV1::Only => return Ok(String::new()),
+            ImdsV1::Fallback if *lock(&self.state.fell_back_to_v1) => return Ok(String::new()),
+            ImdsV1::Fallback => match self.fetch_token() {
+                Ok(token) => token,
+                Err(Error::Cancelled) => return Err(Error::Cancelled),
+                // If IMDS isn't there at all, the first GET will say so.
+                Err(e) => {
+                    log::warn!(
+                        "falling back to IMDSv1, no IMDSv2 token: {}",
+                        e.full_message()
+                    );
+                    *lock(&self.state.fell_back_to_v1) = true;
+                    return Ok(String::new());
+                }
+            },
         };
+        Ok(token.as_str().to_string())
+    }
 
-        let ident_creds_resp = ureq::get(MetadataUrls::AccountId.into())
-            .set("X-aws-ec2-metadata-token", &token)
-            .timeout_connect(Self::REQUEST_TIMEOUT_MS)
-            .call();
+    /// Wait for the Instance Metadata API to start answering requests.
+    ///
+    /// This is meant for very early boot, where IMDS can be briefly unreachable
+    /// before networking settles. The token endpoint is polled with exponential
+    /// backoff until it responds or `max_wait` passes. On success the time spent
+    /// waiting is returned, otherwise `Error::NotReady`.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// use std::time::Duration;
+    /// let client = ec2_instance_metadata::InstanceMetadataClient::new();
+    /// let waited = client.wait_until_ready(Duration::from_secs(30)).expect("IMDS never came up.");
+    /// println!("IMDS ready after {:?}", waited);
+    /// ```
+    pub fn wait_until_ready(&self, max_wait: Duration) -> Result<Duration> {
+        const INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+        const MAX_BACKOFF: Duration = Duration::from_secs(1);
 
-        let account_id = if ident_creds_resp.ok() {
-            let ident_creds = ident_creds_resp.into_string()?;
-            identity_credentials_to_account_id(&ident_creds)?
-        } else {
-            return Err(Error::NotFound(MetadataUrls::AccountId.into()));
-        };
+        let start = Instant::now();
+        let mut backoff = INITIAL_BACKOFF;
 
-        let ami_id_resp = ureq::get(MetadataUrls::AmiId.into())
-            .set("X-aws-ec2-metadata-token", &token)
-            .timeout_connect(Self::REQUEST_TIMEOUT_MS)
-            .call();
+        loop {
+            self.check_cancelled()?;
 
-        let ami_id = if ami_id_resp.ok() {
-            ami_id_resp.into_string()?
-        } else {
-            return Err(Error::NotFound(MetadataUrls::AmiId.into()));
-        };
+            let remaining = max_wait.checked_sub(start.elapsed()).unwrap_or_default();
+            let timeout = remaining.min(self.request_timeout);
+            if timeout.as_millis() == 0 {
+                return Err(Error::NotReady(start.elapsed()));
+            }
 
-        let availability_zone_resp = ureq::get(MetadataUrls::AvailabilityZone.into())
-            .set("X-aws-ec2-metadata-token", &token)
-            .timeout_connect(Self::REQUEST_TIMEOUT_MS)
-            .call();
+            // Without tokens the PUT may be blocked outright, so poll the
+            // listing of API versions instead.
+            let request = match self.imds_v1 {
+                ImdsV1::Only => self.agent.get(&format!("{}/", self.endpoint)),
+                _ => self.agent.put(&self.token_api_url()).set(
+                    "X-aws-ec2-metadata-token-ttl-seconds",
+                    &Self::TOKEN_TTL_SECONDS.to_string(),
+                ),
+            };
+            let resp = self.send_attempt(request, timeout);
 
-        let (availability_zone, region) = if availability_zone_resp.ok() {
-            let availability_zone = availability_zone_resp.into_string()?;
-            let region = availability_zone_to_region(&availability_zone)?;
-            (availability_zone, region)
-        } else {
-            return Err(Error::NotFound(MetadataUrls::AvailabilityZone.into()));
-        };
+            // Any real HTTP answer means IMDS is up, even if it isn't a 200.
+            if !matches!(resp, Err(ureq::Error::Transport(_))) {
+                return Ok(start.elapsed());
+            }
 
-        let instance_type_resp = ureq::get(MetadataUrls::InstanceType.into())
-            .set("X-aws-ec2-metadata-token", &token)
-            .timeout_connect(Self::REQUEST_TIMEOUT_MS)
-            .call();
+            let remaining = max_wait.checked_sub(start.elapsed()).unwrap_or_default();
+            if remaining.as_millis() == 0 {
+                return Err(Error::NotReady(start.elapsed()));
+            }
 
-        let instance_type = if instance_type_resp.ok() {
-            instance_type_resp.into_string()?
-        } else {
-            return Err(Error::NotFound(MetadataUrls::InstanceType.into()));
-        };
+            self.sleep(backoff.min(remaining))?;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Get the instance metadata for the machine.
+    ///
+    /// Concurrent calls on the same client (or its clones) share one set of
+    /// requests to IMDS. The result is kept as the client's snapshot, see
+    /// `cached` and `refresh`.
+    pub fn get(&self) -> Result<InstanceMetadata> {
+        trace::in_span(trace::get_span(), || {
+            self.state.gets.run((), || self.fetch_metadata())
+        })
+    }
 
-        let hostname_resp = ureq::get(MetadataUrls::Hostname.into())
-            .set("X-aws-ec2-metadata-token", &token)
-            .timeout_connect(Self::REQUEST_TIMEOUT_MS)
-            .call();
+    /// The metadata from the most recent successful `get` or `refresh`,
+    /// without contacting IMDS.
+    pub fn cached(&self) -> Option<InstanceMetadata> {
+        lock(&self.state.metadata).clone()
+    }
 
-        let hostname = if hostname_resp.ok() {
-            hostname_resp.into_string()?
-        } else {
-            return Err(Error::NotFound(MetadataUrls::Hostname.into()));
-        };
+    /// Fetch the instance metadata again and compare it with the snapshot from
+    /// the last `get` or `refresh`.
+    ///
+    /// If the instance id changed, e.g. because the process was baked into an
+    /// AMI and is now running on a new instance, all cached state (the
+    /// snapshot, the region and the IMDSv2 token, including a persisted one)
+    /// is discarded, the metadata is fetched again from scratch, and the
+    /// change is reported in the result. Credentials cached by a
+    /// `CredentialsRefresher` or an SDK credentials provider built from the
+    /// client, or a clone of it, are fetched again on their next use.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::MockImds;
+    ///
+    /// let mock = MockImds::start();
+    /// let client = mock.client();
+    /// client.get().unwrap();
+    ///
+    /// // The process moves to a new instance, e.g. baked into an AMI.
+    /// mock.set("meta-data/instance-id", "i-0fedcba9876543210");
+    /// let refreshed = client.refresh().expect("Couldn't refresh the instance metadata.");
+    /// let change = refreshed.identity_change.unwrap();
+    /// assert_eq!(change.previous_instance_id, MockImds::INSTANCE_ID);
+    /// assert_eq!(change.current_instance_id, "i-0fedcba9876543210");
+    /// ```
+    pub fn refresh(&self) -> Result<Refreshed> {
+        let previous = self.cached();
+        let mut metadata = self.get()?;
 
-        let local_hostname_resp = ureq::get(MetadataUrls::LocalHostname.into())
-            .set("X-aws-ec2-metadata-token", &token)
-            .timeout_connect(Self::REQUEST_TIMEOUT_MS)
-            .call();
+        let identity_change = match previous {
+            Some(previous) if previous.instance_id != metadata.instance_id => {
+                // What `get` just fetched was partly served from state cached
+                // on the old instance, like the region.
+                self.invalidate();
+                metadata = self.get()?;
 
-        let local_hostname = if local_hostname_resp.ok() {
-            local_hostname_resp.into_string()?
-        } else {
-            return Err(Error::NotFound(MetadataUrls::LocalHostname.into()));
+                Some(IdentityChange {
+                    previous_instance_id: previous.instance_id,
+                    current_instance_id: metadata.instance_id.clone(),
+                })
+            }
+            _ => None,
         };
 
-        let public_hostname_resp = ureq::get(MetadataUrls::PublicHostname.into())
-            .set("X-aws-ec2-metadata-token", &token)
-            .timeout_connect(Self::REQUEST_TIMEOUT_MS)
-            .call();
+        Ok(Refreshed {
+            metadata,
+            identity_change,
+        })
+    }
+
+    /// Discard the cached metadata snapshot, region and token, and mark
+    /// credentials cached from them as stale.
+    fn invalidate(&self) {
+        lock(&self.state.metadata).take();
+        lock(&self.state.region).take();
+        lock(&self.state.token).take();
+        self.state.token_persistence.clear();
+        self.state
+            .identity_generation
+            .fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// How many times a change of instance has discarded the cached state.
+    fn identity_generation(&self) -> u64 {
+        self.state.identity_generation.load(Ordering::SeqCst)
+    }
+
+    fn fetch_metadata(&self) -> Result<InstanceMetadata> {
+        let token = self.get_token()?;
+
+        let instance_id = self.fetch_required(&token, Field::InstanceId)?;
+
+        let account_id = self.fetch_account_id(&token)?;
+        let ami_id = self.fetch_required(&token, Field::AmiId)?;
+
+        let availability_zone = self.fetch_required(&token, Field::AvailabilityZone)?;
+        // An unrecognised region shouldn't fail the whole call; the zone itself
+        // is still reported.
+        let region = self.resolve_region(&token, || Ok(Some(availability_zone.clone())))?;
+        if let (true, Some(region)) = (self.verify_region, &region) {
+            self.check_region(&token, region)?;
+        }
+
+        let instance_type = self.fetch_required(&token, Field::InstanceType)?;
+        let hostname = self.fetch_required(&token, Field::Hostname)?;
+        let local_hostname = self.fetch_required(&token, Field::LocalHostname)?;
 
         // "public-hostname" isn't always available - the instance must be configured
-        // to support having one assigned.
-        let public_hostname = if public_hostname_resp.ok() {
-            Some(public_hostname_resp.into_string()?)
-        } else {
-            None
-        };
+        // to support having one assigned. IMDS answers 404 in that case; any
+        // other failure is a real error, not a missing hostname.
+        let public_hostname = self.fetch_optional(&token, Field::PublicHostname)?;
 
         let metadata = InstanceMetadata {
             region,
@@ -257,48 +600,2183 @@ impl InstanceMetadataClient {
             hostname,
             local_hostname,
             public_hostname,
+            extras: BTreeMap::new(),
+            imds_version: if token.is_empty() {
+                ImdsVersion::V1
+            } else {
+                ImdsVersion::V2
+            },
+            fetched_at: SystemTime::now(),
         };
 
+        *lock(&self.state.metadata) = Some(metadata.clone());
+
         Ok(metadata)
     }
-}
 
-/// `InstanceMetadata` holds the fetched instance metadata. Fields
-/// on this struct may be incomplete if AWS has updated the fields
-/// or if they haven't been explicitly provided.
-#[derive(Debug, Clone)]
-pub struct InstanceMetadata {
-    /// AWS Region - always available
-    pub region: &'static str,
+    /// Get just `field`, as `get` would report it, without fetching the other
+    /// fields. `None` if the instance has no value for an optional field:
+    /// `AccountId`, `Region`, or `PublicHostname`. A required field that's
+    /// missing is an error, as for `get`.
+    ///
+    /// This doesn't touch the client's snapshot.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::{Field, MockImds};
+    ///
+    /// let mock = MockImds::start();
+    /// let client = mock.client();
+    /// let region = client.get_field(Field::Region).expect("Couldn't get the region.");
+    /// assert_eq!(region.as_deref(), Some(MockImds::REGION));
+    ///
+    /// // Only the token and the region were requested.
+    /// assert_eq!(mock.requests().len(), 2);
+    /// ```
+    pub fn get_field(&self, field: Field) -> Result<Option<String>> {
+        let token = self.get_token()?;
 
-    /// AWS Availability Zone - always available
-    pub availability_zone: String,
+        match field {
+            Field::AccountId => self.fetch_account_id(&token),
+            Field::Region => {
+                let region = self.resolve_region(&token, || {
+                    self.fetch_optional(&token, Field::AvailabilityZone)
+                })?;
+                if let (true, Some(region)) = (self.verify_region, &region) {
+                    self.check_region(&token, region)?;
+                }
+                Ok(region)
+            }
+            Field::PublicHostname => self.fetch_optional(&token, field),
+            Field::InstanceId
+            | Field::AmiId
+            | Field::AvailabilityZone
+            | Field::InstanceType
+            | Field::Hostname
+            | Field::LocalHostname => self.fetch_required(&token, field).map(Some),
+        }
+    }
 
-    /// AWS Instance Id - always available
-    pub instance_id: String,
+    /// Fetch every field like `get`, but keep going after failures and report
+    /// all of them together with the values that were fetched. Only failing
+    /// to get an IMDSv2 token, which no field can be fetched without, is
+    /// returned as an error.
+    ///
+    /// This is meant for troubleshooting; the result isn't kept as the
+    /// client's snapshot.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// let client = ec2_instance_metadata::InstanceMetadataClient::new();
+    /// let diagnostics = client.get_diagnostics().expect("Couldn't reach IMDS.");
+    /// for (field, error) in &diagnostics.errors {
+    ///     eprintln!("{}: {}", field, error);
+    /// }
+    /// ```
+    pub fn get_diagnostics(&self) -> Result<Diagnostics> {
+        let token = self.get_token()?;
+        let mut diagnostics = Diagnostics::default();
 
-    /// AWS Account Id - always available, marked as Internal Only per:
-    /// https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/instancedata-data-categories.html
-    pub account_id: String,
+        diagnostics.record(
+            Field::InstanceId,
+            self.fetch_required(&token, Field::InstanceId),
+        );
+        if let Some(account_id) = self.fetch_account_id(&token).transpose() {
+            diagnostics.record(Field::AccountId, account_id);
+        }
+        diagnostics.record(Field::AmiId, self.fetch_required(&token, Field::AmiId));
 
-    /// AWS AMS Id - always available
-    pub ami_id: String,
+        // A missing region is only reported as an error if there was a zone
+        // to derive it from.
+        let availability_zone = self.fetch_required(&token, Field::AvailabilityZone);
+        let region = match self.resolve_region(&token, || Ok(availability_zone.clone().ok())) {
+            Ok(Some(region)) => Some(Ok(region)),
+            Ok(None) => availability_zone.as_ref().ok().map(|availability_zone| {
+                Err(Error::UnknownAvailabilityZone(availability_zone.clone())
+                    .for_field(Field::Region))
+            }),
+            Err(e) => Some(Err(e)),
+        };
+        diagnostics.record(Field::AvailabilityZone, availability_zone);
+        if let Some(region) = region {
+            diagnostics.record(Field::Region, region);
+        }
 
-    /// AWS Instance Type - always available
-    pub instance_type: String,
+        for field in &[Field::InstanceType, Field::Hostname, Field::LocalHostname] {
+            diagnostics.record(*field, self.fetch_required(&token, *field));
+        }
 
-    /// AWS Instance Local Hostname - always available
-    pub local_hostname: String,
+        match self.fetch(&token, Field::PublicHostname) {
+            Err(Error::NotFound(_)) => {}
+            public_hostname => diagnostics.record(Field::PublicHostname, public_hostname),
+        }
 
-    /// AWS Instance Hostname - always available
-    pub hostname: String,
+        Ok(diagnostics)
+    }
 
-    /// AWS Instance Public Hostname - optionally available
-    pub public_hostname: Option<String>,
+    /// The region, from IMDS's `placement/region` or, where that isn't
+    /// served, derived from the zone `availability_zone` gets. `None` if
+    /// neither works. The region never changes under a running instance, so
+    /// once known it's kept until `refresh` sees the instance change.
+    fn resolve_region<F>(&self, token: &str, availability_zone: F) -> Result<Option<String>>
+    where
+        F: FnOnce() -> Result<Option<String>>,
+    {
+        if let Some(region) = lock(&self.state.region).clone() {
+            return Ok(Some(region));
+        }
+
+        let region = match self.fetch(token, Field::Region) {
+            Ok(region) if !region.is_empty() => Some(region),
+            Ok(_) | Err(Error::NotFound(_)) => {
+                log::warn!("IMDS has no region, deriving it from the availability zone");
+                availability_zone()?
+                    .and_then(|availability_zone| parse_availability_zone(&availability_zone).ok())
+                    .map(|zone| zone.region.to_string())
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Some(region) = &region {
+            *lock(&self.state.region) = Some(region.clone());
+        }
+        Ok(region)
+    }
+
+    /// The region `resolve_region` has cached, if any.
+    #[cfg(feature = "aws-config")]
+    fn cached_region(&self) -> Option<String> {
+        lock(&self.state.region).clone()
+    }
+
+    /// Check `region` against IMDS's `services/partition` and
+    /// `services/domain`, see `InstanceMetadataClientBuilder::verify_region`.
+    fn check_region(&self, token: &str, region: &str) -> Result<()> {
+        let fetch = |path| -> Result<String> {
+            let value = self
+                .fetch_path(token, path)
+                .map_err(|e| e.for_field(Field::Region))?;
+            Ok(value
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default())
+        };
+        let partition = fetch(SERVICES_PARTITION_PATH)?;
+        let domain = fetch(SERVICES_DOMAIN_PATH)?;
+
+        // A region the crate can't place is checked against the partition
+        // IMDS claims, so the domain is still verified.
+        let expected = match partition_for_region(region) {
+            Partition::Unknown => Partition::from_name(&partition),
+            known => known,
+        };
+
+        if expected.as_str() == partition && expected.dns_suffix() == Some(domain.as_str()) {
+            Ok(())
+        } else {
+            Err(Error::RegionMismatch {
+                region: region.to_string(),
+                partition,
+                domain,
+            })
+        }
+    }
+
+    /// Fetch the account id from the identity credentials info. That path is
+    /// documented as internal-only and some accounts and AMIs block it, so if
+    /// it's missing or forbidden the instance identity document is tried
+    /// instead. `None` if neither is available.
+    fn fetch_account_id(&self, token: &str) -> Result<Option<String>> {
+        let unavailable = |e: &Error| matches!(e.kind(), ErrorKind::NotFound | ErrorKind::Disabled);
+
+        match self.fetch(token, Field::AccountId) {
+            Ok(ident_creds) => {
+                return parse_account_id(&ident_creds, "AccountId")
+                    .map(Some)
+                    .map_err(|e| e.for_field(Field::AccountId))
+            }
+            Err(e) if unavailable(&e) => {}
+            Err(e) => return Err(e),
+        }
+
+        match self.fetch_path(token, INSTANCE_IDENTITY_DOCUMENT_PATH) {
+            Ok(Some(document)) => parse_account_id(&document, "accountId").map(Some),
+            Ok(None) => Ok(None),
+            Err(e) if unavailable(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| e.for_field(Field::AccountId))
+    }
+
+    /// The DNS suffix for building service endpoints, e.g.
+    /// `"amazonaws.com.cn"` in China: IMDS's `services/domain` if it's
+    /// served, otherwise the static suffix of the region's partition, see
+    /// `Partition::dns_suffix`. `None` if neither is available.
+    ///
+    /// # Examples:
+    /// ```
+    /// let mock = ec2_instance_metadata::MockImds::start();
+    /// let client = mock.client();
+    /// let metadata = client.get().expect("Couldn't get the instance metadata.");
+    /// if let (Some(region), Some(suffix)) = (metadata.region(), client.get_dns_suffix().unwrap()) {
+    ///     assert_eq!(format!("https://sts.{}.{}", region, suffix), "https://sts.us-east-1.amazonaws.com");
+    /// }
+    /// ```
+    pub fn get_dns_suffix(&self) -> Result<Option<String>> {
+        let token = self.get_token()?;
+        match self.fetch_path(&token, SERVICES_DOMAIN_PATH)? {
+            Some(domain) if !domain.trim().is_empty() => {
+                return Ok(Some(domain.trim().to_string()))
+            }
+            _ => {}
+        }
+
+        let region = self.resolve_region(&token, || {
+            self.fetch_optional(&token, Field::AvailabilityZone)
+        })?;
+
+        Ok(region
+            .and_then(|region| partition_for_region(&region).dns_suffix())
+            .map(str::to_string))
+    }
+
+    /// List the metadata API versions IMDS supports, oldest first. Any of
+    /// them can be passed to `InstanceMetadataClientBuilder::api_version`.
+    ///
+    /// # Examples:
+    /// ```
+    /// let mock = ec2_instance_metadata::MockImds::start();
+    /// let client = mock.client();
+    /// let versions = client.list_api_versions().expect("Couldn't list API versions.");
+    /// assert!(versions.iter().any(|version| version == "latest"));
+    /// ```
+    pub fn list_api_versions(&self) -> Result<Vec<String>> {
+        let token = self.get_token()?;
+        let versions = self
+            .fetch_absolute(&token, "/")?
+            .ok_or_else(|| Error::HttpStatus {
+                status: 404,
+                path: "/".to_string(),
+                body_excerpt: String::new(),
+            })?;
+
+        Ok(versions
+            .lines()
+            .map(str::trim)
+            .filter(|version| !version.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Fetch the value of `field`, trimmed of surrounding whitespace,
+    /// including the trailing newline some IMDS implementations add. Errors
+    /// are attributed to `field`.
+    fn fetch(&self, token: &str, field: Field) -> Result<String> {
+        match self.fetch_path(token, field.path()) {
+            Ok(Some(value)) if looks_like_html(&value) => {
+                Err(Error::UnexpectedResponse("got an HTML page".to_string()).for_field(field))
+            }
+            Ok(Some(value)) => Ok(value.trim().to_string()),
+            Ok(None) => Err(Error::NotFound(field)),
+            Err(e) => Err(e.for_field(field)),
+        }
+    }
+
+    /// Like `fetch`, but a 404 is `None`.
+    fn fetch_optional(&self, token: &str, field: Field) -> Result<Option<String>> {
+        match self.fetch(token, field) {
+            Ok(value) => Ok(Some(value)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Like `fetch`, but an empty value is an error. Middleboxes have been
+    /// seen answering 200 with no body, which mustn't pass for a real value.
+    fn fetch_required(&self, token: &str, field: Field) -> Result<String> {
+        let value = self.fetch(token, field)?;
+        if value.is_empty() {
+            return Err(Error::EmptyResponse(field));
+        }
+        Ok(value)
+    }
+
+    /// Fetch a path under the configured API version, returning `None` if
+    /// IMDS answers 404.
+    fn fetch_path(&self, token: &str, path: &str) -> Result<Option<String>> {
+        self.fetch_absolute(token, &format!("/{}/{}", self.api_version, path))
+    }
+
+    /// Fetch `path`, relative to the IMDS endpoint rather than an API version.
+    /// Only a 404 yields `None`; other error statuses are reported as
+    /// `Error::HttpStatus` and transport failures as `Error::NotEc2`,
+    /// `Error::Timeout`, or `Error::HttpRequest`.
+    pub(crate) fn fetch_absolute(&self, token: &str, path: &str) -> Result<Option<String>> {
+        self.fetch_absolute_bytes(token, path, self.max_body_size)?
+            .map(|body| decode_utf8(body, path))
+            .transpose()
+    }
+
+    /// Like `fetch_absolute`, but returns the body undecoded, failing with
+    /// `Error::ResponseTooLarge` if it's over `limit` bytes.
+    fn fetch_absolute_bytes(&self, token: &str, path: &str, limit: u64) -> Result<Option<Vec<u8>>> {
+        self.state.requests.run(path.to_string(), || {
+            trace::in_span(trace::fetch_span(path), || {
+                meter::measure(path, || self.send_get(token, path, limit))
+            })
+        })
+    }
+
+    /// Send the GET for `path`, for `fetch_absolute_bytes`.
+    fn send_get(&self, token: &str, path: &str, limit: u64) -> Result<Option<Vec<u8>>> {
+        let url = format!("{}{}", self.endpoint, path);
+        let mut request = self.agent.get(&url);
+        if !token.is_empty() {
+            request = request.set("X-aws-ec2-metadata-token", token);
+        }
+        let resp = self.call(request)?;
+        trace::record_status(resp.status());
+        meter::record_status(resp.status());
+
+        // The cached token was rejected, most likely because it expired
+        // early. Drop it so the next call fetches a fresh one.
+        if resp.status() == 401 {
+            log::warn!("the IMDS token was rejected for {}, dropping it", path);
+            lock(&self.state.token).take();
+            self.state.token_persistence.clear();
+        }
+
+        match resp.status() {
+            200..=299 => {
+                check_content_type(&resp, path)?;
+                self.read_body(resp, limit).map(Some)
+            }
+            404 => Ok(None),
+            _ => Err(Error::from_status(resp, path)),
+        }
+    }
+
+    /// Send a request, retrying failures according to the retry policy and
+    /// consulting the circuit breaker if one is configured.
+    ///
+    /// HTTP error statuses are returned as responses; only transport failures
+    /// become errors.
+    fn call(&self, request: ureq::Request) -> Result<ureq::Response> {
+        // Only the method and URL are logged: the token travels in a header,
+        // and bodies may hold credentials.
+        let (method, url) = (request.method(), request.url());
+        let started = Instant::now();
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.check_cancelled()?;
+
+            // The attempt gets the per-request limit, or whatever's left of
+            // the overall budget if that's shorter.
+            let (limit, deadline) = match self.overall_timeout {
+                Some(budget) => {
+                    let remaining = budget.saturating_sub(started.elapsed());
+                    if remaining.is_zero() {
+                        log::error!("{} {}: gave up after {:?}", method, url, budget);
+                        return Err(Error::Timeout {
+                            elapsed: started.elapsed(),
+                            limit: budget,
+                            deadline: Deadline::Overall,
+                        });
+                    }
+                    if remaining < self.request_timeout {
+                        (remaining, Deadline::Overall)
+                    } else {
+                        (self.request_timeout, Deadline::Request)
+                    }
+                }
+                None => (self.request_timeout, Deadline::Request),
+            };
+
+            if let Some(breaker) = &self.state.circuit_breaker {
+                if !breaker.allow() {
+                    log::warn!("{} {}: not sent, the circuit breaker is open", method, url);
+                    return Err(Error::CircuitOpen);
+                }
+            }
+
+            log::debug!("{} {}: attempt {}", method, url, attempt);
+            let attempt_started = Instant::now();
+            let result = self.send_attempt(request.clone(), limit);
+            match &result {
+                Ok(resp) | Err(ureq::Error::Status(_, resp)) => log::debug!(
+                    "{} {}: {} in {:?}",
+                    method,
+                    url,
+                    resp.status(),
+                    attempt_started.elapsed()
+                ),
+                Err(ureq::Error::Transport(transport)) => log::debug!(
+                    "{} {}: failed in {:?}: {}",
+                    method,
+                    url,
+                    attempt_started.elapsed(),
+                    transport
+                ),
+            }
+
+            if let Some(breaker) = &self.state.circuit_breaker {
+                match result {
+                    Err(ureq::Error::Transport(_)) => breaker.record_failure(),
+                    _ => breaker.record_success(),
+                }
+            }
+
+            let failure = match &result {
+                Ok(_) => None,
+                Err(ureq::Error::Status(status, _)) => FailureKind::from_status(*status),
+                Err(ureq::Error::Transport(transport)) => {
+                    Some(FailureKind::from_transport(transport))
+                }
+            };
+
+            let retry_after =
+                failure.and_then(
+                    |failure| match self.retry_policy.0.decide(attempt, failure) {
+                        RetryDecision::Retry(after) => Some(after),
+                        RetryDecision::GiveUp => None,
+                    },
+                );
+
+            if let Some(failure) = failure {
+                match retry_after {
+                    Some(after) => log::warn!(
+                        "{} {}: {:?} on attempt {}, retrying in {:?}",
+                        method,
+                        url,
+                        failure,
+                        attempt,
+                        after
+                    ),
+                    None => log::error!(
+                        "{} {}: {:?}, giving up after {} attempts",
+                        method,
+                        url,
+                        failure,
+                        attempt
+                    ),
+                }
+            }
+
+            match (result, retry_after) {
+                (_, Some(after)) => self.sleep(match self.overall_timeout {
+                    // Don't sleep past the budget; the next attempt reports it.
+                    Some(budget) => after.min(budget.saturating_sub(started.elapsed())),
+                    None => after,
+                })?,
+                // IMDS never redirects. Whatever answered isn't IMDS, and it
+                // mustn't be able to bounce our token somewhere else.
+                (Ok(resp), None) if (300..400).contains(&resp.status()) => {
+                    let location = resp.header("Location").unwrap_or_default();
+                    log::error!("{} {}: redirected to {:?}", method, url, location);
+                    return Err(Error::UnexpectedRedirect(location.to_string()));
+                }
+                (Ok(resp), None) => return Ok(resp),
+                (Err(ureq::Error::Status(_, resp)), None) => return Ok(resp),
+                (Err(ureq::Error::Transport(transport)), None) => {
+                    let (elapsed, limit) = match (deadline, self.overall_timeout) {
+                        (Deadline::Overall, Some(budget)) => (started.elapsed(), budget),
+                        _ => (attempt_started.elapsed(), limit),
+                    };
+                    return Err(Error::from_transport(transport, elapsed, limit, deadline));
+                }
+            }
+        }
+    }
+
+    /// Make one attempt at `request`, limited to `limit`.
+    // The error is ureq's, as `Request::call` returns it.
+    #[allow(clippy::result_large_err)]
+    fn send_attempt(
+        &self,
+        request: ureq::Request,
+        limit: Duration,
+    ) -> std::result::Result<ureq::Response, ureq::Error> {
+        match &self.bind {
+            Some(bind) => bind.send(&request, limit),
+            None => request.timeout(limit).call(),
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        match &self.cancellation_token {
+            Some(token) if token.is_cancelled() => Err(Error::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sleep between attempts, waking early if the client is cancelled.
+    fn sleep(&self, duration: Duration) -> Result<()> {
+        match &self.cancellation_token {
+            Some(token) if !token.sleep(duration) => Err(Error::Cancelled),
+            Some(_) => Ok(()),
+            None => {
+                std::thread::sleep(duration);
+                Ok(())
+            }
+        }
+    }
+
+    /// Read a response body, refusing to buffer more than `limit` bytes.
+    fn read_body(&self, resp: ureq::Response, limit: u64) -> Result<Vec<u8>> {
+        let declared_len = resp
+            .header("Content-Length")
+            .and_then(|len| len.parse::<u64>().ok());
+        if declared_len.is_some_and(|len| len > limit) {
+            return Err(Error::ResponseTooLarge(limit));
+        }
+
+        // Read at most one byte past the limit so oversized bodies are
+        // detected without ever being fully buffered.
+        let mut body = Vec::new();
+        resp.into_reader()
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut body)?;
+        if body.len() as u64 > limit {
+            return Err(Error::ResponseTooLarge(limit));
+        }
+
+        Ok(body)
+    }
 }
 
-impl std::fmt::Display for InstanceMetadata {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+/// IMDS never answers with HTML. If something does, it's a proxy or captive
+/// portal standing in for IMDS, and its body mustn't be taken as a value.
+fn check_content_type(resp: &ureq::Response, path: &str) -> Result<()> {
+    let content_type = resp.content_type();
+    if content_type.eq_ignore_ascii_case("text/html")
+        || content_type.eq_ignore_ascii_case("application/xhtml+xml")
+    {
+        return Err(Error::UnexpectedResponse(format!(
+            "{} answered with Content-Type {}",
+            path, content_type
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `body` is an HTML page, which no IMDS value ever is.
+fn looks_like_html(body: &str) -> bool {
+    let start = body.trim_start().as_bytes();
+    let starts_with = |prefix: &str| {
+        start.len() >= prefix.len() && start[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+    };
+    starts_with("<!doctype html") || starts_with("<html")
+}
+
+/// Decode a text response body, keeping a short excerpt of it in the error if
+/// it isn't UTF-8.
+fn decode_utf8(body: Vec<u8>, path: &str) -> Result<String> {
+    const PREVIEW_LEN: usize = 64;
+
+    String::from_utf8(body).map_err(|e| {
+        let bytes = e.as_bytes();
+        Error::InvalidUtf8 {
+            path: path.to_string(),
+            lossy_preview: String::from_utf8_lossy(&bytes[..bytes.len().min(PREVIEW_LEN)])
+                .into_owned(),
+        }
+    })
+}
+
+/// `Diagnostics` is the result of `InstanceMetadataClient::get_diagnostics`.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    /// The fields that were fetched, in fetch order
+    pub values: Vec<(Field, String)>,
+
+    /// The fields that couldn't be fetched, in fetch order
+    pub errors: Vec<(Field, Error)>,
+}
+
+impl Diagnostics {
+    /// Whether every field was fetched.
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// The fetched value of `field`, if any.
+    pub fn value(&self, field: Field) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(f, _)| *f == field)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn record(&mut self, field: Field, result: Result<String>) {
+        match result {
+            Ok(value) => self.values.push((field, value)),
+            Err(error) => self.errors.push((field, error)),
+        }
+    }
+}
+
+/// `Refreshed` is the result of `InstanceMetadataClient::refresh`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Refreshed {
+    /// The freshly fetched metadata
+    pub metadata: InstanceMetadata,
+
+    /// Set if the instance id differs from the previous snapshot
+    pub identity_change: Option<IdentityChange>,
+}
+
+/// `IdentityChange` reports that the process is now running on a different
+/// instance than when metadata was last fetched.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IdentityChange {
+    pub previous_instance_id: String,
+    pub current_instance_id: String,
+}
+
+/// `InstanceMetadataClientBuilder` configures an `InstanceMetadataClient`.
+///
+/// # Examples:
+/// ```no_run
+/// let client = ec2_instance_metadata::InstanceMetadataClient::builder()
+///     .max_body_size(64 * 1024)
+///     .build();
+/// let instance_metadata = client.get().expect("Couldn't get the instance metadata.");
+/// ```
+#[derive(Debug, Clone)]
+pub struct InstanceMetadataClientBuilder {
+    endpoint: String,
+    api_version: String,
+    max_body_size: u64,
+    max_user_data_size: u64,
+    retry_policy: SharedRetryPolicy,
+    request_timeout: Duration,
+    connect_timeout: Option<Duration>,
+    overall_timeout: Option<Duration>,
+    cancellation_token: Option<CancellationToken>,
+    local_address: Option<IpAddr>,
+    circuit_breaker: Option<(u32, Duration)>,
+    token_persistence: TokenPersistence,
+    verify_region: bool,
+    imds_v1: ImdsV1,
+}
+
+impl InstanceMetadataClientBuilder {
+    /// The metadata API version used by default.
+    pub const DEFAULT_API_VERSION: &'static str = "latest";
+
+    /// The IMDS endpoint used by default.
+    pub const DEFAULT_ENDPOINT: &'static str = "http://169.254.169.254";
+
+    /// Largest response body the client will read by default: 1 MiB.
+    pub const DEFAULT_MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+    /// Largest user data the client will read by default: 64 KiB. EC2 allows
+    /// 16 KiB of user data.
+    pub const DEFAULT_MAX_USER_DATA_SIZE: u64 = 64 * 1024;
+
+    /// Longest a single attempt at a request may take by default: 2 seconds.
+    pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+    pub fn new() -> Self {
+        Self {
+            endpoint: Self::DEFAULT_ENDPOINT.to_string(),
+            api_version: Self::DEFAULT_API_VERSION.to_string(),
+            max_body_size: Self::DEFAULT_MAX_BODY_SIZE,
+            max_user_data_size: Self::DEFAULT_MAX_USER_DATA_SIZE,
+            retry_policy: SharedRetryPolicy::default(),
+            request_timeout: Self::DEFAULT_REQUEST_TIMEOUT,
+            connect_timeout: None,
+            overall_timeout: None,
+            cancellation_token: None,
+            local_address: None,
+            circuit_breaker: None,
+            token_persistence: TokenPersistence::Disabled,
+            verify_region: false,
+            imds_v1: ImdsV1::Disabled,
+        }
+    }
+
+    /// Send requests to `endpoint` instead of the link-local IMDS address,
+    /// e.g. a local mock such as amazon-ec2-metadata-mock, or `MockImds` with
+    /// the `test-util` feature. It's the scheme, host, and optionally port; a
+    /// trailing `/` is ignored.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::{InstanceMetadataClient, MockImds};
+    ///
+    /// let mock = MockImds::empty();
+    /// mock.set("meta-data/instance-id", "i-0mock");
+    ///
+    /// let client = InstanceMetadataClient::builder().endpoint(mock.endpoint()).build();
+    /// assert_eq!(client.get_raw("instance-id").unwrap().as_deref(), Some("i-0mock"));
+    /// ```
+    pub fn endpoint<S: Into<String>>(mut self, endpoint: S) -> Self {
+        let endpoint = endpoint.into();
+        self.endpoint = endpoint.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Pin the client to a dated metadata API version, e.g. `"2021-07-15"`,
+    /// instead of `"latest"`. The version applies to every metadata path; see
+    /// `InstanceMetadataClient::list_api_versions` for the supported values.
+    pub fn api_version<S: Into<String>>(mut self, api_version: S) -> Self {
+        self.api_version = api_version.into();
+        self
+    }
+
+    /// Set the largest response body, in bytes, the client will read. Larger
+    /// responses fail with `Error::ResponseTooLarge`. User data has its own
+    /// limit, see `max_user_data_size`.
+    pub fn max_body_size(mut self, max_body_size: u64) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Set the largest user data, in bytes, `get_user_data` will read. Larger
+    /// user data fails with `Error::ResponseTooLarge`.
+    pub fn max_user_data_size(mut self, max_user_data_size: u64) -> Self {
+        self.max_user_data_size = max_user_data_size;
+        self
+    }
+
+    /// Set the policy for retrying failed requests. Defaults to
+    /// `ExponentialBackoff::default()`; use `NoRetry` to disable retries.
+    pub fn retry_policy<P: RetryPolicy + 'static>(mut self, retry_policy: P) -> Self {
+        self.retry_policy = SharedRetryPolicy(Arc::new(retry_policy));
+        self
+    }
+
+    /// Limit each attempt at a request, from connecting until the body is
+    /// read, to `request_timeout`. An attempt that runs out fails with an
+    /// `Error::Timeout` whose deadline is `Deadline::Request`, and may be
+    /// retried. Defaults to `DEFAULT_REQUEST_TIMEOUT`.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// use std::time::Duration;
+    /// // Give up quickly when checking whether this is an EC2 instance at all.
+    /// let client = ec2_instance_metadata::InstanceMetadataClient::builder()
+    ///     .request_timeout(Duration::from_millis(500))
+    ///     .build();
+    /// ```
+    pub fn request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// Limit connecting to IMDS to `connect_timeout`. The attempt as a whole
+    /// is still limited by the request timeout. Defaults to the request
+    /// timeout.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// Limit the total time spent on one request, including retries and the
+    /// sleeps between them. Each attempt is still limited by the request
+    /// timeout, or whatever's left of the budget if that's shorter. When
+    /// the budget runs out the request fails with an `Error::Timeout` whose
+    /// deadline is `Deadline::Overall`. Unlimited by default.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// use std::time::Duration;
+    /// let client = ec2_instance_metadata::InstanceMetadataClient::builder()
+    ///     .overall_timeout(Duration::from_secs(5))
+    ///     .build();
+    /// ```
+    pub fn overall_timeout(mut self, overall_timeout: Duration) -> Self {
+        self.overall_timeout = Some(overall_timeout);
+        self
+    }
+
+    /// Stop retries and polling loops, such as `wait_until_ready`, when `token`
+    /// is cancelled. They then fail with `Error::Cancelled`.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation_token = Some(token);
+        self
+    }
+
+    /// Send every IMDS request from `local_address`. On multi-homed hosts the
+    /// link-local IMDS route may only work from the primary interface's
+    /// address, and the OS doesn't always pick it.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// let client = ec2_instance_metadata::InstanceMetadataClient::builder()
+    ///     .local_address("10.0.0.12".parse().unwrap())
+    ///     .build();
+    /// ```
+    pub fn local_address(mut self, local_address: IpAddr) -> Self {
+        self.local_address = Some(local_address);
+        self
+    }
+
+    /// Enable a circuit breaker: after `failure_threshold` consecutive transport
+    /// failures (timeouts, refused connections), requests fail immediately with
+    /// `Error::CircuitOpen` for `cooldown`. Then a single request is let
+    /// through to probe IMDS, while the rest keep failing fast until it's
+    /// done: success closes the circuit, failure opens it again. The breaker
+    /// is shared by all clones of the built client. Disabled by default.
+    ///
+    /// `wait_until_ready` is not subject to the breaker.
+    pub fn circuit_breaker(mut self, failure_threshold: u32, cooldown: Duration) -> Self {
+        self.circuit_breaker = Some((failure_threshold, cooldown));
+        self
+    }
+
+    /// Persist the IMDSv2 token across processes, e.g. for CLIs run many times
+    /// in quick succession. See `TokenPersistence` for details.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// use ec2_instance_metadata::{InstanceMetadataClient, TokenPersistence};
+    /// let client = InstanceMetadataClient::builder()
+    ///     .token_persistence(TokenPersistence::File("/run/user/1000/imds-token".into()))
+    ///     .build();
+    /// ```
+    pub fn token_persistence(mut self, token_persistence: TokenPersistence) -> Self {
+        self.token_persistence = token_persistence;
+        self
+    }
+
+    /// Have `get` check the region against the partition and domain IMDS
+    /// reports under `services/`, e.g. that a `cn-` region is paired with
+    /// `aws-cn` and `amazonaws.com.cn`, and fail with `Error::RegionMismatch`
+    /// if they disagree or either is missing. This catches spoofed or
+    /// mangled metadata at the cost of two requests per `get`. Partitions
+    /// this crate doesn't know about always fail the check. Off by default.
+    pub fn verify_region(mut self, verify_region: bool) -> Self {
+        self.verify_region = verify_region;
+        self
+    }
+
+    /// Allow IMDSv1, which needs no session token, for hosts where the token
+    /// PUT is blocked. IMDSv1 requests can be forged by anything that can
+    /// make the instance send a GET, which is why IMDSv2 exists; see
+    /// `ImdsV1`. Disabled by default.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// use ec2_instance_metadata::{ImdsV1, ImdsVersion, InstanceMetadataClient};
+    /// let client = InstanceMetadataClient::builder()
+    ///     .imds_v1(ImdsV1::Fallback)
+    ///     .build();
+    /// let metadata = client.get().expect("Couldn't get the instance metadata.");
+    /// if metadata.imds_version == ImdsVersion::V1 {
+    ///     eprintln!("warning: fell back to IMDSv1");
+    /// }
+    /// ```
+    pub fn imds_v1(mut self, imds_v1: ImdsV1) -> Self {
+        self.imds_v1 = imds_v1;
+        self
+    }
+
+    pub fn build(self) -> InstanceMetadataClient {
+        let circuit_breaker = self
+            .circuit_breaker
+            .map(|(threshold, cooldown)| CircuitBreaker::new(threshold, cooldown));
+
+        let connect_timeout = self
+            .connect_timeout
+            .unwrap_or(self.request_timeout)
+            .min(self.request_timeout);
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(connect_timeout)
+            .redirects(0)
+            .build();
+        let bind = self.local_address.map(|local_address| {
+            Arc::new(bind::BindToLocalAddress {
+                local_address,
+                connect_timeout,
+                max_response_size: self.max_body_size.max(self.max_user_data_size),
+            })
+        });
+
+        InstanceMetadataClient {
+            agent,
+            bind,
+            endpoint: self.endpoint,
+            api_version: self.api_version,
+            max_body_size: self.max_body_size,
+            max_user_data_size: self.max_user_data_size,
+            retry_policy: self.retry_policy,
+            request_timeout: self.request_timeout,
+            overall_timeout: self.overall_timeout,
+            cancellation_token: self.cancellation_token,
+            verify_region: self.verify_region,
+            imds_v1: self.imds_v1,
+            state: Arc::new(ClientState::new(circuit_breaker, self.token_persistence)),
+        }
+    }
+}
+
+impl Default for InstanceMetadataClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `InstanceMetadata` holds the fetched instance metadata. Fields
+/// on this struct may be incomplete if AWS has updated the fields
+/// or if they haven't been explicitly provided.
+///
+/// New fields are added as IMDS exposes more, so the struct can't be built
+/// literally outside this crate; use `InstanceMetadata::builder` instead.
+/// Fields can be read directly or through the accessor methods.
+///
+/// Snapshots with the same values compare equal and hash alike, however
+/// they were obtained. `fetched_at` is ignored.
+///
+/// # Examples:
+/// ```
+/// use std::collections::HashSet;
+/// use std::time::{Duration, SystemTime};
+/// use ec2_instance_metadata::InstanceMetadata;
+///
+/// let first = InstanceMetadata::builder().build();
+/// let second = InstanceMetadata::builder()
+///     .fetched_at(SystemTime::now() + Duration::from_secs(60))
+///     .build();
+/// assert_eq!(first, second);
+///
+/// let snapshots: HashSet<_> = vec![first, second].into_iter().collect();
+/// assert_eq!(snapshots.len(), 1);
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(
+    feature = "schemars",
+    schemars(description = "EC2 instance metadata, as written by `InstanceMetadata::to_json`.")
+)]
+#[non_exhaustive]
+pub struct InstanceMetadata {
+    /// AWS Region - read from IMDS, or derived from the availability zone
+    /// where IMDS doesn't serve it. `None` if neither works; see
+    /// `is_known_region` to check it against the regions this crate knows
+    pub region: Option<String>,
+
+    /// AWS Availability Zone - always available
+    pub availability_zone: String,
+
+    /// AWS Instance Id - always available
+    pub instance_id: String,
+
+    /// AWS Account Id - read from identity-credentials, which is marked as
+    /// Internal Only per:
+    /// https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/instancedata-data-categories.html
+    /// or else from the instance identity document. `None` if neither is
+    /// available.
+    pub account_id: Option<String>,
+
+    /// AWS AMS Id - always available
+    pub ami_id: String,
+
+    /// AWS Instance Type - always available
+    pub instance_type: String,
+
+    /// AWS Instance Local Hostname - always available
+    pub local_hostname: String,
+
+    /// AWS Instance Hostname - always available
+    pub hostname: String,
+
+    /// AWS Instance Public Hostname - `None` if the instance has none assigned
+    pub public_hostname: Option<String>,
+
+    /// Additional raw values keyed by their path under `meta-data/`, as
+    /// requested with `InstanceMetadataClient::get_with_extras` - empty
+    /// otherwise
+    pub extras: BTreeMap<String, String>,
+
+    /// Which IMDS protocol the metadata was fetched with
+    pub imds_version: ImdsVersion,
+
+    /// When the metadata was fetched. Snapshots served from the client's
+    /// cache keep the time they were originally fetched
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
+    pub fetched_at: SystemTime,
+}
+
+impl PartialEq for InstanceMetadata {
+    fn eq(&self, other: &InstanceMetadata) -> bool {
+        self.entries() == other.entries() && self.extras == other.extras
+    }
+}
+
+impl Eq for InstanceMetadata {}
+
+impl std::hash::Hash for InstanceMetadata {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.entries().hash(state);
+        self.extras.hash(state);
+    }
+}
+
+impl InstanceMetadata {
+    /// Create a builder for fabricating metadata, e.g. for tests of code that
+    /// consumes it, without contacting IMDS.
+    pub fn builder() -> InstanceMetadataBuilder {
+        InstanceMetadataBuilder::new()
+    }
+
+    /// The value of `field`, `None` if it's unavailable.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::{Field, InstanceMetadata};
+    ///
+    /// let metadata = InstanceMetadata::builder().public_hostname(None).build();
+    /// assert_eq!(metadata.value(Field::InstanceType), Some("m5.large"));
+    /// assert_eq!(metadata.value(Field::PublicHostname), None);
+    /// ```
+    pub fn value(&self, field: Field) -> Option<&str> {
+        match field {
+            Field::Region => self.region(),
+            Field::AvailabilityZone => Some(self.availability_zone()),
+            Field::InstanceId => Some(self.instance_id()),
+            Field::AccountId => self.account_id(),
+            Field::AmiId => Some(self.ami_id()),
+            Field::InstanceType => Some(self.instance_type()),
+            Field::LocalHostname => Some(self.local_hostname()),
+            Field::Hostname => Some(self.hostname()),
+            Field::PublicHostname => self.public_hostname(),
+        }
+    }
+
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
+
+    pub fn availability_zone(&self) -> &str {
+        &self.availability_zone
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    pub fn account_id(&self) -> Option<&str> {
+        self.account_id.as_deref()
+    }
+
+    pub fn ami_id(&self) -> &str {
+        &self.ami_id
+    }
+
+    pub fn instance_type(&self) -> &str {
+        &self.instance_type
+    }
+
+    pub fn local_hostname(&self) -> &str {
+        &self.local_hostname
+    }
+
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    pub fn public_hostname(&self) -> Option<&str> {
+        self.public_hostname.as_deref()
+    }
+
+    pub fn extras(&self) -> &BTreeMap<String, String> {
+        &self.extras
+    }
+
+    pub fn imds_version(&self) -> ImdsVersion {
+        self.imds_version
+    }
+
+    pub fn fetched_at(&self) -> SystemTime {
+        self.fetched_at
+    }
+
+    /// The region as a `Region`, for matching without string literals.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// use ec2_instance_metadata::Region;
+    /// let client = ec2_instance_metadata::InstanceMetadataClient::new();
+    /// let metadata = client.get().expect("Couldn't get the instance metadata.");
+    /// if metadata.parsed_region() == Some(Region::UsEast1) {
+    ///     println!("in the oldest region");
+    /// }
+    /// ```
+    pub fn parsed_region(&self) -> Option<Region> {
+        self.region.as_deref().map(|region| match region.parse() {
+            Ok(region) => region,
+            Err(never) => match never {},
+        })
+    }
+
+    /// The partition of the region, see `partition_for_region`. `None` if
+    /// the region is unavailable.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::{InstanceMetadata, Partition};
+    ///
+    /// let metadata = InstanceMetadata::builder().region(Some("cn-northwest-1")).build();
+    /// assert_eq!(metadata.partition(), Some(Partition::AwsCn));
+    ///
+    /// let metadata = InstanceMetadata::builder().region(None).build();
+    /// assert_eq!(metadata.partition(), None);
+    /// ```
+    pub fn partition(&self) -> Option<Partition> {
+        self.parsed_region().map(|region| region.partition())
+    }
+
+    /// The static DNS suffix of the region's partition, see
+    /// `Partition::dns_suffix`. `None` if the region is unavailable or its
+    /// partition unknown.
+    pub fn dns_suffix(&self) -> Option<&'static str> {
+        self.partition().and_then(Partition::dns_suffix)
+    }
+
+    /// The instance's ARN, `arn:<partition>:ec2:<region>:<account>:instance/<id>`,
+    /// with the partition derived from the region. Fails with
+    /// `Error::MissingField` if the region or account id is unavailable, or
+    /// `Error::UnknownPartition` if the region's partition can't be told.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::{Error, InstanceMetadata};
+    ///
+    /// let arn = |region| {
+    ///     InstanceMetadata::builder()
+    ///         .region(Some(region))
+    ///         .instance_id("i-0fixture")
+    ///         .build()
+    ///         .instance_arn()
+    /// };
+    /// assert_eq!(arn("us-east-1").unwrap(), "arn:aws:ec2:us-east-1:123456789012:instance/i-0fixture");
+    /// assert_eq!(arn("cn-north-1").unwrap(), "arn:aws-cn:ec2:cn-north-1:123456789012:instance/i-0fixture");
+    /// assert_eq!(
+    ///     arn("us-gov-west-1").unwrap(),
+    ///     "arn:aws-us-gov:ec2:us-gov-west-1:123456789012:instance/i-0fixture"
+    /// );
+    /// assert_eq!(
+    ///     arn("us-isob-east-1").unwrap(),
+    ///     "arn:aws-iso-b:ec2:us-isob-east-1:123456789012:instance/i-0fixture"
+    /// );
+    /// assert_eq!(arn("xx-example-1"), Err(Error::UnknownPartition("xx-example-1".to_string())));
+    ///
+    /// let metadata = InstanceMetadata::builder().account_id(None).build();
+    /// assert_eq!(metadata.instance_arn(), Err(Error::MissingField("account_id")));
+    /// ```
+    pub fn instance_arn(&self) -> Result<String> {
+        let region = self.parsed_region().ok_or(Error::MissingField("region"))?;
+        let account_id = self
+            .account_id
+            .as_deref()
+            .ok_or(Error::MissingField("account_id"))?;
+
+        let partition = match region.partition() {
+            Partition::Unknown => return Err(Error::UnknownPartition(region.to_string())),
+            partition => partition,
+        };
+
+        Ok(format!(
+            "arn:{}:ec2:{}:{}:instance/{}",
+            partition, region, account_id, self.instance_id
+        ))
+    }
+
+    /// The zone letter of the availability zone, see `zone_letter`.
+    pub fn zone_letter(&self) -> Option<char> {
+        zone_letter(&self.availability_zone)
+    }
+
+    /// The kind of zone the instance is in, see `ZoneKind`.
+    pub fn zone_kind(&self) -> ZoneKind {
+        zone_kind(&self.availability_zone)
+    }
+
+    /// The Local Zone the instance is in, see `local_zone`.
+    pub fn local_zone(&self) -> Option<&str> {
+        local_zone(&self.availability_zone)
+    }
+
+    /// A view of the metadata for logging, with the account id and hostnames
+    /// masked. See `RedactionOptions` for choosing what's masked.
+    ///
+    /// # Examples:
+    /// ```
+    /// let metadata = ec2_instance_metadata::InstanceMetadata::builder().build();
+    /// let logged = metadata.redacted().to_string();
+    /// assert!(logged.contains("account_id:        ********9012"));
+    /// assert!(!logged.contains("ip-10-0-0-1"));
+    /// assert!(logged.contains("us-east-1a"));
+    /// ```
+    pub fn redacted(&self) -> RedactedInstanceMetadata<'_> {
+        self.redacted_with(RedactionOptions::default())
+    }
+
+    /// Like `redacted`, masking the fields chosen by `options`.
+    pub fn redacted_with(&self, options: RedactionOptions) -> RedactedInstanceMetadata<'_> {
+        RedactedInstanceMetadata::new(self, options)
+    }
+
+    /// The instance type split into family and size, see `InstanceTypeInfo`.
+    pub fn instance_type_info(&self) -> InstanceTypeInfo {
+        InstanceTypeInfo::parse(&self.instance_type)
+    }
+
+    /// The metadata as a single-line JSON object with snake_case keys, e.g.
+    /// for shipping to a log pipeline. Missing values are `null`; every key is
+    /// always present. `extras` is a nested object keyed by path, and
+    /// `fetched_at` is an RFC 3339 timestamp to the second.
+    ///
+    /// # Examples:
+    /// ```
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// use ec2_instance_metadata::InstanceMetadata;
+    ///
+    /// let builder = InstanceMetadata::builder()
+    ///     .ami_id("ami-0abcdef1234567890")
+    ///     .public_hostname(Some("ec2-1-2-3-4.compute-1.amazonaws.com"))
+    ///     .extra("placement/group-name", "web")
+    ///     .fetched_at(UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+    /// let metadata = builder.clone().build();
+    ///
+    /// assert_eq!(
+    ///     metadata.to_json(),
+    ///     concat!(
+    ///         r#"{"region":"us-east-1","availability_zone":"us-east-1a","#,
+    ///         r#""instance_id":"i-0123456789abcdef0","account_id":"123456789012","#,
+    ///         r#""ami_id":"ami-0abcdef1234567890","instance_type":"m5.large","#,
+    ///         r#""local_hostname":"ip-10-0-0-1.ec2.internal","#,
+    ///         r#""hostname":"ip-10-0-0-1.ec2.internal","#,
+    ///         r#""public_hostname":"ec2-1-2-3-4.compute-1.amazonaws.com","#,
+    ///         r#""imds_version":"v2","#,
+    ///         r#""extras":{"placement/group-name":"web"},"#,
+    ///         r#""fetched_at":"2023-11-14T22:13:20Z"}"#,
+    ///     )
+    /// );
+    ///
+    /// let metadata = builder.account_id(None).hostname("quote\"d").build();
+    /// let json = metadata.to_json();
+    /// assert!(json.contains(r#""account_id":null"#));
+    /// assert!(json.contains(r#""hostname":"quote\"d""#));
+    /// ```
+    ///
+    /// With the `schemars` feature, `schemars::schema_for!(InstanceMetadata)`
+    /// describes this output:
+    /// ```
+    /// # #[cfg(feature = "schemars")]
+    /// # {
+    /// use ec2_instance_metadata::InstanceMetadata;
+    /// use serde_json::Value;
+    ///
+    /// let schema = serde_json::to_value(schemars::schema_for!(InstanceMetadata)).unwrap();
+    /// let metadata = InstanceMetadata::builder().extra("kernel-id", "aki-1").build();
+    /// let sample: Value = serde_json::from_str(&metadata.to_json()).unwrap();
+    ///
+    /// fn matches(schema: &Value, definitions: &Value, value: &Value) -> bool {
+    ///     if let Some(reference) = schema["$ref"].as_str() {
+    ///         let name = reference.trim_start_matches("#/definitions/");
+    ///         return matches(&definitions[name], definitions, value);
+    ///     }
+    ///     if let Some(all) = schema["allOf"].as_array() {
+    ///         return all.iter().all(|schema| matches(schema, definitions, value));
+    ///     }
+    ///     if let Some(options) = schema["enum"].as_array() {
+    ///         return options.contains(value);
+    ///     }
+    ///     let type_name = match value {
+    ///         Value::Null => "null",
+    ///         Value::String(_) => "string",
+    ///         Value::Object(object) => {
+    ///             let values = &schema["additionalProperties"];
+    ///             if !object.values().all(|v| matches(values, definitions, v)) {
+    ///                 return false;
+    ///             }
+    ///             "object"
+    ///         }
+    ///         _ => return false,
+    ///     };
+    ///     match &schema["type"] {
+    ///         Value::String(allowed) => allowed == type_name,
+    ///         Value::Array(allowed) => allowed.iter().any(|allowed| allowed == type_name),
+    ///         _ => false,
+    ///     }
+    /// }
+    ///
+    /// let properties = &schema["properties"];
+    /// for (key, value) in sample.as_object().unwrap() {
+    ///     assert!(matches(&properties[key], &schema["definitions"], value), "{}", key);
+    /// }
+    /// for key in schema["required"].as_array().unwrap() {
+    ///     assert!(sample.get(key.as_str().unwrap()).is_some(), "{}", key);
+    /// }
+    /// # }
+    /// ```
+    pub fn to_json(&self) -> String {
+        let mut object = json::JsonValue::new_object();
+        for (key, value) in self.entries().iter() {
+            object[*key] = (*value).into();
+        }
+        let mut extras = json::JsonValue::new_object();
+        for (path, value) in &self.extras {
+            extras[path.as_str()] = value.as_str().into();
+        }
+        object["extras"] = extras;
+        object["fetched_at"] = rfc3339::format(self.fetched_at).into();
+        object.dump()
+    }
+
+    /// Parse metadata written by `to_json`, e.g. a snapshot saved to disk.
+    /// Unknown keys are ignored. Missing or `null` optional values are `None`;
+    /// a missing required value or one with the wrong type fails with
+    /// `Error::MissingField`. `imds_version` defaults to `v2` if absent.
+    ///
+    /// # Examples:
+    /// ```
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// use ec2_instance_metadata::{Error, InstanceMetadata};
+    ///
+    /// let fetched_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    /// let metadata = InstanceMetadata::builder()
+    ///     .account_id(None)
+    ///     .extra("kernel-id", "aki-12345678")
+    ///     .fetched_at(fetched_at)
+    ///     .build();
+    ///
+    /// let parsed = InstanceMetadata::from_json(&metadata.to_json()).unwrap();
+    /// assert_eq!(parsed, metadata);
+    /// assert_eq!(parsed.fetched_at, fetched_at);
+    ///
+    /// let json = r#"{
+    ///     "region": "eu-west-1", "availability_zone": "eu-west-1b",
+    ///     "instance_id": "i-0fixture", "ami_id": "ami-0fixture",
+    ///     "instance_type": "t3.micro", "local_hostname": "ip-10-0-0-2.internal",
+    ///     "hostname": "ip-10-0-0-2.internal", "fetched_at": "2023-11-14T22:13:20Z",
+    ///     "tags": {"Name": "web"}, "schema": 2
+    /// }"#;
+    /// let parsed = InstanceMetadata::from_json(json).unwrap();
+    /// assert_eq!(parsed.region.as_deref(), Some("eu-west-1"));
+    /// assert_eq!(parsed.public_hostname, None);
+    ///
+    /// let json = json.replace(r#""instance_id": "i-0fixture","#, "");
+    /// assert_eq!(
+    ///     InstanceMetadata::from_json(&json),
+    ///     Err(Error::MissingField("instance_id"))
+    /// );
+    /// ```
+    pub fn from_json(json: &str) -> Result<InstanceMetadata> {
+        let parsed = json::parse(json)?;
+
+        let optional = |key: &'static str| -> Result<Option<String>> {
+            let value = &parsed[key];
+            match value.as_str() {
+                Some(value) => Ok(Some(value.to_string())),
+                None if value.is_null() => Ok(None),
+                None => Err(Error::MissingField(key)),
+            }
+        };
+        let required = |key: &'static str| -> Result<String> {
+            optional(key)?.ok_or(Error::MissingField(key))
+        };
+
+        let mut extras = BTreeMap::new();
+        for (path, value) in parsed["extras"].entries() {
+            let value = value.as_str().ok_or(Error::MissingField("extras"))?;
+            extras.insert(path.to_string(), value.to_string());
+        }
+
+        let imds_version = match optional("imds_version")?.as_deref() {
+            None | Some("v2") => ImdsVersion::V2,
+            Some("v1") => ImdsVersion::V1,
+            Some(_) => return Err(Error::MissingField("imds_version")),
+        };
+
+        let fetched_at =
+            rfc3339::parse(&required("fetched_at")?).ok_or(Error::MissingField("fetched_at"))?;
+
+        Ok(InstanceMetadata {
+            region: optional("region")?,
+            availability_zone: required("availability_zone")?,
+            instance_id: required("instance_id")?,
+            account_id: optional("account_id")?,
+            ami_id: required("ami_id")?,
+            instance_type: required("instance_type")?,
+            local_hostname: required("local_hostname")?,
+            hostname: required("hostname")?,
+            public_hostname: optional("public_hostname")?,
+            extras,
+            imds_version,
+            fetched_at,
+        })
+    }
+
+    /// The fields as `(key, value)` pairs, e.g. for tagging resources or
+    /// building log context. Keys are the ones `to_json` uses, and every key
+    /// is always present, with `None` for missing optional values. `extras`
+    /// and `fetched_at` aren't included.
+    ///
+    /// # Examples:
+    /// ```
+    /// let metadata = ec2_instance_metadata::InstanceMetadata::builder().build();
+    /// let keys: Vec<_> = metadata.iter().map(|(key, _)| key).collect();
+    /// assert_eq!(
+    ///     keys,
+    ///     [
+    ///         "region",
+    ///         "availability_zone",
+    ///         "instance_id",
+    ///         "account_id",
+    ///         "ami_id",
+    ///         "instance_type",
+    ///         "local_hostname",
+    ///         "hostname",
+    ///         "public_hostname",
+    ///         "imds_version",
+    ///     ]
+    /// );
+    /// assert!(metadata.iter().any(|entry| entry == ("public_hostname", None)));
+    /// assert!(metadata.iter().any(|entry| entry == ("instance_type", Some("m5.large"))));
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, Option<&str>)> {
+        IntoIterator::into_iter(self.entries())
+    }
+
+    /// Every field as a `(key, value)` pair, in declaration order.
+    fn entries(&self) -> [(&'static str, Option<&str>); 10] {
+        [
+            ("region", self.region.as_deref()),
+            ("availability_zone", Some(self.availability_zone.as_str())),
+            ("instance_id", Some(self.instance_id.as_str())),
+            ("account_id", self.account_id.as_deref()),
+            ("ami_id", Some(self.ami_id.as_str())),
+            ("instance_type", Some(self.instance_type.as_str())),
+            ("local_hostname", Some(self.local_hostname.as_str())),
+            ("hostname", Some(self.hostname.as_str())),
+            ("public_hostname", self.public_hostname.as_deref()),
+            ("imds_version", Some(self.imds_version.as_str())),
+        ]
+    }
+}
+
+/// One `key: value` line per field, with the values aligned. Missing values
+/// are shown as `-`. Extras, keyed by path, come just before `fetched_at`.
+///
+/// # Examples:
+/// ```
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// # let metadata = ec2_instance_metadata::InstanceMetadata::builder()
+/// #     .fetched_at(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+/// #     .build();
+/// assert_eq!(
+///     metadata.to_string(),
+///     "\
+/// region:            us-east-1
+/// availability_zone: us-east-1a
+/// instance_id:       i-0123456789abcdef0
+/// account_id:        123456789012
+/// ami_id:            ami-0123456789abcdef0
+/// instance_type:     m5.large
+/// local_hostname:    ip-10-0-0-1.ec2.internal
+/// hostname:          ip-10-0-0-1.ec2.internal
+/// public_hostname:   -
+/// imds_version:      v2
+/// fetched_at:        2023-11-14T22:13:20Z"
+/// );
+/// ```
+impl std::fmt::Display for InstanceMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut entries = self.entries().to_vec();
+        entries.extend(
+            self.extras
+                .iter()
+                .map(|(path, value)| (path.as_str(), Some(value.as_str()))),
+        );
+        write_lines(f, &entries, self.fetched_at)
+    }
+}
+
+/// Write `entries` and `fetched_at` as aligned `key: value` lines.
+fn write_lines(
+    f: &mut std::fmt::Formatter,
+    entries: &[(&str, Option<&str>)],
+    fetched_at: SystemTime,
+) -> std::fmt::Result {
+    for (key, value) in entries {
+        writeln!(f, "{:<18} {}", format!("{}:", key), value.unwrap_or("-"))?;
+    }
+    write!(f, "{:<18} {}", "fetched_at:", rfc3339::format(fetched_at))
+}
+
+/// `ImdsV1` is whether a client may use IMDSv1, see
+/// `InstanceMetadataClientBuilder::imds_v1`. Whichever is used is recorded
+/// in `InstanceMetadata::imds_version`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ImdsV1 {
+    /// Only use IMDSv2.
+    #[default]
+    Disabled,
+
+    /// Use IMDSv2, but if a session token can't be had, send plain IMDSv1
+    /// requests from then on.
+    Fallback,
+
+    /// Never request a token; only send IMDSv1 requests.
+    Only,
+}
+
+/// `ImdsVersion` is the IMDS protocol used to fetch metadata: IMDSv2 with a
+/// session token, or IMDSv1 without one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename_all = "lowercase"))]
+pub enum ImdsVersion {
+    V1,
+    V2,
+}
+
+impl ImdsVersion {
+    /// `"v1"` or `"v2"`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ImdsVersion::V1 => "v1",
+            ImdsVersion::V2 => "v2",
+        }
+    }
+}
+
+impl std::fmt::Display for ImdsVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// `InstanceMetadataBuilder` builds an `InstanceMetadata` without contacting
+/// IMDS. Every field starts out with a plausible placeholder value, in
+/// `us-east-1a` with no public hostname and fetched when the builder was
+/// created, so tests only need to set the fields they care about.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::InstanceMetadata;
+///
+/// fn log_prefix(metadata: &InstanceMetadata) -> String {
+///     format!("[{}/{}]", metadata.region.as_deref().unwrap_or("?"), metadata.instance_id)
+/// }
+///
+/// let metadata = InstanceMetadata::builder()
+///     .region(Some("eu-west-1"))
+///     .availability_zone("eu-west-1b")
+///     .instance_id("i-0fixture")
+///     .build();
+/// assert_eq!(log_prefix(&metadata), "[eu-west-1/i-0fixture]");
+///
+/// let metadata = InstanceMetadata::builder().region(None).build();
+/// assert_eq!(log_prefix(&metadata), "[?/i-0123456789abcdef0]");
+/// ```
+#[derive(Debug, Clone)]
+pub struct InstanceMetadataBuilder {
+    metadata: InstanceMetadata,
+}
+
+impl InstanceMetadataBuilder {
+    pub fn new() -> Self {
+        Self {
+            metadata: InstanceMetadata {
+                region: Some("us-east-1".to_string()),
+                availability_zone: "us-east-1a".to_string(),
+                instance_id: "i-0123456789abcdef0".to_string(),
+                account_id: Some("123456789012".to_string()),
+                ami_id: "ami-0123456789abcdef0".to_string(),
+                instance_type: "m5.large".to_string(),
+                local_hostname: "ip-10-0-0-1.ec2.internal".to_string(),
+                hostname: "ip-10-0-0-1.ec2.internal".to_string(),
+                public_hostname: None,
+                extras: BTreeMap::new(),
+                imds_version: ImdsVersion::V2,
+                fetched_at: SystemTime::now(),
+            },
+        }
+    }
+
+    pub fn region(mut self, region: Option<&str>) -> Self {
+        self.metadata.region = region.map(str::to_string);
+        self
+    }
+
+    pub fn availability_zone<S: Into<String>>(mut self, availability_zone: S) -> Self {
+        self.metadata.availability_zone = availability_zone.into();
+        self
+    }
+
+    pub fn instance_id<S: Into<String>>(mut self, instance_id: S) -> Self {
+        self.metadata.instance_id = instance_id.into();
+        self
+    }
+
+    pub fn account_id(mut self, account_id: Option<&str>) -> Self {
+        self.metadata.account_id = account_id.map(str::to_string);
+        self
+    }
+
+    pub fn ami_id<S: Into<String>>(mut self, ami_id: S) -> Self {
+        self.metadata.ami_id = ami_id.into();
+        self
+    }
+
+    pub fn instance_type<S: Into<String>>(mut self, instance_type: S) -> Self {
+        self.metadata.instance_type = instance_type.into();
+        self
+    }
+
+    pub fn local_hostname<S: Into<String>>(mut self, local_hostname: S) -> Self {
+        self.metadata.local_hostname = local_hostname.into();
+        self
+    }
+
+    pub fn hostname<S: Into<String>>(mut self, hostname: S) -> Self {
+        self.metadata.hostname = hostname.into();
+        self
+    }
+
+    pub fn public_hostname(mut self, public_hostname: Option<&str>) -> Self {
+        self.metadata.public_hostname = public_hostname.map(str::to_string);
+        self
+    }
+
+    pub fn imds_version(mut self, imds_version: ImdsVersion) -> Self {
+        self.metadata.imds_version = imds_version;
+        self
+    }
+
+    pub fn fetched_at(mut self, fetched_at: SystemTime) -> Self {
+        self.metadata.fetched_at = fetched_at;
+        self
+    }
+
+    /// Add a value to `extras`, keyed by its path.
+    pub fn extra<P: Into<String>, V: Into<String>>(mut self, path: P, value: V) -> Self {
+        self.metadata.extras.insert(path.into(), value.into());
+        self
+    }
+
+    pub fn build(self) -> InstanceMetadata {
+        self.metadata
+    }
+}
+
+impl Default for InstanceMetadataBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Barrier;
+
+    #[test]
+    fn concurrent_gets_share_requests() {
+        const CALLERS: usize = 8;
+
+        let mock = MockImds::start();
+        // Slow enough that every caller arrives while the first is underway.
+        mock.set_delay(Duration::from_millis(50));
+        let client = mock.client();
+        let barrier = Arc::new(Barrier::new(CALLERS));
+
+        let callers: Vec<_> = (0..CALLERS)
+            .map(|_| {
+                let client = client.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    client.get()
+                })
+            })
+            .collect();
+        for caller in callers {
+            let metadata = caller.join().unwrap().unwrap();
+            assert_eq!(metadata.instance_id, MockImds::INSTANCE_ID);
+        }
+
+        let requests = mock.requests();
+        let puts = requests.iter().filter(|r| r.method == "PUT").count();
+        assert_eq!(puts, 1);
+        let mut gets: HashMap<&str, usize> = HashMap::new();
+        for request in requests.iter().filter(|r| r.method == "GET") {
+            *gets.entry(request.path.as_str()).or_default() += 1;
+        }
+        assert!(gets.contains_key("/latest/meta-data/instance-id"));
+        assert!(gets.values().all(|&count| count == 1), "{:?}", gets);
+    }
+
+    #[test]
+    fn identity_changes_are_reported() {
+        let mock = MockImds::start();
+        let client = mock.client();
+        let unchanged = client.refresh().unwrap();
+        assert_eq!(unchanged.identity_change, None);
+        assert_eq!(client.refresh().unwrap().identity_change, None);
+
+        mock.set("meta-data/instance-id", "i-0fedcba9876543210");
+        mock.clear_requests();
+        let refreshed = client.refresh().unwrap();
+        assert_eq!(
+            refreshed.identity_change,
+            Some(IdentityChange {
+                previous_instance_id: MockImds::INSTANCE_ID.to_string(),
+                current_instance_id: "i-0fedcba9876543210".to_string(),
+            })
+        );
+        assert_eq!(refreshed.metadata.instance_id, "i-0fedcba9876543210");
+        assert_eq!(client.cached(), Some(refreshed.metadata));
+        // The token from the old instance was dropped for a new one.
+        let puts = mock.requests().iter().filter(|r| r.method == "PUT").count();
+        assert_eq!(puts, 1);
+
+        assert_eq!(client.refresh().unwrap().identity_change, None);
+    }
+
+    #[test]
+    fn timeouts_are_not_reported_as_not_found() {
+        let mock = MockImds::start();
+        mock.set_delay(Duration::from_millis(300));
+        let client = InstanceMetadataClient::builder()
+            .endpoint(mock.endpoint())
+            .imds_v1(ImdsV1::Only)
+            .retry_policy(NoRetry)
+            .request_timeout(Duration::from_millis(50))
+            .build();
+
+        let error = client.get().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Timeout);
+        assert!(!error.is_not_found());
+        match error {
+            Error::Fetch { field, source } => {
+                assert_eq!(field, Field::InstanceId);
+                assert!(matches!(*source, Error::Timeout { .. }), "{:?}", source);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn server_errors_carry_their_status() {
+        let mock = MockImds::start();
+        mock.set_status("meta-data/instance-id", 500);
+        let client = InstanceMetadataClient::builder()
+            .endpoint(mock.endpoint())
+            .retry_policy(NoRetry)
+            .build();
+
+        let error = client.get().unwrap_err();
+        assert!(!error.is_not_found());
+        match error {
+            Error::Fetch { field, source } => {
+                assert_eq!(field, Field::InstanceId);
+                assert!(
+                    matches!(*source, Error::HttpStatus { status: 500, .. }),
+                    "{:?}",
+                    source
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn values_are_trimmed() {
+        for body in &["m5.large", "m5.large\n", "m5.large\r\n", " m5.large \n"] {
+            let mock = MockImds::start();
+            mock.set("meta-data/instance-type", *body);
+            let client = mock.client();
+
+            assert_eq!(
+                client.get().unwrap().instance_type,
+                "m5.large",
+                "{:?}",
+                body
+            );
+            assert_eq!(
+                client.get_field(Field::InstanceType).unwrap().as_deref(),
+                Some("m5.large"),
+                "{:?}",
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn listings_and_user_data_are_not_trimmed() {
+        let mock = MockImds::start();
+        mock.set("meta-data/block-device-mapping/", "ami\nroot\n");
+        mock.set("user-data", " #!/bin/sh\necho hello\n");
+        let client = mock.client();
+
+        assert_eq!(
+            client.get_raw("block-device-mapping/").unwrap().as_deref(),
+            Some("ami\nroot\n")
+        );
+        assert_eq!(
+            client.get_user_data().unwrap().as_deref(),
+            Some(&b" #!/bin/sh\necho hello\n"[..])
+        );
+    }
+
+    #[test]
+    fn separately_fetched_snapshots_are_equal() {
+        use std::collections::HashSet;
+
+        let mock = MockImds::start();
+        let client = mock.client();
+        let first = client.get().unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        client.invalidate();
+        let second = client.get().unwrap();
+
+        assert_ne!(first.fetched_at, second.fetched_at);
+        assert_eq!(first, second);
+        let snapshots: HashSet<_> = vec![first, second].into_iter().collect();
+        assert_eq!(snapshots.len(), 1);
+    }
+
+    #[test]
+    fn region_is_read_from_imds_and_resolved_again_on_a_new_instance() {
+        let mock = MockImds::start();
+        // Made up, so it can't come from the zone or the compiled-in table.
+        mock.set("meta-data/placement/region", "xx-example-1");
+        let client = mock.client();
+        assert_eq!(
+            client.get().unwrap().region.as_deref(),
+            Some("xx-example-1")
+        );
+        assert_eq!(
+            client.get_field(Field::Region).unwrap().as_deref(),
+            Some("xx-example-1")
+        );
+
+        // The region is cached while the instance stays the same.
+        mock.set("meta-data/placement/region", "xx-example-2");
+        assert_eq!(
+            client.refresh().unwrap().metadata.region.as_deref(),
+            Some("xx-example-1")
+        );
+
+        mock.set("meta-data/instance-id", "i-0fedcba9876543210");
+        let refreshed = client.refresh().unwrap();
+        assert!(refreshed.identity_change.is_some());
+        assert_eq!(refreshed.metadata.region.as_deref(), Some("xx-example-2"));
+        assert_eq!(
+            client.get_field(Field::Region).unwrap().as_deref(),
+            Some("xx-example-2")
+        );
+    }
+
+    fn count(mock: &MockImds, method: &str, path: &str) -> usize {
+        mock.requests()
+            .iter()
+            .filter(|r| r.method == method && r.path == path)
+            .count()
+    }
+
+    fn retrying_client(mock: &MockImds, max_retries: u32) -> InstanceMetadataClient {
+        InstanceMetadataClient::builder()
+            .endpoint(mock.endpoint())
+            .retry_policy(ExponentialBackoff::new(
+                max_retries,
+                Duration::from_millis(1),
+                Duration::from_millis(1),
+            ))
+            .build()
+    }
+
+    #[test]
+    fn server_errors_are_retried() {
+        let mock = MockImds::start();
+        mock.fail_next("meta-data/instance-id", 500, 2);
+        let client = retrying_client(&mock, 2);
+
+        assert_eq!(
+            client.get_raw("instance-id").unwrap().as_deref(),
+            Some(MockImds::INSTANCE_ID)
+        );
+        assert_eq!(count(&mock, "GET", "/latest/meta-data/instance-id"), 3);
+    }
+
+    #[test]
+    fn retries_give_up_after_the_limit() {
+        let mock = MockImds::start();
+        mock.set_status("meta-data/instance-id", 503);
+        let client = retrying_client(&mock, 2);
+
+        let error = client.get_raw("instance-id").unwrap_err();
+        assert!(
+            matches!(error, Error::HttpStatus { status: 503, .. }),
+            "{:?}",
+            error
+        );
+        assert_eq!(count(&mock, "GET", "/latest/meta-data/instance-id"), 3);
+    }
+
+    #[test]
+    fn not_found_is_not_retried() {
+        let mock = MockImds::start();
+        let client = retrying_client(&mock, 2);
+
+        assert_eq!(client.get_raw("no-such-path"), Ok(None));
+        assert_eq!(count(&mock, "GET", "/latest/meta-data/no-such-path"), 1);
+    }
+
+    #[test]
+    fn rejected_tokens_are_dropped() {
+        let mock = MockImds::start();
+        mock.require_token(true);
+        let client = mock.client();
+        client.get_raw("instance-id").unwrap();
+
+        // IMDS forgets the token, e.g. after the instance was stopped. The
+        // call that finds out fails, and the next one gets a new token.
+        mock.invalidate_tokens();
+        mock.clear_requests();
+        let error = client.get_raw("instance-id").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Unauthorized);
+        assert_eq!(
+            client.get_raw("instance-id").unwrap().as_deref(),
+            Some(MockImds::INSTANCE_ID)
+        );
+        client.get_raw("instance-id").unwrap();
+
+        let tokens: Vec<_> = mock
+            .requests()
+            .iter()
+            .map(|r| {
+                (
+                    r.method.clone(),
+                    r.header("X-aws-ec2-metadata-token").map(str::to_string),
+                )
+            })
+            .collect();
+        let sent =
+            |method: &str, token: Option<&str>| (method.to_string(), token.map(str::to_string));
+        assert_eq!(
+            tokens,
+            vec![
+                sent("GET", Some("mock-token-1")),
+                sent("PUT", None),
+                sent("GET", Some("mock-token-2")),
+                sent("GET", Some("mock-token-2")),
+            ]
+        );
+    }
+
+    #[test]
+    fn html_responses_are_rejected() {
+        let mock = MockImds::start();
+        mock.set(
+            "meta-data/instance-id",
+            "<!DOCTYPE html>\n<html><body>Please log in</body></html>",
+        );
+        let client = mock.client();
+
+        match client.get().unwrap_err() {
+            Error::Fetch { field, source } => {
+                assert_eq!(field, Field::InstanceId);
+                assert!(
+                    matches!(*source, Error::UnexpectedResponse(_)),
+                    "{:?}",
+                    source
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn persisted_tokens_are_reused() {
+        let mock = MockImds::start();
+        let dir =
+            std::env::temp_dir().join(format!("ec2-metadata-token-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let persistence = TokenPersistence::File(dir.join("token"));
+        let client = || {
+            InstanceMetadataClient::builder()
+                .endpoint(mock.endpoint())
+                .token_persistence(persistence.clone())
+                .build()
+        };
+
+        client().get_raw("instance-id").unwrap();
+        // A new client, as in the next run of a short-lived process.
+        client().get_raw("instance-id").unwrap();
+        let put = count(&mock, "PUT", "/latest/api/token");
+
+        // A rejected persisted token is dropped, and the next run replaces it.
+        mock.invalidate_tokens();
+        assert!(client().get_raw("instance-id").is_err());
+        client().get_raw("instance-id").unwrap();
+        client().get_raw("instance-id").unwrap();
+        let replaced = count(&mock, "PUT", "/latest/api/token");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(put, 1);
+        assert_eq!(replaced, 2);
+    }
+
+    #[test]
+    fn cancelled_clients_send_nothing() {
+        let mock = MockImds::start();
+        let cancel = CancellationToken::new();
+        let client = InstanceMetadataClient::builder()
+            .endpoint(mock.endpoint())
+            .cancellation_token(cancel.clone())
+            .build();
+        cancel.cancel();
+
+        assert_eq!(client.get(), Err(Error::Cancelled));
+        assert!(mock.requests().is_empty());
+    }
+
+    #[test]
+    fn cancelling_wakes_retry_backoff() {
+        let mock = MockImds::start();
+        mock.set_status("meta-data/instance-id", 500);
+        let cancel = CancellationToken::new();
+        let client = InstanceMetadataClient::builder()
+            .endpoint(mock.endpoint())
+            .cancellation_token(cancel.clone())
+            .retry_policy(ExponentialBackoff::new(
+                5,
+                Duration::from_secs(10),
+                Duration::from_secs(10),
+            ))
+            .build();
+
+        let started = Instant::now();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            cancel.cancel();
+        });
+        assert_eq!(client.get_raw("instance-id"), Err(Error::Cancelled));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn account_id_is_read_from_either_document() {
+        let info =
+            r#"{"Code":"Success","LastUpdated":"2023-11-14T22:13:20Z","AccountId":"123456789012"}"#;
+        assert_eq!(
+            parse_account_id(info, "AccountId"),
+            Ok("123456789012".to_string())
+        );
+
+        let document = r#"{"accountId":"123456789012","region":"us-east-1","instanceId":"i-0123456789abcdef0"}"#;
+        assert_eq!(
+            parse_account_id(document, "accountId"),
+            Ok("123456789012".to_string())
+        );
+    }
+
+    #[test]
+    fn missing_or_null_account_ids_are_missing_fields() {
+        let cases = [
+            (r#"{"Code":"Success"}"#, "AccountId"),
+            (r#"{"Code":"Success","AccountId":null}"#, "AccountId"),
+            (r#"{"Code":"Success","AccountId":""}"#, "AccountId"),
+            (
+                r#"{"Code":"Success","AccountId":123456789012}"#,
+                "AccountId",
+            ),
+            // Keys are case sensitive; the identity document's isn't the
+            // info's.
+            (r#"{"accountId":"123456789012"}"#, "AccountId"),
+            (r#"{"region":"us-east-1"}"#, "accountId"),
+            (r#"{"accountId":null}"#, "accountId"),
+            (r#"{"AccountId":"123456789012"}"#, "accountId"),
+        ];
+        for (document, key) in &cases {
+            assert_eq!(
+                parse_account_id(document, key),
+                Err(Error::MissingField(key)),
+                "{} in {}",
+                key,
+                document
+            );
+        }
+
+        assert!(matches!(
+            parse_account_id("not json", "AccountId"),
+            Err(Error::JsonError(_))
+        ));
+    }
+
+    #[test]
+    fn null_account_ids_fail_the_field() {
+        let mock = MockImds::start();
+        mock.set(
+            "meta-data/identity-credentials/ec2/info",
+            r#"{"Code":"Success","AccountId":null}"#,
+        );
+        let client = mock.client();
+        assert_eq!(
+            client.get_field(Field::AccountId),
+            Err(Error::Fetch {
+                field: Field::AccountId,
+                source: Box::new(Error::MissingField("AccountId")),
+            })
+        );
+
+        // Without the info, the identity document is read instead.
+        mock.remove("meta-data/identity-credentials/ec2/info");
+        mock.set(
+            "dynamic/instance-identity/document",
+            r#"{"region":"us-east-1"}"#,
+        );
+        assert_eq!(
+            client.get_field(Field::AccountId),
+            Err(Error::Fetch {
+                field: Field::AccountId,
+                source: Box::new(Error::MissingField("accountId")),
+            })
+        );
+    }
+
+    #[test]
+    fn redirects_are_not_followed() {
+        let mock = MockImds::start();
+        let target = MockImds::start();
+        let location = format!("{}/latest/meta-data/instance-id", target.endpoint());
+        mock.set_redirect("meta-data/instance-id", 301, location.clone());
+
+        let client = mock.client();
+        assert_eq!(
+            client.get_raw("instance-id"),
+            Err(Error::UnexpectedRedirect(location))
+        );
+        // The token was sent to the mock, but never to the target.
+        let sent = mock.requests();
+        assert!(sent
+            .last()
+            .unwrap()
+            .header("X-aws-ec2-metadata-token")
+            .is_some());
+        assert!(target.requests().is_empty());
+    }
+
+    #[test]
+    fn token_redirects_are_not_followed() {
+        let mock = MockImds::start();
+        let target = MockImds::start();
+        let location = format!("{}/latest/api/token", target.endpoint());
+        mock.set_redirect("/latest/api/token", 307, location.clone());
+
+        match mock.client().fetch_token() {
+            Err(Error::Token(e)) => assert_eq!(*e, Error::UnexpectedRedirect(location)),
+            result => panic!("{:?}", result),
+        }
+        assert!(target.requests().is_empty());
     }
 }
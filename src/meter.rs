@@ -0,0 +1,82 @@
+use crate::Result;
+#[cfg(feature = "metrics")]
+use crate::{metrics::*, ErrorKind};
+#[cfg(feature = "metrics")]
+use std::cell::Cell;
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+
+#[cfg(feature = "metrics")]
+thread_local! {
+    // The HTTP status of the response to the request being measured on this
+    // thread, if one arrived.
+    static STATUS: Cell<Option<u16>> = const { Cell::new(None) };
+}
+
+/// Run the request for `path`, counting it by outcome and recording how long
+/// it took, retries included.
+#[cfg(feature = "metrics")]
+pub(crate) fn measure<T, F>(path: &str, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    STATUS.with(|status| status.set(None));
+    let started = Instant::now();
+    let result = f();
+    let elapsed = started.elapsed();
+
+    let status = match (STATUS.with(Cell::take), &result) {
+        (Some(status), _) => status.to_string(),
+        (None, Err(e)) if e.kind() == ErrorKind::Timeout => "timeout".to_string(),
+        (None, _) => "error".to_string(),
+    };
+    ::metrics::counter!(REQUESTS_TOTAL, "path" => path.to_string(), "status" => status)
+        .increment(1);
+    ::metrics::histogram!(REQUEST_DURATION_SECONDS, "path" => path.to_string())
+        .record(elapsed.as_secs_f64());
+    result
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn measure<T, F>(_path: &str, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    f()
+}
+
+/// Record the HTTP status of the response to the request being measured.
+#[cfg(feature = "metrics")]
+pub(crate) fn record_status(status: u16) {
+    STATUS.with(|current| current.set(Some(status)));
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn record_status(_status: u16) {}
+
+/// Count a token served from the client's cache.
+#[cfg(feature = "metrics")]
+pub(crate) fn token_cache_hit() {
+    ::metrics::counter!(TOKEN_CACHE_HITS_TOTAL).increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn token_cache_hit() {}
+
+/// Count a token the client's cache couldn't serve.
+#[cfg(feature = "metrics")]
+pub(crate) fn token_cache_miss() {
+    ::metrics::counter!(TOKEN_CACHE_MISSES_TOTAL).increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn token_cache_miss() {}
+
+/// Count a new token from IMDS.
+#[cfg(feature = "metrics")]
+pub(crate) fn token_refreshed() {
+    ::metrics::counter!(TOKEN_REFRESHES_TOTAL).increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn token_refreshed() {}
@@ -0,0 +1,81 @@
+//! The names of the metrics the client records with the `metrics` crate,
+//! through whichever recorder is installed. Without one, recording is a
+//! no-op.
+//!
+//! - `ec2_metadata_requests_total{path, status}` (counter): requests to
+//!   IMDS, including token requests, by path and outcome. Each one counts
+//!   once, however many times it was retried. The status is the HTTP status
+//!   of the last response, or `timeout` or `error` if none arrived.
+//! - `ec2_metadata_request_duration_seconds{path}` (histogram): how long
+//!   requests took, retries included.
+//! - `ec2_metadata_token_refreshes_total` (counter): IMDSv2 tokens obtained
+//!   from IMDS.
+//! - `ec2_metadata_token_cache_hits_total` and
+//!   `ec2_metadata_token_cache_misses_total` (counters): whether the token a
+//!   request needed was already cached by the client. A miss is served by
+//!   the persisted token, if any, or by a refresh.
+//!
+//! Paths are those requested, e.g. `/latest/meta-data/instance-id`, and
+//! `/latest/api/token` for tokens. Raw getters like `get_raw` add a path
+//! per distinct argument.
+//!
+//! # Examples:
+//! ```
+//! use ec2_instance_metadata::metrics::*;
+//! use ec2_instance_metadata::MockImds;
+//! use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+//!
+//! let mock = MockImds::start();
+//! mock.set_status("meta-data/public-hostname", 404);
+//!
+//! let recorder = DebuggingRecorder::new();
+//! let snapshotter = recorder.snapshotter();
+//! metrics::with_local_recorder(&recorder, || {
+//!     let client = mock.client();
+//!     client.get().unwrap();
+//!     client.fetch_token().unwrap();
+//! });
+//!
+//! let snapshot = snapshotter.snapshot().into_vec();
+//! let value = |name: &str, labels: &[(&str, &str)]| {
+//!     snapshot
+//!         .iter()
+//!         .find(|(key, _, _, _)| {
+//!             let key = key.key();
+//!             let key_labels: Vec<_> = key.labels().map(|l| (l.key(), l.value())).collect();
+//!             key.name() == name && key_labels == labels
+//!         })
+//!         .map(|(_, _, _, value)| value)
+//! };
+//!
+//! let token = [("path", "/latest/api/token"), ("status", "200")];
+//! assert_eq!(value(REQUESTS_TOTAL, &token), Some(&DebugValue::Counter(1)));
+//! let instance_id = [("path", "/latest/meta-data/instance-id"), ("status", "200")];
+//! assert_eq!(value(REQUESTS_TOTAL, &instance_id), Some(&DebugValue::Counter(1)));
+//! let public_hostname = [("path", "/latest/meta-data/public-hostname"), ("status", "404")];
+//! assert_eq!(value(REQUESTS_TOTAL, &public_hostname), Some(&DebugValue::Counter(1)));
+//!
+//! match value(REQUEST_DURATION_SECONDS, &[("path", "/latest/meta-data/instance-id")]) {
+//!     Some(DebugValue::Histogram(durations)) => assert_eq!(durations.len(), 1),
+//!     other => panic!("{:?}", other),
+//! }
+//!
+//! assert_eq!(value(TOKEN_REFRESHES_TOTAL, &[]), Some(&DebugValue::Counter(1)));
+//! assert_eq!(value(TOKEN_CACHE_MISSES_TOTAL, &[]), Some(&DebugValue::Counter(1)));
+//! assert_eq!(value(TOKEN_CACHE_HITS_TOTAL, &[]), Some(&DebugValue::Counter(1)));
+//! ```
+
+/// Requests to IMDS, by `path` and `status`.
+pub const REQUESTS_TOTAL: &str = "ec2_metadata_requests_total";
+
+/// How long requests to IMDS took, in seconds, by `path`.
+pub const REQUEST_DURATION_SECONDS: &str = "ec2_metadata_request_duration_seconds";
+
+/// IMDSv2 tokens obtained from IMDS.
+pub const TOKEN_REFRESHES_TOTAL: &str = "ec2_metadata_token_refreshes_total";
+
+/// Tokens served from the client's cache.
+pub const TOKEN_CACHE_HITS_TOTAL: &str = "ec2_metadata_token_cache_hits_total";
+
+/// Tokens the client's cache couldn't serve.
+pub const TOKEN_CACHE_MISSES_TOTAL: &str = "ec2_metadata_token_cache_misses_total";
@@ -0,0 +1,160 @@
+use crate::{InstanceMetadata, InstanceMetadataClient, Result};
+use std::borrow::Cow;
+
+// OpenTelemetry's resource semantic conventions for cloud hosts.
+const CLOUD_PROVIDER: &str = "cloud.provider";
+const CLOUD_PLATFORM: &str = "cloud.platform";
+const CLOUD_ACCOUNT_ID: &str = "cloud.account.id";
+const CLOUD_REGION: &str = "cloud.region";
+const CLOUD_AVAILABILITY_ZONE: &str = "cloud.availability_zone";
+const HOST_ID: &str = "host.id";
+const HOST_TYPE: &str = "host.type";
+const HOST_NAME: &str = "host.name";
+const HOST_IMAGE_ID: &str = "host.image.id";
+
+/// A resource attribute's key and value.
+pub type ResourceAttribute = (Cow<'static, str>, Cow<'static, str>);
+
+impl InstanceMetadata {
+    /// The instance as OpenTelemetry resource attributes, keyed by the
+    /// semantic conventions: `cloud.provider` (`aws`), `cloud.platform`
+    /// (`aws_ec2`), `cloud.account.id`, `cloud.region`,
+    /// `cloud.availability_zone`, `host.id`, `host.type`, `host.name`, and
+    /// `host.image.id`. Unavailable values, such as a missing account id, are
+    /// left out rather than empty.
+    ///
+    /// This needs no OpenTelemetry dependency; the `opentelemetry` feature
+    /// adds `to_resource` and `Ec2MetadataResourceDetector`.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::MockImds;
+    /// use opentelemetry_semantic_conventions::resource;
+    ///
+    /// let mock = MockImds::start();
+    /// let attributes = mock.client().get_resource_attributes().unwrap();
+    /// let keys: Vec<&str> = attributes.iter().map(|(key, _)| key.as_ref()).collect();
+    /// assert_eq!(
+    ///     keys,
+    ///     [
+    ///         resource::CLOUD_PROVIDER,
+    ///         resource::CLOUD_PLATFORM,
+    ///         resource::CLOUD_ACCOUNT_ID,
+    ///         resource::CLOUD_REGION,
+    ///         resource::CLOUD_AVAILABILITY_ZONE,
+    ///         resource::HOST_ID,
+    ///         resource::HOST_TYPE,
+    ///         resource::HOST_NAME,
+    ///         resource::HOST_IMAGE_ID,
+    ///     ]
+    /// );
+    /// assert!(attributes.contains(&("host.id".into(), MockImds::INSTANCE_ID.into())));
+    ///
+    /// // An instance without an account id.
+    /// mock.set_status("meta-data/identity-credentials/ec2/info", 404);
+    /// mock.set_status("dynamic/instance-identity/document", 404);
+    /// let metadata = mock.client().get().unwrap();
+    /// assert!(metadata
+    ///     .resource_attributes()
+    ///     .iter()
+    ///     .all(|(key, _)| key != resource::CLOUD_ACCOUNT_ID));
+    /// ```
+    pub fn resource_attributes(&self) -> Vec<ResourceAttribute> {
+        let values: [(&'static str, Option<&str>); 9] = [
+            (CLOUD_PROVIDER, Some("aws")),
+            (CLOUD_PLATFORM, Some("aws_ec2")),
+            (CLOUD_ACCOUNT_ID, self.account_id.as_deref()),
+            (CLOUD_REGION, self.region.as_deref()),
+            (CLOUD_AVAILABILITY_ZONE, Some(&self.availability_zone)),
+            (HOST_ID, Some(&self.instance_id)),
+            (HOST_TYPE, Some(&self.instance_type)),
+            (HOST_NAME, Some(&self.hostname)),
+            (HOST_IMAGE_ID, Some(&self.ami_id)),
+        ];
+
+        values
+            .iter()
+            .filter_map(|(key, value)| match value.map(str::trim) {
+                Some(value) if !value.is_empty() => {
+                    Some((Cow::Borrowed(*key), Cow::Owned(value.to_string())))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The instance as an OpenTelemetry `Resource`, with the attributes of
+    /// `resource_attributes`.
+    #[cfg(feature = "opentelemetry")]
+    pub fn to_resource(&self) -> opentelemetry_sdk::Resource {
+        opentelemetry_sdk::Resource::builder_empty()
+            .with_attributes(
+                self.resource_attributes()
+                    .into_iter()
+                    .map(|(key, value)| opentelemetry::KeyValue::new(key, value)),
+            )
+            .build()
+    }
+}
+
+impl InstanceMetadataClient {
+    /// Get the instance metadata, as `get` does, as OpenTelemetry resource
+    /// attributes; see `InstanceMetadata::resource_attributes`.
+    pub fn get_resource_attributes(&self) -> Result<Vec<ResourceAttribute>> {
+        Ok(self.get()?.resource_attributes())
+    }
+}
+
+/// `Ec2MetadataResourceDetector` detects the OpenTelemetry resource of the
+/// instance, with the attributes of `InstanceMetadata::resource_attributes`.
+///
+/// Detection runs `InstanceMetadataClient::get`, following the client's
+/// timeouts and retries. If that fails, e.g. off EC2, the resource is empty,
+/// as detectors' are meant to be, and the error is logged.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::{Ec2MetadataResourceDetector, MockImds};
+/// use opentelemetry::Key;
+/// use opentelemetry_sdk::Resource;
+///
+/// let mock = MockImds::start();
+/// let resource = Resource::builder_empty()
+///     .with_detector(Box::new(Ec2MetadataResourceDetector::from_client(mock.client())))
+///     .build();
+/// assert_eq!(
+///     resource.get(&Key::from_static_str("host.id")).map(|id| id.to_string()),
+///     Some(MockImds::INSTANCE_ID.to_string())
+/// );
+/// ```
+#[cfg(feature = "opentelemetry")]
+#[derive(Debug, Clone, Default)]
+pub struct Ec2MetadataResourceDetector {
+    client: InstanceMetadataClient,
+}
+
+#[cfg(feature = "opentelemetry")]
+impl Ec2MetadataResourceDetector {
+    /// A detector using a default `InstanceMetadataClient`.
+    pub fn new() -> Ec2MetadataResourceDetector {
+        Ec2MetadataResourceDetector::default()
+    }
+
+    /// A detector using `client`, sharing its configuration and token.
+    pub fn from_client(client: InstanceMetadataClient) -> Ec2MetadataResourceDetector {
+        Ec2MetadataResourceDetector { client }
+    }
+}
+
+#[cfg(feature = "opentelemetry")]
+impl opentelemetry_sdk::resource::ResourceDetector for Ec2MetadataResourceDetector {
+    fn detect(&self) -> opentelemetry_sdk::Resource {
+        match self.client.get() {
+            Ok(metadata) => metadata.to_resource(),
+            Err(e) => {
+                log::warn!("couldn't detect the EC2 resource: {}", e.full_message());
+                opentelemetry_sdk::Resource::builder_empty().build()
+            }
+        }
+    }
+}
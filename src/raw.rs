@@ -0,0 +1,406 @@
+use crate::{Error, InstanceMetadata, InstanceMetadataClient, Result};
+use std::collections::BTreeMap;
+
+impl InstanceMetadataClient {
+    /// Get the raw value at `path` under `meta-data/`, e.g. `"placement/group-name"`
+    /// or `"network/interfaces/macs/"` for a listing. Returns `None` if IMDS has
+    /// nothing at that path.
+    ///
+    /// Paths are validated before any request is made: absolute URLs, `.` and
+    /// `..` segments, empty segments, percent-encoding, query strings, and
+    /// whitespace or control characters are rejected with `Error::InvalidPath`.
+    /// A single leading `/` is ignored.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::{Error, InstanceMetadataClient};
+    /// let client = InstanceMetadataClient::new();
+    ///
+    /// for path in &["../api/token", "http://evil/", "a//b", "kernel-id\n", "%2e%2e/x", "//x"] {
+    ///     assert!(matches!(client.get_raw(path), Err(Error::InvalidPath(_))));
+    /// }
+    /// ```
+    pub fn get_raw(&self, path: &str) -> Result<Option<String>> {
+        let path = validate_path(path)?;
+        let token = self.get_token()?;
+        self.fetch_path(&token, &format!("meta-data/{}", path))
+    }
+
+    /// Get the raw value at `path` under `dynamic/`, e.g.
+    /// `"instance-identity/document"`. Paths are validated as for `get_raw`.
+    pub fn get_dynamic(&self, path: &str) -> Result<Option<String>> {
+        let path = validate_path(path)?;
+        let token = self.get_token()?;
+        self.fetch_path(&token, &format!("dynamic/{}", path))
+    }
+
+    /// Get the instance metadata like `get`, plus the raw values at `paths`
+    /// under `meta-data/`, which land in `extras` keyed by path. Paths IMDS
+    /// has nothing at are left out. Failing to fetch an extra doesn't fail the
+    /// call; the failures are reported in `errors`.
+    ///
+    /// The client's snapshot, see `cached`, doesn't include extras.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// let client = ec2_instance_metadata::InstanceMetadataClient::new();
+    /// let result = client
+    ///     .get_with_extras(&["placement/group-name", "kernel-id"])
+    ///     .expect("Couldn't get the instance metadata.");
+    /// if let Some(group) = result.metadata.extras.get("placement/group-name") {
+    ///     println!("placement group: {}", group);
+    /// }
+    /// for (path, error) in &result.errors {
+    ///     eprintln!("{}: {}", path, error);
+    /// }
+    /// ```
+    pub fn get_with_extras(&self, paths: &[&str]) -> Result<WithExtras> {
+        let mut metadata = self.get()?;
+        let mut errors = Vec::new();
+
+        for path in paths {
+            match self.get_raw(path) {
+                Ok(Some(value)) => {
+                    metadata
+                        .extras
+                        .insert(path.to_string(), value.trim().to_string());
+                }
+                Ok(None) => {}
+                Err(error) => errors.push((path.to_string(), error)),
+            }
+        }
+
+        Ok(WithExtras { metadata, errors })
+    }
+
+    /// Walk the whole `meta-data/` tree, getting every value in it. See
+    /// `get_all_under`.
+    pub fn get_all(&self) -> Result<BTreeMap<String, Result<String>>> {
+        self.get_all_under("")
+    }
+
+    /// Walk the `meta-data/` tree under `prefix`, e.g. `"network/"`, getting
+    /// every value in it, keyed by path relative to `meta-data/`.
+    ///
+    /// A value or listing that fails to fetch doesn't fail the walk; its path
+    /// maps to the error instead, with listings keyed by their path with a
+    /// trailing `/`. Only failing to list `prefix` itself fails the call, and
+    /// an empty map means IMDS has nothing there. `prefix` is validated as
+    /// for `get_raw`. This makes a request per path, and the tree includes
+    /// the instance's IAM credentials.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// let client = ec2_instance_metadata::InstanceMetadataClient::new();
+    /// let tree = client.get_all_under("placement/").expect("Couldn't list placement/.");
+    /// for (path, value) in &tree {
+    ///     match value {
+    ///         Ok(value) => println!("{}: {}", path, value),
+    ///         Err(error) => println!("{}: failed: {}", path, error),
+    ///     }
+    /// }
+    /// ```
+    pub fn get_all_under(&self, prefix: &str) -> Result<BTreeMap<String, Result<String>>> {
+        let prefix = validate_path(prefix)?;
+        let prefix = if prefix.is_empty() || prefix.ends_with('/') {
+            prefix.to_string()
+        } else {
+            format!("{}/", prefix)
+        };
+
+        let mut tree = BTreeMap::new();
+        let mut listings = vec![prefix.clone()];
+
+        while let Some(listing) = listings.pop() {
+            let entries = match self.get_raw(&listing) {
+                Ok(entries) => entries.unwrap_or_default(),
+                Err(error) if listing == prefix => return Err(error),
+                Err(error) => {
+                    tree.insert(listing, Err(error));
+                    continue;
+                }
+            };
+
+            for entry in entries.lines().map(str::trim).filter(|e| !e.is_empty()) {
+                // public-keys/ lists its keys as `0=name`, under `0/`.
+                let path = match entry.split_once('=') {
+                    Some((index, _)) => format!("{}{}/", listing, index),
+                    None => format!("{}{}", listing, entry),
+                };
+                if path.ends_with('/') {
+                    listings.push(path);
+                    continue;
+                }
+                match self.get_raw(&path) {
+                    Ok(Some(value)) => {
+                        tree.insert(path, Ok(value));
+                    }
+                    Ok(None) => {}
+                    Err(error) => {
+                        tree.insert(path, Err(error));
+                    }
+                }
+            }
+        }
+
+        Ok(tree)
+    }
+
+    /// Get the user data the instance was launched with, or `None` if it has
+    /// none. User data is returned as bytes since it's often compressed or
+    /// otherwise binary. User data over the client's `max_user_data_size`
+    /// fails with `Error::ResponseTooLarge`.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// let client = ec2_instance_metadata::InstanceMetadataClient::new();
+    /// if let Some(user_data) = client.get_user_data().expect("Couldn't get user data.") {
+    ///     println!("{} bytes of user data", user_data.len());
+    /// }
+    /// ```
+    pub fn get_user_data(&self) -> Result<Option<Vec<u8>>> {
+        let token = self.get_token()?;
+        self.fetch_absolute_bytes(
+            &token,
+            &format!("/{}/user-data", self.api_version),
+            self.max_user_data_size,
+        )
+    }
+}
+
+/// `WithExtras` is the result of `InstanceMetadataClient::get_with_extras`.
+#[derive(Debug, Clone)]
+pub struct WithExtras {
+    /// The metadata, with the extras that could be fetched
+    pub metadata: InstanceMetadata,
+
+    /// The extra paths that couldn't be fetched, and why
+    pub errors: Vec<(String, Error)>,
+}
+
+/// Check that `path` is a plain relative metadata path, returning it without
+/// any leading `/`.
+fn validate_path(path: &str) -> Result<&str> {
+    let invalid = || Error::InvalidPath(path.to_string());
+
+    let relative = path.strip_prefix('/').unwrap_or(path);
+
+    if relative.starts_with('/') || relative.contains("://") {
+        return Err(invalid());
+    }
+
+    // Metadata paths are ASCII. Colons are allowed since MAC addresses
+    // appear in network interface paths.
+    let allowed = |c: char| c.is_ascii_graphic() && !"%?#\\".contains(c);
+    if !relative.chars().all(allowed) {
+        return Err(invalid());
+    }
+
+    // A trailing slash requests a listing; other empty segments aren't valid.
+    let trimmed = relative.strip_suffix('/').unwrap_or(relative);
+    if !trimmed.is_empty()
+        && trimmed
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+    {
+        return Err(invalid());
+    }
+
+    Ok(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_path;
+    use crate::{Error, InstanceMetadataClient, InstanceMetadataClientBuilder, MockImds};
+
+    /// Segments a valid path is built from, MAC addresses included.
+    const VALID_SEGMENTS: &[&str] = &[
+        "network",
+        "interfaces",
+        "macs",
+        "0e:49:61:0f:c3:11",
+        "vpc-ipv4-cidr-blocks",
+        "iam",
+        "security-credentials",
+        "my_role.v2",
+        "a",
+        "...",
+        "..a",
+        "a..",
+    ];
+
+    /// Segments that make any path they're in invalid.
+    const INVALID_SEGMENTS: &[&str] = &[".", "..", "%2e%2e", "%2F"];
+
+    /// Characters that make any segment they're in invalid.
+    const INVALID_CHARS: &[char] = &[
+        '%', '?', '#', '\\', ' ', '\t', '\n', '\r', '\0', '\x07', '\x1b', '\x7f', '\u{a0}',
+        '\u{2028}', 'é', '\u{feff}',
+    ];
+
+    /// Every path of up to two of `VALID_SEGMENTS`, bare, with a leading `/`,
+    /// and with a trailing `/`.
+    fn valid_paths() -> Vec<String> {
+        let mut bases = vec![Vec::new()];
+        for first in VALID_SEGMENTS {
+            bases.push(vec![*first]);
+            for second in VALID_SEGMENTS {
+                bases.push(vec![*first, *second]);
+            }
+        }
+        let mut paths = Vec::new();
+        for base in bases {
+            let path = base.join("/");
+            for (leading, trailing) in &[("", ""), ("/", ""), ("", "/"), ("/", "/")] {
+                if !path.is_empty() || trailing.is_empty() {
+                    paths.push(format!("{}{}{}", leading, path, trailing));
+                }
+            }
+        }
+        paths
+    }
+
+    /// `segment` at the start, middle, and end of valid paths, bare and with a
+    /// leading or trailing `/`.
+    fn paths_with(segment: &str) -> Vec<String> {
+        let mut paths = Vec::new();
+        for (before, after) in &[
+            ("", ""),
+            ("macs/", ""),
+            ("", "/vpc-id"),
+            ("network/interfaces/", "/0e:49:61:0f:c3:11"),
+        ] {
+            let path = format!("{}{}{}", before, segment, after);
+            for (leading, trailing) in &[("", ""), ("/", ""), ("", "/")] {
+                paths.push(format!("{}{}{}", leading, path, trailing));
+            }
+        }
+        paths
+    }
+
+    #[test]
+    fn accepts_valid_paths() {
+        for path in valid_paths() {
+            let relative = path.strip_prefix('/').unwrap_or(&path);
+            assert_eq!(validate_path(&path).ok(), Some(relative), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_segments() {
+        for segment in INVALID_SEGMENTS {
+            for path in paths_with(segment) {
+                assert_eq!(
+                    validate_path(&path),
+                    Err(Error::InvalidPath(path.clone())),
+                    "{:?}",
+                    path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_characters() {
+        for c in INVALID_CHARS {
+            for segment in &[
+                c.to_string(),
+                format!("{}a", c),
+                format!("a{}b", c),
+                format!("a{}", c),
+            ] {
+                for path in paths_with(segment) {
+                    assert!(validate_path(&path).is_err(), "{:?}", path);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_empty_segments() {
+        for path in valid_paths() {
+            let path = path.trim_matches('/');
+            if path.is_empty() {
+                continue;
+            }
+            for bad in &[
+                format!("//{}", path),
+                format!("{}//", path),
+                format!("{}//x", path),
+                format!("x//{}", path),
+                "//".to_string(),
+            ] {
+                assert!(validate_path(bad).is_err(), "{:?}", bad);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_urls() {
+        for scheme in &["http", "https", "file", "gopher", "a"] {
+            for path in paths_with(&format!("{}://evil.example", scheme)) {
+                assert!(validate_path(&path).is_err(), "{:?}", path);
+            }
+        }
+    }
+
+    #[test]
+    fn rejected_paths_send_nothing() {
+        let mock = MockImds::start();
+        let client = mock.client();
+        for path in paths_with("..").iter().chain(&paths_with("http://evil")) {
+            assert_eq!(client.get_raw(path), Err(Error::InvalidPath(path.clone())));
+            assert_eq!(
+                client.get_dynamic(path),
+                Err(Error::InvalidPath(path.clone()))
+            );
+        }
+        assert!(mock.requests().is_empty());
+    }
+
+    #[test]
+    fn user_data_has_its_own_size_limit() {
+        let mock = MockImds::start();
+        mock.set("user-data", "x".repeat(1024));
+        mock.set("meta-data/tags/instance/Name", "y".repeat(1024));
+
+        let client = InstanceMetadataClient::builder()
+            .endpoint(mock.endpoint())
+            .max_body_size(512)
+            .build();
+        assert_eq!(client.get_user_data().unwrap().map(|d| d.len()), Some(1024));
+        assert_eq!(
+            client.get_raw("tags/instance/Name"),
+            Err(Error::ResponseTooLarge(512))
+        );
+    }
+
+    #[test]
+    fn oversize_user_data_is_rejected() {
+        let mock = MockImds::start();
+        mock.set("user-data", "x".repeat(1025));
+
+        let client = InstanceMetadataClient::builder()
+            .endpoint(mock.endpoint())
+            .max_user_data_size(1024)
+            .build();
+        assert_eq!(client.get_user_data(), Err(Error::ResponseTooLarge(1024)));
+
+        mock.set("user-data", "x".repeat(1024));
+        assert_eq!(client.get_user_data().unwrap().map(|d| d.len()), Some(1024));
+    }
+
+    #[test]
+    fn user_data_defaults_to_64_kib() {
+        let mock = MockImds::start();
+        mock.set("user-data", "x".repeat(64 * 1024 + 1));
+
+        assert_eq!(
+            mock.client().get_user_data(),
+            Err(Error::ResponseTooLarge(
+                InstanceMetadataClientBuilder::DEFAULT_MAX_USER_DATA_SIZE
+            ))
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{write_lines, InstanceMetadata};
+
+/// `RedactionOptions` chooses which fields `InstanceMetadata::redacted_with`
+/// masks. The default masks the account id and hostnames, leaving the region,
+/// zone, and instance type intact.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::{InstanceMetadata, RedactionOptions};
+///
+/// let options = RedactionOptions {
+///     instance_id: true,
+///     hostnames: false,
+///     ..RedactionOptions::default()
+/// };
+/// let logged = InstanceMetadata::builder().build().redacted_with(options).to_string();
+/// assert!(logged.contains("instance_id:       <redacted>"));
+/// assert!(logged.contains("hostname:          ip-10-0-0-1.ec2.internal"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RedactionOptions {
+    /// Show only the last 4 digits of the account id
+    pub account_id: bool,
+
+    /// Hide the hostnames and public hostname
+    pub hostnames: bool,
+
+    /// Hide the instance id
+    pub instance_id: bool,
+
+    /// Hide the values in `extras`, keeping their paths
+    pub extras: bool,
+}
+
+impl Default for RedactionOptions {
+    fn default() -> Self {
+        Self {
+            account_id: true,
+            hostnames: true,
+            instance_id: false,
+            extras: false,
+        }
+    }
+}
+
+/// `RedactedInstanceMetadata` displays an `InstanceMetadata` like its own
+/// `Display`, with the fields chosen by `RedactionOptions` masked. Its `Debug`
+/// output is the same, so it can't leak the masked values either way.
+#[derive(Clone, Copy)]
+pub struct RedactedInstanceMetadata<'a> {
+    metadata: &'a InstanceMetadata,
+    options: RedactionOptions,
+}
+
+const REDACTED: &str = "<redacted>";
+
+impl<'a> RedactedInstanceMetadata<'a> {
+    pub(crate) fn new(metadata: &'a InstanceMetadata, options: RedactionOptions) -> Self {
+        Self { metadata, options }
+    }
+}
+
+impl std::fmt::Display for RedactedInstanceMetadata<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let account_id = self.metadata.account_id.as_deref().map(mask_account_id);
+
+        let mut entries = self.metadata.entries().to_vec();
+        for (key, value) in entries.iter_mut() {
+            let redact = match *key {
+                "account_id" if self.options.account_id => {
+                    *value = account_id.as_deref();
+                    continue;
+                }
+                "local_hostname" | "hostname" | "public_hostname" => self.options.hostnames,
+                "instance_id" => self.options.instance_id,
+                _ => false,
+            };
+            if redact && value.is_some() {
+                *value = Some(REDACTED);
+            }
+        }
+
+        let mut extras = Vec::new();
+        for (path, value) in &self.metadata.extras {
+            let value = if self.options.extras { REDACTED } else { value };
+            extras.push((path.as_str(), Some(value)));
+        }
+        entries.extend(extras);
+
+        write_lines(f, &entries, self.metadata.fetched_at)
+    }
+}
+
+impl std::fmt::Debug for RedactedInstanceMetadata<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+/// Mask all but the last 4 characters of `account_id`.
+fn mask_account_id(account_id: &str) -> String {
+    let len = account_id.chars().count();
+    account_id
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if i + 4 < len { '*' } else { c })
+        .collect()
+}
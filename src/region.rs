@@ -0,0 +1,409 @@
+use crate::coalesce::lock;
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// Region names added at runtime with `register_regions`.
+static REGISTERED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// `Region` is an AWS region. Regions this crate doesn't know about, such as
+/// ones launched after it was released, parse as `Region::Other`.
+///
+/// New regions are added as variants over time, so a region that parses as
+/// `Other` today may get its own variant later; match on `as_str()` if that
+/// matters.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::{Partition, Region};
+///
+/// let region: Region = "cn-north-1".parse().unwrap();
+/// assert_eq!(region, Region::CnNorth1);
+/// assert_eq!(region.partition(), Partition::AwsCn);
+///
+/// let region: Region = "us-gov-west-1".parse().unwrap();
+/// assert_eq!(region, Region::UsGovWest1);
+/// assert_eq!(region.partition(), Partition::AwsUsGov);
+///
+/// let region: Region = "us-isob-east-1".parse().unwrap();
+/// assert_eq!(region, Region::UsIsobEast1);
+/// assert_eq!(region.partition(), Partition::AwsIsoB);
+///
+/// // Newer regions are placed in a partition by their name's prefix.
+/// let region: Region = "us-iso-south-1".parse().unwrap();
+/// assert_eq!(region, Region::Other("us-iso-south-1".to_string()));
+/// assert_eq!(region.partition(), Partition::AwsIso);
+///
+/// let region: Region = "xx-example-1".parse().unwrap();
+/// assert_eq!(region, Region::Other("xx-example-1".to_string()));
+/// assert_eq!(region.as_str(), "xx-example-1");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Region {
+    ApSouth1,
+    EuWest3,
+    EuNorth1,
+    EuWest2,
+    EuWest1,
+    ApNortheast3,
+    ApNortheast2,
+    ApNortheast1,
+    SaEast1,
+    CaCentral1,
+    ApSoutheast1,
+    ApSoutheast2,
+    EuCentral1,
+    UsEast1,
+    UsEast2,
+    UsWest1,
+    UsWest2,
+    CnNorth1,
+    CnNorthwest1,
+    AfSouth1,
+    ApEast1,
+    ApEast2,
+    ApSouth2,
+    ApSoutheast3,
+    ApSoutheast4,
+    ApSoutheast5,
+    ApSoutheast7,
+    CaWest1,
+    EuCentral2,
+    EuSouth1,
+    EuSouth2,
+    IlCentral1,
+    MeCentral1,
+    MeSouth1,
+    MxCentral1,
+    UsGovWest1,
+    UsGovEast1,
+    UsIsoEast1,
+    UsIsoWest1,
+    UsIsobEast1,
+    Other(String), // A region this crate doesn't know about.
+}
+
+impl Region {
+    /// Every region this crate knows about, i.e. all but `Other`.
+    pub const KNOWN: &'static [Region] = &[
+        Region::ApSouth1,
+        Region::EuWest3,
+        Region::EuNorth1,
+        Region::EuWest2,
+        Region::EuWest1,
+        Region::ApNortheast3,
+        Region::ApNortheast2,
+        Region::ApNortheast1,
+        Region::SaEast1,
+        Region::CaCentral1,
+        Region::ApSoutheast1,
+        Region::ApSoutheast2,
+        Region::EuCentral1,
+        Region::UsEast1,
+        Region::UsEast2,
+        Region::UsWest1,
+        Region::UsWest2,
+        Region::CnNorth1,
+        Region::CnNorthwest1,
+        Region::AfSouth1,
+        Region::ApEast1,
+        Region::ApEast2,
+        Region::ApSouth2,
+        Region::ApSoutheast3,
+        Region::ApSoutheast4,
+        Region::ApSoutheast5,
+        Region::ApSoutheast7,
+        Region::CaWest1,
+        Region::EuCentral2,
+        Region::EuSouth1,
+        Region::EuSouth2,
+        Region::IlCentral1,
+        Region::MeCentral1,
+        Region::MeSouth1,
+        Region::MxCentral1,
+        Region::UsGovWest1,
+        Region::UsGovEast1,
+        Region::UsIsoEast1,
+        Region::UsIsoWest1,
+        Region::UsIsobEast1,
+    ];
+
+    /// The region's name, e.g. `"us-east-1"`.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Region::ApSouth1 => "ap-south-1",
+            Region::EuWest3 => "eu-west-3",
+            Region::EuNorth1 => "eu-north-1",
+            Region::EuWest2 => "eu-west-2",
+            Region::EuWest1 => "eu-west-1",
+            Region::ApNortheast3 => "ap-northeast-3",
+            Region::ApNortheast2 => "ap-northeast-2",
+            Region::ApNortheast1 => "ap-northeast-1",
+            Region::SaEast1 => "sa-east-1",
+            Region::CaCentral1 => "ca-central-1",
+            Region::ApSoutheast1 => "ap-southeast-1",
+            Region::ApSoutheast2 => "ap-southeast-2",
+            Region::EuCentral1 => "eu-central-1",
+            Region::UsEast1 => "us-east-1",
+            Region::UsEast2 => "us-east-2",
+            Region::UsWest1 => "us-west-1",
+            Region::UsWest2 => "us-west-2",
+            Region::CnNorth1 => "cn-north-1",
+            Region::CnNorthwest1 => "cn-northwest-1",
+            Region::AfSouth1 => "af-south-1",
+            Region::ApEast1 => "ap-east-1",
+            Region::ApEast2 => "ap-east-2",
+            Region::ApSouth2 => "ap-south-2",
+            Region::ApSoutheast3 => "ap-southeast-3",
+            Region::ApSoutheast4 => "ap-southeast-4",
+            Region::ApSoutheast5 => "ap-southeast-5",
+            Region::ApSoutheast7 => "ap-southeast-7",
+            Region::CaWest1 => "ca-west-1",
+            Region::EuCentral2 => "eu-central-2",
+            Region::EuSouth1 => "eu-south-1",
+            Region::EuSouth2 => "eu-south-2",
+            Region::IlCentral1 => "il-central-1",
+            Region::MeCentral1 => "me-central-1",
+            Region::MeSouth1 => "me-south-1",
+            Region::MxCentral1 => "mx-central-1",
+            Region::UsGovWest1 => "us-gov-west-1",
+            Region::UsGovEast1 => "us-gov-east-1",
+            Region::UsIsoEast1 => "us-iso-east-1",
+            Region::UsIsoWest1 => "us-iso-west-1",
+            Region::UsIsobEast1 => "us-isob-east-1",
+            Region::Other(name) => name,
+        }
+    }
+
+    /// The partition the region belongs to. For `Region::Other` it's guessed
+    /// from the name's prefix, and is `Partition::Unknown` if there's no
+    /// telling.
+    pub fn partition(&self) -> Partition {
+        match self {
+            Region::CnNorth1 | Region::CnNorthwest1 => Partition::AwsCn,
+            Region::UsGovWest1 | Region::UsGovEast1 => Partition::AwsUsGov,
+            Region::UsIsoEast1 | Region::UsIsoWest1 => Partition::AwsIso,
+            Region::UsIsobEast1 => Partition::AwsIsoB,
+            Region::Other(name) if name.starts_with("us-gov-") => Partition::AwsUsGov,
+            Region::Other(name) if name.starts_with("us-iso-") => Partition::AwsIso,
+            Region::Other(name) if name.starts_with("us-isob-") => Partition::AwsIsoB,
+            Region::Other(name) if name.starts_with("cn-") => Partition::AwsCn,
+            Region::Other(_) => Partition::Unknown,
+            _ => Partition::Aws,
+        }
+    }
+}
+
+impl Region {
+    /// The known region `availability_zone` is in, or `None` if it isn't in
+    /// one. The zone must be the region's name followed by a zone letter or,
+    /// for Local Zones, a `-` and the zone's name, so `ap-southeast-10a`
+    /// doesn't count as a zone in `ap-southeast-1`.
+    ///
+    /// Regions added with `register_regions` count as known, and are
+    /// reported as `Region::Other`.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::Region;
+    ///
+    /// let zones = [
+    ///     ("ap-south-1a", Region::ApSouth1),
+    ///     ("eu-west-3b", Region::EuWest3),
+    ///     ("eu-north-1c", Region::EuNorth1),
+    ///     ("eu-west-2a", Region::EuWest2),
+    ///     ("eu-west-1b", Region::EuWest1),
+    ///     ("ap-northeast-3a", Region::ApNortheast3),
+    ///     ("ap-northeast-2d", Region::ApNortheast2),
+    ///     ("ap-northeast-1c", Region::ApNortheast1),
+    ///     ("sa-east-1a", Region::SaEast1),
+    ///     ("ca-central-1b", Region::CaCentral1),
+    ///     ("ap-southeast-1a", Region::ApSoutheast1),
+    ///     ("ap-southeast-2c", Region::ApSoutheast2),
+    ///     ("eu-central-1a", Region::EuCentral1),
+    ///     ("us-east-1f", Region::UsEast1),
+    ///     ("us-east-2a", Region::UsEast2),
+    ///     ("us-west-1b", Region::UsWest1),
+    ///     ("us-west-2d", Region::UsWest2),
+    ///     ("cn-north-1a", Region::CnNorth1),
+    ///     ("cn-northwest-1b", Region::CnNorthwest1),
+    ///     ("af-south-1a", Region::AfSouth1),
+    ///     ("ap-east-1b", Region::ApEast1),
+    ///     ("ap-east-2a", Region::ApEast2),
+    ///     ("ap-south-2a", Region::ApSouth2),
+    ///     ("ap-southeast-3b", Region::ApSoutheast3),
+    ///     ("ap-southeast-4a", Region::ApSoutheast4),
+    ///     ("ap-southeast-5a", Region::ApSoutheast5),
+    ///     ("ap-southeast-7c", Region::ApSoutheast7),
+    ///     ("ca-west-1a", Region::CaWest1),
+    ///     ("eu-central-2b", Region::EuCentral2),
+    ///     ("eu-south-1a", Region::EuSouth1),
+    ///     ("eu-south-2c", Region::EuSouth2),
+    ///     ("il-central-1a", Region::IlCentral1),
+    ///     ("me-central-1b", Region::MeCentral1),
+    ///     ("me-south-1a", Region::MeSouth1),
+    ///     ("mx-central-1a", Region::MxCentral1),
+    ///     ("us-gov-west-1a", Region::UsGovWest1),
+    ///     ("us-gov-east-1b", Region::UsGovEast1),
+    ///     ("us-iso-east-1a", Region::UsIsoEast1),
+    ///     ("us-iso-west-1b", Region::UsIsoWest1),
+    ///     ("us-isob-east-1a", Region::UsIsobEast1),
+    /// ];
+    /// for (zone, region) in &zones {
+    ///     assert_eq!(Region::from_availability_zone(zone).as_ref(), Some(region), "{}", zone);
+    /// }
+    ///
+    /// // Every known region is covered above.
+    /// assert_eq!(zones.len(), Region::KNOWN.len());
+    ///
+    /// assert_eq!(Region::from_availability_zone("us-west-2-lax-1a"), Some(Region::UsWest2));
+    /// assert_eq!(Region::from_availability_zone("ap-southeast-10a"), None);
+    /// assert_eq!(Region::from_availability_zone("us-east-1"), None);
+    /// assert_eq!(Region::from_availability_zone("xx-example-1a"), None);
+    /// ```
+    pub fn from_availability_zone(availability_zone: &str) -> Option<Region> {
+        let in_region = |region: &str| match availability_zone.strip_prefix(region) {
+            Some(rest) => rest.starts_with(|c: char| c.is_ascii_lowercase() || c == '-'),
+            None => false,
+        };
+
+        Region::KNOWN
+            .iter()
+            .find(|region| in_region(region.as_str()))
+            .cloned()
+            .or_else(|| {
+                lock(&REGISTERED)
+                    .iter()
+                    .find(|region| in_region(region))
+                    .map(|region| Region::Other(region.clone()))
+            })
+    }
+}
+
+/// Add `regions` to the registered ones, skipping any already known.
+pub(crate) fn register(regions: &[&str]) {
+    let mut registered = lock(&REGISTERED);
+    for region in regions {
+        let known = Region::KNOWN.iter().any(|known| known.as_str() == *region);
+        if !known && !registered.iter().any(|registered| registered == region) {
+            registered.push(region.to_string());
+        }
+    }
+}
+
+/// Whether `region` was added with `register_regions`.
+pub(crate) fn is_registered(region: &str) -> bool {
+    lock(&REGISTERED)
+        .iter()
+        .any(|registered| registered == region)
+}
+
+impl FromStr for Region {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Region::KNOWN
+            .iter()
+            .find(|region| region.as_str() == s)
+            .cloned()
+            .unwrap_or_else(|| Region::Other(s.to_string())))
+    }
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// `Partition` is the group of AWS regions a region belongs to, which
+/// determines its ARN prefix and service endpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Partition {
+    /// The commercial regions, `aws`
+    Aws,
+
+    /// The China regions, `aws-cn`
+    AwsCn,
+
+    /// The AWS GovCloud (US) regions, `aws-us-gov`
+    AwsUsGov,
+
+    /// The US ISO East and West regions, `aws-iso`
+    AwsIso,
+
+    /// The US ISOB East region, `aws-iso-b`
+    AwsIsoB,
+
+    /// A region this crate doesn't know the partition of
+    Unknown,
+}
+
+impl Partition {
+    /// The partition's name as used in ARNs, e.g. `"aws-cn"`. `"unknown"` for
+    /// `Partition::Unknown`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Partition::Aws => "aws",
+            Partition::AwsCn => "aws-cn",
+            Partition::AwsUsGov => "aws-us-gov",
+            Partition::AwsIso => "aws-iso",
+            Partition::AwsIsoB => "aws-iso-b",
+            Partition::Unknown => "unknown",
+        }
+    }
+
+    /// The partition named `name`, as IMDS's `services/partition` reports
+    /// it. `Partition::Unknown` for names this crate doesn't know.
+    pub(crate) fn from_name(name: &str) -> Partition {
+        [
+            Partition::Aws,
+            Partition::AwsCn,
+            Partition::AwsUsGov,
+            Partition::AwsIso,
+            Partition::AwsIsoB,
+        ]
+        .iter()
+        .copied()
+        .find(|partition| partition.as_str() == name)
+        .unwrap_or(Partition::Unknown)
+    }
+
+    /// The DNS suffix of the partition's service endpoints, as IMDS's
+    /// `services/domain` reports it. GovCloud shares the commercial suffix.
+    /// `None` for `Partition::Unknown`. On an instance, prefer
+    /// `InstanceMetadataClient::get_dns_suffix`, which asks IMDS first.
+    ///
+    /// # Examples:
+    /// ```
+    /// use ec2_instance_metadata::Partition;
+    ///
+    /// let endpoint = |service, region, partition: Partition| {
+    ///     format!("https://{}.{}.{}", service, region, partition.dns_suffix().unwrap())
+    /// };
+    /// assert_eq!(endpoint("s3", "cn-north-1", Partition::AwsCn), "https://s3.cn-north-1.amazonaws.com.cn");
+    /// assert_eq!(endpoint("sts", "us-gov-west-1", Partition::AwsUsGov), "https://sts.us-gov-west-1.amazonaws.com");
+    ///
+    /// assert_eq!(Partition::Aws.dns_suffix(), Some("amazonaws.com"));
+    /// assert_eq!(Partition::AwsIso.dns_suffix(), Some("c2s.ic.gov"));
+    /// assert_eq!(Partition::AwsIsoB.dns_suffix(), Some("sc2s.sgov.gov"));
+    /// assert_eq!(Partition::Unknown.dns_suffix(), None);
+    /// ```
+    pub fn dns_suffix(self) -> Option<&'static str> {
+        match self {
+            Partition::Aws | Partition::AwsUsGov => Some("amazonaws.com"),
+            Partition::AwsCn => Some("amazonaws.com.cn"),
+            Partition::AwsIso => Some("c2s.ic.gov"),
+            Partition::AwsIsoB => Some("sc2s.sgov.gov"),
+            Partition::Unknown => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Partition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,193 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// `FailureKind` classifies a failed request so a `RetryPolicy` can decide
+/// whether it's worth trying again.
+///
+/// New kinds may be added, so matches need a wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FailureKind {
+    /// The connection or response didn't complete in time.
+    Timeout,
+
+    /// Nothing is listening or there's no route to IMDS, typically because
+    /// the host isn't on EC2.
+    ConnectionRefused,
+
+    /// The connection was reset or aborted mid-request.
+    ConnectionReset,
+
+    /// IMDS answered with a 5xx status.
+    ServerError(u16),
+
+    /// IMDS answered with 429 Too Many Requests.
+    Throttled,
+
+    /// Any other transport failure.
+    Other,
+}
+
+impl FailureKind {
+    pub(crate) fn from_transport(transport: &ureq::Transport) -> FailureKind {
+        use std::error::Error;
+
+        transport
+            .source()
+            .and_then(|source| source.downcast_ref::<std::io::Error>())
+            .map_or(FailureKind::Other, FailureKind::from_io)
+    }
+
+    pub(crate) fn from_io(error: &std::io::Error) -> FailureKind {
+        match error.kind() {
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => FailureKind::Timeout,
+            std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::HostUnreachable
+            | std::io::ErrorKind::NetworkUnreachable => FailureKind::ConnectionRefused,
+            std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::BrokenPipe => FailureKind::ConnectionReset,
+            _ => FailureKind::Other,
+        }
+    }
+
+    pub(crate) fn from_status(status: u16) -> Option<FailureKind> {
+        match status {
+            429 => Some(FailureKind::Throttled),
+            500..=599 => Some(FailureKind::ServerError(status)),
+            _ => None,
+        }
+    }
+}
+
+/// What a `RetryPolicy` wants done after a failed attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Try again after sleeping for the given duration.
+    Retry(Duration),
+
+    /// Stop and report the failure.
+    GiveUp,
+}
+
+/// `RetryPolicy` decides whether, and when, a failed IMDS request is retried.
+///
+/// `attempt` is the number of attempts made so far, starting at 1.
+///
+/// # Examples:
+/// ```
+/// use std::time::Duration;
+/// use ec2_instance_metadata::{FailureKind, RetryDecision, RetryPolicy};
+///
+/// /// Retry timeouts only, never 5xx.
+/// struct TimeoutsOnly;
+///
+/// impl RetryPolicy for TimeoutsOnly {
+///     fn decide(&self, attempt: u32, failure: FailureKind) -> RetryDecision {
+///         match failure {
+///             FailureKind::Timeout if attempt < 3 => RetryDecision::Retry(Duration::from_millis(200)),
+///             _ => RetryDecision::GiveUp,
+///         }
+///     }
+/// }
+///
+/// let client = ec2_instance_metadata::InstanceMetadataClient::builder()
+///     .retry_policy(TimeoutsOnly)
+///     .build();
+/// ```
+pub trait RetryPolicy: Send + Sync {
+    fn decide(&self, attempt: u32, failure: FailureKind) -> RetryDecision;
+}
+
+/// A `RetryPolicy` that never retries.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoRetry;
+
+impl RetryPolicy for NoRetry {
+    fn decide(&self, _attempt: u32, _failure: FailureKind) -> RetryDecision {
+        RetryDecision::GiveUp
+    }
+}
+
+/// `ExponentialBackoff` is the default `RetryPolicy`. It retries timeouts,
+/// resets, 5xx, and throttled responses up to `max_retries` times, sleeping a
+/// random duration between zero and `base * 2^(attempt - 1)`, capped at `cap`
+/// ("full jitter"). Refused connections aren't retried since they usually mean
+/// IMDS doesn't exist on this host.
+#[derive(Debug, Clone, Copy)]
+pub struct ExponentialBackoff {
+    max_retries: u32,
+    base: Duration,
+    cap: Duration,
+}
+
+impl ExponentialBackoff {
+    pub fn new(max_retries: u32, base: Duration, cap: Duration) -> Self {
+        Self {
+            max_retries,
+            base,
+            cap,
+        }
+    }
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self::new(2, Duration::from_millis(100), Duration::from_secs(1))
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn decide(&self, attempt: u32, failure: FailureKind) -> RetryDecision {
+        match failure {
+            FailureKind::ConnectionRefused | FailureKind::Other => return RetryDecision::GiveUp,
+            _ if attempt > self.max_retries => return RetryDecision::GiveUp,
+            _ => {}
+        }
+
+        let exponent = attempt.saturating_sub(1).min(31);
+        let ceiling = self
+            .base
+            .checked_mul(1 << exponent)
+            .unwrap_or(self.cap)
+            .min(self.cap);
+
+        RetryDecision::Retry(ceiling.mul_f64(jitter()))
+    }
+}
+
+/// A cheap pseudo-random value in [0, 1). Retry jitter only needs to spread
+/// callers out, not be unpredictable.
+fn jitter() -> f64 {
+    static STATE: AtomicU64 = AtomicU64::new(0);
+
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    let mut x = STATE.fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed) ^ seed;
+
+    // splitmix64 finalizer
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A shareable `RetryPolicy` that can live in `Debug` structs.
+#[derive(Clone)]
+pub(crate) struct SharedRetryPolicy(pub(crate) Arc<dyn RetryPolicy>);
+
+impl Default for SharedRetryPolicy {
+    fn default() -> Self {
+        SharedRetryPolicy(Arc::new(ExponentialBackoff::default()))
+    }
+}
+
+impl std::fmt::Debug for SharedRetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "RetryPolicy")
+    }
+}
@@ -0,0 +1,564 @@
+use crate::coalesce::lock;
+use crate::InstanceMetadataClient;
+use std::any::Any;
+use std::future::Future;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+#[cfg(any(feature = "aws-credential-types", feature = "rusoto"))]
+use std::time::{Duration, SystemTime};
+
+/// `Ec2MetadataRegionProvider` gives the AWS SDK for Rust the instance's
+/// region, as `InstanceMetadataClient::get_field(Field::Region)` resolves it,
+/// so the SDK doesn't need its own IMDS client.
+///
+/// The region is cached by the client after the first lookup, and requests
+/// follow the client's timeouts and retries. If IMDS can't be reached or has
+/// no region, the provider gives `None`, so a provider chain moves on. Until
+/// the region is cached, each lookup runs on a thread of its own, as the
+/// client is blocking.
+///
+/// # Examples:
+/// ```no_run
+/// use aws_config::BehaviorVersion;
+/// use ec2_instance_metadata::Ec2MetadataRegionProvider;
+///
+/// # async fn example() {
+/// let config = aws_config::defaults(BehaviorVersion::latest())
+///     .region(Ec2MetadataRegionProvider::new())
+///     .load()
+///     .await;
+/// println!("region: {:?}", config.region());
+/// # }
+/// ```
+#[cfg(feature = "aws-config")]
+#[derive(Debug, Clone, Default)]
+pub struct Ec2MetadataRegionProvider {
+    client: InstanceMetadataClient,
+}
+
+#[cfg(feature = "aws-config")]
+impl Ec2MetadataRegionProvider {
+    /// A provider using a default `InstanceMetadataClient`.
+    pub fn new() -> Ec2MetadataRegionProvider {
+        Ec2MetadataRegionProvider::default()
+    }
+
+    /// A provider using `client`, sharing its configuration and cache.
+    pub fn from_client(client: InstanceMetadataClient) -> Ec2MetadataRegionProvider {
+        Ec2MetadataRegionProvider { client }
+    }
+}
+
+#[cfg(feature = "aws-config")]
+impl aws_config::meta::region::ProvideRegion for Ec2MetadataRegionProvider {
+    fn region(&self) -> aws_config::meta::region::future::ProvideRegion<'_> {
+        use aws_config::meta::region::future::ProvideRegion;
+
+        if let Some(region) = self.client.cached_region() {
+            return ProvideRegion::ready(Some(aws_config::Region::new(region)));
+        }
+
+        let client = self.client.clone();
+        let region = spawn_blocking(move || client.get_field(crate::Field::Region).ok().flatten());
+        ProvideRegion::new(async move {
+            match region.await {
+                Ok(region) => region.map(aws_config::Region::new),
+                Err(e) => {
+                    log::error!("looking up the region failed: {}", e);
+                    None
+                }
+            }
+        })
+    }
+}
+
+/// `Ec2MetadataCredentialsProvider` gives the AWS SDK for Rust the IAM role
+/// credentials of the instance profile, as
+/// `InstanceMetadataClient::get_credentials` fetches them, so the SDK doesn't
+/// need its own IMDS client.
+///
+/// The credentials are cached along with their expiration, and fetched again
+/// once they're within the refresh margin of expiring, 5 minutes by default.
+/// An instance without a role, or a host where IMDS can't be reached or times
+/// out, gives the SDK's "not loaded" error, so a provider chain moves on; other failures
+/// are provider errors carrying this crate's `Error`. Fetches run on a thread
+/// of their own, as the client is blocking; cached credentials are served
+/// without one.
+///
+/// # Examples:
+/// ```no_run
+/// use aws_credential_types::provider::ProvideCredentials;
+/// use ec2_instance_metadata::Ec2MetadataCredentialsProvider;
+///
+/// # async fn example() {
+/// let provider = Ec2MetadataCredentialsProvider::new();
+/// let credentials = provider.provide_credentials().await.expect("Couldn't get credentials.");
+/// println!("expires: {:?}", credentials.expiry());
+/// # }
+/// ```
+#[cfg(feature = "aws-credential-types")]
+#[derive(Debug, Clone, Default)]
+pub struct Ec2MetadataCredentialsProvider {
+    cache: CredentialsCache,
+}
+
+#[cfg(feature = "aws-credential-types")]
+impl Ec2MetadataCredentialsProvider {
+    /// How long before they expire the credentials are fetched again, unless
+    /// `refresh_margin` says otherwise. IMDS has new credentials ready at
+    /// least this long before the old ones expire.
+    pub const DEFAULT_REFRESH_MARGIN: Duration = DEFAULT_REFRESH_MARGIN;
+
+    /// A provider using a default `InstanceMetadataClient`.
+    pub fn new() -> Ec2MetadataCredentialsProvider {
+        Ec2MetadataCredentialsProvider::default()
+    }
+
+    /// A provider using `client`, sharing its configuration and token.
+    pub fn from_client(client: InstanceMetadataClient) -> Ec2MetadataCredentialsProvider {
+        Ec2MetadataCredentialsProvider {
+            cache: CredentialsCache::new(client),
+        }
+    }
+
+    /// Fetch new credentials once the cached ones are within `margin` of
+    /// expiring.
+    pub fn refresh_margin(mut self, margin: Duration) -> Ec2MetadataCredentialsProvider {
+        self.cache.refresh_margin = margin;
+        self
+    }
+
+    fn load(&self) -> aws_credential_types::provider::Result {
+        use aws_credential_types::provider::error::CredentialsError;
+
+        let credentials = self.cache.get().map_err(|e| {
+            if not_loaded(&e) {
+                CredentialsError::not_loaded(e)
+            } else {
+                CredentialsError::provider_error(e)
+            }
+        })?;
+
+        Ok(sdk_credentials(credentials))
+    }
+}
+
+/// `credentials` as the SDK's.
+#[cfg(feature = "aws-credential-types")]
+fn sdk_credentials(credentials: crate::Credentials) -> aws_credential_types::Credentials {
+    aws_credential_types::Credentials::new(
+        credentials.access_key_id,
+        credentials.secret_access_key,
+        Some(credentials.token),
+        Some(credentials.expiration),
+        PROVIDER_NAME,
+    )
+}
+
+#[cfg(feature = "aws-credential-types")]
+impl aws_credential_types::provider::ProvideCredentials for Ec2MetadataCredentialsProvider {
+    fn provide_credentials<'a>(
+        &'a self,
+    ) -> aws_credential_types::provider::future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        use aws_credential_types::provider::error::CredentialsError;
+        use aws_credential_types::provider::future::ProvideCredentials;
+
+        if let Some(credentials) = self.cache.cached() {
+            return ProvideCredentials::ready(Ok(sdk_credentials(credentials)));
+        }
+
+        let provider = self.clone();
+        let credentials = spawn_blocking(move || provider.load());
+        ProvideCredentials::new(async move {
+            credentials
+                .await
+                .unwrap_or_else(|e| Err(CredentialsError::provider_error(e)))
+        })
+    }
+}
+
+/// `Ec2MetadataRusotoProvider` gives Rusoto the IAM role credentials of the
+/// instance profile, as `InstanceMetadataClient::get_credentials` fetches
+/// them, in place of Rusoto's own `InstanceMetadataProvider`. Unlike that,
+/// it uses IMDSv2.
+///
+/// The credentials are cached along with their expiration, and fetched again
+/// once they're within the refresh margin of expiring, 5 minutes by default.
+/// Failures are Rusoto `CredentialsError`s with this crate's error as their
+/// message. Fetches run on a thread of their own, as the client is blocking;
+/// cached credentials are served without one.
+///
+/// # Examples:
+/// ```no_run
+/// use ec2_instance_metadata::Ec2MetadataRusotoProvider;
+/// use rusoto_core::{HttpClient, Region};
+/// use rusoto_s3::{S3Client, S3};
+///
+/// # async fn example() {
+/// let client = S3Client::new_with(
+///     HttpClient::new().expect("Couldn't create an HTTP client."),
+///     Ec2MetadataRusotoProvider::new(),
+///     Region::default(),
+/// );
+/// let buckets = client.list_buckets().await.expect("Couldn't list buckets.");
+/// println!("{:?}", buckets.buckets);
+/// # }
+/// ```
+#[cfg(feature = "rusoto")]
+#[derive(Debug, Clone, Default)]
+pub struct Ec2MetadataRusotoProvider {
+    cache: CredentialsCache,
+}
+
+#[cfg(feature = "rusoto")]
+impl Ec2MetadataRusotoProvider {
+    /// How long before they expire the credentials are fetched again, unless
+    /// `refresh_margin` says otherwise. IMDS has new credentials ready at
+    /// least this long before the old ones expire.
+    pub const DEFAULT_REFRESH_MARGIN: Duration = DEFAULT_REFRESH_MARGIN;
+
+    /// A provider using a default `InstanceMetadataClient`.
+    pub fn new() -> Ec2MetadataRusotoProvider {
+        Ec2MetadataRusotoProvider::default()
+    }
+
+    /// A provider using `client`, sharing its configuration and token.
+    pub fn from_client(client: InstanceMetadataClient) -> Ec2MetadataRusotoProvider {
+        Ec2MetadataRusotoProvider {
+            cache: CredentialsCache::new(client),
+        }
+    }
+
+    /// Fetch new credentials once the cached ones are within `margin` of
+    /// expiring.
+    pub fn refresh_margin(mut self, margin: Duration) -> Ec2MetadataRusotoProvider {
+        self.cache.refresh_margin = margin;
+        self
+    }
+}
+
+#[cfg(feature = "rusoto")]
+#[async_trait::async_trait]
+impl rusoto_credential::ProvideAwsCredentials for Ec2MetadataRusotoProvider {
+    async fn credentials(
+        &self,
+    ) -> Result<rusoto_credential::AwsCredentials, rusoto_credential::CredentialsError> {
+        let credentials = match self.cache.cached() {
+            Some(credentials) => credentials,
+            None => {
+                let cache = self.cache.clone();
+                spawn_blocking(move || cache.get().map_err(|e| e.full_message()))
+                    .await
+                    .unwrap_or_else(|e| Err(e.to_string()))
+                    .map_err(|e| {
+                        rusoto_credential::CredentialsError::new(format!(
+                            "couldn't get credentials from IMDS: {}",
+                            e
+                        ))
+                    })?
+            }
+        };
+
+        Ok(rusoto_credential::AwsCredentials::new(
+            credentials.access_key_id,
+            credentials.secret_access_key,
+            Some(credentials.token),
+            Some(credentials.expiration.into()),
+        ))
+    }
+}
+
+/// How long before they expire cached credentials are fetched again.
+#[cfg(any(feature = "aws-credential-types", feature = "rusoto"))]
+const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// The name the SDK's credentials report as their provider.
+#[cfg(feature = "aws-credential-types")]
+const PROVIDER_NAME: &str = "Ec2InstanceMetadata";
+
+/// Whether `error` means there are no credentials to be had from IMDS
+/// here, rather than that getting them failed: the instance has no role, or
+/// IMDS can't be reached, as off EC2, where requests time out or are refused
+/// and the circuit breaker opens.
+#[cfg(feature = "aws-credential-types")]
+fn not_loaded(error: &crate::Error) -> bool {
+    matches!(
+        error,
+        crate::Error::CredentialsUnavailable(_) | crate::Error::CircuitOpen
+    ) || matches!(
+        error.kind(),
+        crate::ErrorKind::NotFound | crate::ErrorKind::Transport | crate::ErrorKind::Timeout
+    )
+}
+
+/// The credentials the providers share between clones, fetched again once
+/// they're within `refresh_margin` of expiring, or once the client's
+/// `refresh` has seen the instance change under them.
+#[cfg(any(feature = "aws-credential-types", feature = "rusoto"))]
+#[derive(Debug, Clone)]
+struct CredentialsCache {
+    client: InstanceMetadataClient,
+    refresh_margin: Duration,
+    // The credentials, with the client's identity generation they're from.
+    cached: Arc<Mutex<Option<(u64, crate::Credentials)>>>,
+}
+
+#[cfg(any(feature = "aws-credential-types", feature = "rusoto"))]
+impl CredentialsCache {
+    fn new(client: InstanceMetadataClient) -> CredentialsCache {
+        CredentialsCache {
+            client,
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+            cached: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The cached credentials, unless they're due to be fetched again.
+    fn cached(&self) -> Option<crate::Credentials> {
+        let (generation, credentials) = lock(&self.cached).clone()?;
+        if generation == self.client.identity_generation()
+            && credentials.expiration > SystemTime::now() + self.refresh_margin
+        {
+            Some(credentials)
+        } else {
+            None
+        }
+    }
+
+    fn get(&self) -> crate::Result<crate::Credentials> {
+        if let Some(credentials) = self.cached() {
+            return Ok(credentials);
+        }
+
+        let generation = self.client.identity_generation();
+        let credentials = self.client.get_credentials()?;
+        *lock(&self.cached) = Some((generation, credentials.clone()));
+        Ok(credentials)
+    }
+}
+
+#[cfg(any(feature = "aws-credential-types", feature = "rusoto"))]
+impl Default for CredentialsCache {
+    fn default() -> CredentialsCache {
+        CredentialsCache::new(InstanceMetadataClient::new())
+    }
+}
+
+/// Run `f` on a new thread, as a future of its result. If `f` panics, or the
+/// thread can't be started, the future gives an error saying so instead, so
+/// the caller isn't left waiting forever.
+fn spawn_blocking<T, F>(f: F) -> Blocking<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(BlockingState {
+        result: None,
+        waker: None,
+    }));
+
+    let thread_shared = shared.clone();
+    let spawned = std::thread::Builder::new()
+        .name("ec2-metadata-blocking".to_string())
+        .spawn(move || {
+            let result = catch_unwind(AssertUnwindSafe(f))
+                .map_err(|panic| BlockingError::Panicked(panic_message(&*panic)));
+            let waker = {
+                let mut state = lock(&thread_shared);
+                state.result = Some(result);
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        });
+    if let Err(e) = spawned {
+        lock(&shared).result = Some(Err(BlockingError::Spawn(e.to_string())));
+    }
+
+    Blocking { shared }
+}
+
+/// Why a `spawn_blocking` future had no result.
+#[derive(Debug, thiserror::Error)]
+enum BlockingError {
+    #[error("the IMDS lookup panicked: {0}")]
+    Panicked(String),
+
+    #[error("couldn't start a thread for the IMDS lookup: {0}")]
+    Spawn(String),
+}
+
+/// The message `panic!` was given, if it was a string.
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_default()
+}
+
+struct Blocking<T> {
+    shared: Arc<Mutex<BlockingState<T>>>,
+}
+
+struct BlockingState<T> {
+    result: Option<Result<T, BlockingError>>,
+    waker: Option<Waker>,
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = Result<T, BlockingError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, BlockingError>> {
+        let mut state = lock(&self.shared);
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "aws-credential-types", feature = "rusoto"))]
+    use crate::MockImds;
+    use std::task::Wake;
+    use std::thread::Thread;
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Poll `future` to completion on this thread.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn blocking_futures_give_the_result() {
+        assert_eq!(block_on(spawn_blocking(|| 42)).unwrap(), 42);
+    }
+
+    #[test]
+    fn blocking_futures_give_an_error_if_the_closure_panics() {
+        let result = block_on(spawn_blocking(|| -> u32 { panic!("boom") }));
+        match result {
+            Err(BlockingError::Panicked(message)) => assert_eq!(message, "boom"),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[cfg(any(feature = "aws-credential-types", feature = "rusoto"))]
+    #[test]
+    fn cached_credentials_are_dropped_after_an_identity_change() {
+        let mock = MockImds::start();
+        let client = mock.client();
+        client.get().unwrap();
+        let cache = CredentialsCache::new(client.clone());
+        cache.get().unwrap();
+
+        let path = format!("meta-data/iam/security-credentials/{}", MockImds::ROLE_NAME);
+        let fetches = || {
+            mock.requests()
+                .iter()
+                .filter(|r| r.path.ends_with(&path))
+                .count()
+        };
+        cache.get().unwrap();
+        assert_eq!(fetches(), 1);
+
+        mock.set("meta-data/instance-id", "i-0fedcba9876543210");
+        assert!(client.refresh().unwrap().identity_change.is_some());
+        cache.get().unwrap();
+        assert_eq!(fetches(), 2);
+    }
+
+    #[cfg(feature = "aws-credential-types")]
+    #[test]
+    fn credentials_provider_serves_cached_credentials() {
+        use aws_credential_types::provider::ProvideCredentials;
+
+        let mock = MockImds::start();
+        let provider = Ec2MetadataCredentialsProvider::from_client(mock.client());
+        for _ in 0..2 {
+            let credentials = block_on(provider.provide_credentials()).unwrap();
+            assert_eq!(credentials.access_key_id(), MockImds::ACCESS_KEY_ID);
+        }
+
+        let path = format!("meta-data/iam/security-credentials/{}", MockImds::ROLE_NAME);
+        let fetches = mock
+            .requests()
+            .iter()
+            .filter(|r| r.path.ends_with(&path))
+            .count();
+        assert_eq!(fetches, 1);
+    }
+
+    #[cfg(feature = "aws-credential-types")]
+    #[test]
+    fn unreachable_imds_is_not_loaded() {
+        use crate::{Deadline, Error};
+        use std::time::Duration;
+
+        let timeout = Error::Timeout {
+            elapsed: Duration::from_secs(1),
+            limit: Duration::from_secs(1),
+            deadline: Deadline::Overall,
+        };
+        assert!(not_loaded(&timeout));
+        assert!(not_loaded(&Error::Token(Box::new(timeout))));
+        assert!(not_loaded(&Error::CircuitOpen));
+        assert!(not_loaded(&Error::CredentialsUnavailable(String::new())));
+
+        let server_error = Error::HttpStatus {
+            status: 500,
+            path: "/latest/api/token".to_string(),
+            body_excerpt: String::new(),
+        };
+        assert!(!not_loaded(&server_error));
+    }
+
+    #[cfg(feature = "aws-credential-types")]
+    #[test]
+    fn timing_out_imds_gives_not_loaded() {
+        use crate::{InstanceMetadataClient, NoRetry};
+        use aws_credential_types::provider::error::CredentialsError;
+        use aws_credential_types::provider::ProvideCredentials;
+        use std::time::Duration;
+
+        let mock = MockImds::start();
+        mock.set_delay(Duration::from_millis(300));
+        let client = InstanceMetadataClient::builder()
+            .endpoint(mock.endpoint())
+            .retry_policy(NoRetry)
+            .request_timeout(Duration::from_millis(50))
+            .build();
+        let provider = Ec2MetadataCredentialsProvider::from_client(client);
+        match block_on(provider.provide_credentials()) {
+            Err(CredentialsError::CredentialsNotLoaded(_)) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+}
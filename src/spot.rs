@@ -0,0 +1,156 @@
+use crate::rfc3339;
+use crate::{Error, InstanceMetadataClient, Result};
+use std::time::SystemTime;
+
+const INSTANCE_ACTION_PATH: &str = "meta-data/spot/instance-action";
+const REBALANCE_PATH: &str = "meta-data/events/recommendations/rebalance";
+
+/// `InterruptionNotice` is the two-minute warning IMDS serves before a Spot
+/// instance is stopped, hibernated, or terminated.
+///
+/// # Examples:
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use ec2_instance_metadata::{Error, InterruptionNotice};
+///
+/// let notice =
+///     InterruptionNotice::from_json(r#"{"action": "terminate", "time": "2023-11-14T22:13:20Z"}"#)
+///         .unwrap();
+/// assert_eq!(notice.action, "terminate");
+/// assert_eq!(notice.time, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+/// assert_eq!(notice.to_string(), "terminate at 2023-11-14T22:13:20Z");
+/// assert_eq!(InterruptionNotice::from_json(&notice.to_json()).unwrap(), notice);
+///
+/// assert_eq!(
+///     InterruptionNotice::from_json(r#"{"action": "stop", "time": "soon"}"#),
+///     Err(Error::MissingField("time"))
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterruptionNotice {
+    /// What will happen to the instance: `"stop"`, `"hibernate"`, or
+    /// `"terminate"`
+    pub action: String,
+
+    /// When it will happen
+    pub time: SystemTime,
+}
+
+impl InterruptionNotice {
+    /// Parse the `spot/instance-action` document, or one written by `to_json`.
+    pub fn from_json(json: &str) -> Result<InterruptionNotice> {
+        let parsed = json::parse(json)?;
+        let action = match parsed["action"].as_str() {
+            Some(action) if !action.is_empty() => action.to_string(),
+            _ => return Err(Error::MissingField("action")),
+        };
+        let time = parsed["time"]
+            .as_str()
+            .and_then(rfc3339::parse)
+            .ok_or(Error::MissingField("time"))?;
+
+        Ok(InterruptionNotice { action, time })
+    }
+
+    /// The notice as a JSON object, shaped like the IMDS document.
+    pub fn to_json(&self) -> String {
+        json::object! {
+            "action" => self.action.as_str(),
+            "time" => rfc3339::format(self.time),
+        }
+        .dump()
+    }
+}
+
+impl std::fmt::Display for InterruptionNotice {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at {}", self.action, rfc3339::format(self.time))
+    }
+}
+
+/// `RebalanceRecommendation` is IMDS's early signal that a Spot instance is at
+/// elevated risk of interruption, usually well before an `InterruptionNotice`.
+///
+/// # Examples:
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use ec2_instance_metadata::{Error, RebalanceRecommendation};
+///
+/// let recommendation =
+///     RebalanceRecommendation::from_json(r#"{"noticeTime": "2023-11-14T22:13:20Z"}"#).unwrap();
+/// assert_eq!(recommendation.notice_time, UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+/// assert_eq!(recommendation.to_string(), "rebalance recommended at 2023-11-14T22:13:20Z");
+/// assert_eq!(
+///     RebalanceRecommendation::from_json(&recommendation.to_json()).unwrap(),
+///     recommendation
+/// );
+///
+/// assert_eq!(
+///     RebalanceRecommendation::from_json("{}"),
+///     Err(Error::MissingField("noticeTime"))
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebalanceRecommendation {
+    /// When the recommendation was issued
+    pub notice_time: SystemTime,
+}
+
+impl RebalanceRecommendation {
+    /// Parse the `events/recommendations/rebalance` document, or one written
+    /// by `to_json`.
+    pub fn from_json(json: &str) -> Result<RebalanceRecommendation> {
+        let parsed = json::parse(json)?;
+        let notice_time = parsed["noticeTime"]
+            .as_str()
+            .and_then(rfc3339::parse)
+            .ok_or(Error::MissingField("noticeTime"))?;
+
+        Ok(RebalanceRecommendation { notice_time })
+    }
+
+    /// The recommendation as a JSON object, shaped like the IMDS document.
+    pub fn to_json(&self) -> String {
+        json::object! {
+            "noticeTime" => rfc3339::format(self.notice_time),
+        }
+        .dump()
+    }
+}
+
+impl std::fmt::Display for RebalanceRecommendation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "rebalance recommended at {}",
+            rfc3339::format(self.notice_time)
+        )
+    }
+}
+
+impl InstanceMetadataClient {
+    /// Get the pending Spot interruption, or `None` if there is none - which
+    /// includes On-Demand instances.
+    ///
+    /// # Examples:
+    /// ```no_run
+    /// let client = ec2_instance_metadata::InstanceMetadataClient::new();
+    /// if let Some(notice) = client.get_spot_interruption().unwrap() {
+    ///     println!("draining, instance will {}", notice);
+    /// }
+    /// ```
+    pub fn get_spot_interruption(&self) -> Result<Option<InterruptionNotice>> {
+        let token = self.get_token()?;
+        self.fetch_path(&token, INSTANCE_ACTION_PATH)?
+            .map(|body| InterruptionNotice::from_json(&body))
+            .transpose()
+    }
+
+    /// Get the rebalance recommendation, or `None` if there is none.
+    pub fn get_rebalance_recommendation(&self) -> Result<Option<RebalanceRecommendation>> {
+        let token = self.get_token()?;
+        self.fetch_path(&token, REBALANCE_PATH)?
+            .map(|body| RebalanceRecommendation::from_json(&body))
+            .transpose()
+    }
+}
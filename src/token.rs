@@ -0,0 +1,202 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// `Token` is an IMDSv2 session token along with the time it expires.
+///
+/// Tokens can be obtained with `InstanceMetadataClient::fetch_token` and handed
+/// to other clients with `InstanceMetadataClient::with_token`, or attached to
+/// your own requests via the `X-aws-ec2-metadata-token` header. The `Debug` and
+/// `Display` output never include the token value.
+///
+/// # Examples:
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use ec2_instance_metadata::Token;
+///
+/// let token = Token::new("AQAEAFTNrA4=", SystemTime::now() + Duration::from_secs(60));
+/// assert_eq!(token.as_str(), "AQAEAFTNrA4=");
+/// assert!(!format!("{:?} {}", token, token).contains("AQAEAFTNrA4="));
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct Token {
+    value: String,
+    expires_at: SystemTime,
+}
+
+impl Token {
+    pub fn new<S: Into<String>>(value: S, expires_at: SystemTime) -> Self {
+        Self {
+            value: value.into(),
+            expires_at,
+        }
+    }
+
+    /// The secret token value, as sent in the `X-aws-ec2-metadata-token` header.
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
+
+    /// When IMDS stops accepting the token.
+    pub fn expires_at(&self) -> SystemTime {
+        self.expires_at
+    }
+
+    /// Whether the token has expired, or will within `margin`.
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        self.expires_at <= SystemTime::now() + margin
+    }
+
+    /// Whether the token has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires_within(Duration::from_secs(0))
+    }
+}
+
+impl std::fmt::Debug for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Token")
+            .field("value", &"<redacted>")
+            .field("expires_at", &self.expires_at)
+            .finish()
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<redacted token>")
+    }
+}
+
+/// `TokenPersistence` controls whether the client saves its IMDSv2 token to
+/// disk so short-lived processes can reuse it instead of each requesting one.
+///
+/// Persistence is disabled by default. Setting the
+/// `EC2_METADATA_DISABLE_TOKEN_PERSISTENCE` environment variable to any value
+/// disables it regardless of how the client was configured.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TokenPersistence {
+    /// Keep the token in memory only.
+    #[default]
+    Disabled,
+
+    /// Save the token to this file, readable and writable only by the owner.
+    File(PathBuf),
+}
+
+impl TokenPersistence {
+    const DISABLE_ENV_VAR: &'static str = "EC2_METADATA_DISABLE_TOKEN_PERSISTENCE";
+
+    fn path(&self) -> Option<&Path> {
+        match self {
+            TokenPersistence::File(path) if std::env::var_os(Self::DISABLE_ENV_VAR).is_none() => {
+                Some(path)
+            }
+            _ => None,
+        }
+    }
+
+    /// Load a previously saved token. Missing, corrupt, or expired files are
+    /// treated as absent.
+    pub(crate) fn load(&self, margin: Duration) -> Option<Token> {
+        let path = self.path()?;
+
+        let file = File::open(path).ok()?;
+        if !owner_only(&file) {
+            return None;
+        }
+
+        let mut contents = String::new();
+        file.take(MAX_FILE_SIZE)
+            .read_to_string(&mut contents)
+            .ok()?;
+
+        let mut lines = contents.lines();
+        let expires_at = lines.next()?.trim().parse::<u64>().ok()?;
+        let value = lines.next()?.trim();
+        if value.is_empty() || lines.next().is_some() {
+            return None;
+        }
+
+        let token = Token::new(value, UNIX_EPOCH + Duration::from_secs(expires_at));
+        if token.expires_within(margin) {
+            None
+        } else {
+            Some(token)
+        }
+    }
+
+    /// Save the token, replacing any existing file atomically.
+    pub(crate) fn store(&self, token: &Token) -> io::Result<()> {
+        let path = match self.path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let expires_at = token
+            .expires_at()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut tmp_name = path.as_os_str().to_owned();
+        tmp_name.push(format!(".{}.tmp", std::process::id()));
+        let tmp_path = PathBuf::from(tmp_name);
+
+        let result = create_owner_only(&tmp_path)
+            .and_then(|mut file| {
+                writeln!(file, "{}\n{}", expires_at, token.as_str())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp_path, path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+
+        result
+    }
+
+    /// Remove a saved token that IMDS has rejected.
+    pub(crate) fn clear(&self) {
+        if let Some(path) = self.path() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// Tokens are under 100 bytes; anything much larger isn't ours.
+const MAX_FILE_SIZE: u64 = 4096;
+
+#[cfg(unix)]
+fn create_owner_only(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_owner_only(path: &Path) -> io::Result<File> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+}
+
+#[cfg(unix)]
+fn owner_only(file: &File) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    file.metadata()
+        .map(|metadata| metadata.permissions().mode() & 0o077 == 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn owner_only(_file: &File) -> bool {
+    true
+}
@@ -0,0 +1,94 @@
+use crate::Result;
+
+/// A `tracing::Span` with the `tracing` feature, and nothing without it, so
+/// call sites don't need `cfg`s of their own.
+#[cfg(feature = "tracing")]
+pub(crate) type Span = tracing::Span;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Span;
+
+/// The span of an `InstanceMetadataClient::get`.
+#[cfg(feature = "tracing")]
+pub(crate) fn get_span() -> Span {
+    tracing::info_span!(
+        target: "ec2_instance_metadata",
+        "imds.get",
+        duration_ms = tracing::field::Empty,
+        error = tracing::field::Empty,
+    )
+}
+
+/// The span of one request for `path`.
+#[cfg(feature = "tracing")]
+pub(crate) fn fetch_span(path: &str) -> Span {
+    tracing::debug_span!(
+        target: "ec2_instance_metadata",
+        "imds.fetch",
+        path,
+        status = tracing::field::Empty,
+        duration_ms = tracing::field::Empty,
+        error = tracing::field::Empty,
+    )
+}
+
+/// The span of a token request.
+#[cfg(feature = "tracing")]
+pub(crate) fn token_span() -> Span {
+    tracing::debug_span!(
+        target: "ec2_instance_metadata",
+        "imds.token",
+        path = crate::InstanceMetadataClient::TOKEN_API_PATH,
+        status = tracing::field::Empty,
+        duration_ms = tracing::field::Empty,
+        error = tracing::field::Empty,
+    )
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn get_span() -> Span {
+    Span
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn fetch_span(_path: &str) -> Span {
+    Span
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn token_span() -> Span {
+    Span
+}
+
+/// Run `f` in `span`, recording how long it took and any error on it.
+#[cfg(feature = "tracing")]
+pub(crate) fn in_span<T, F>(span: Span, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    let _entered = span.enter();
+    let started = std::time::Instant::now();
+    let result = f();
+    span.record("duration_ms", started.elapsed().as_millis() as u64);
+    if let Err(e) = &result {
+        span.record("error", tracing::field::display(e));
+    }
+    result
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn in_span<T, F>(_span: Span, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    f()
+}
+
+/// Record the HTTP status of the response on the current span.
+#[cfg(feature = "tracing")]
+pub(crate) fn record_status(status: u16) {
+    tracing::Span::current().record("status", status);
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record_status(_status: u16) {}
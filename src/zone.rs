@@ -0,0 +1,321 @@
+use crate::error::Error;
+use crate::region::Region;
+
+/// `ZoneKind` is the kind of zone an availability zone name refers to.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::{zone_kind, Region, ZoneKind};
+///
+/// let zones = [
+///     ("us-east-1-wl1-bos-wlz-1", Region::UsEast1),
+///     ("us-east-1-wl1-nyc-wlz-1", Region::UsEast1),
+///     ("us-west-2-wl1-sea-wlz-1", Region::UsWest2),
+///     ("ap-northeast-1-wl1-nrt-wlz-1", Region::ApNortheast1),
+///     ("eu-west-2-wl1-lon-wlz-1", Region::EuWest2),
+///     ("eu-central-1-wl1-ber-wlz-1", Region::EuCentral1),
+/// ];
+/// for (zone, region) in &zones {
+///     assert_eq!(zone_kind(zone), ZoneKind::Wavelength, "{}", zone);
+///     assert_eq!(Region::from_availability_zone(zone).as_ref(), Some(region), "{}", zone);
+/// }
+///
+/// assert_eq!(zone_kind("us-east-1a"), ZoneKind::Standard);
+/// assert_eq!(zone_kind("us-gov-west-1b"), ZoneKind::Standard);
+/// assert_eq!(zone_kind("eu-north-2b"), ZoneKind::Standard);
+/// assert_eq!(zone_kind("us-west-2-lax-1a"), ZoneKind::LocalZone);
+/// assert_eq!(zone_kind("us-east-1-bos-1a"), ZoneKind::LocalZone);
+/// assert_eq!(zone_kind("us-east-1-wl1-bos-wlz"), ZoneKind::Unknown);
+/// assert_eq!(zone_kind("us-east-1"), ZoneKind::Unknown);
+/// assert_eq!(zone_kind("unknown"), ZoneKind::Unknown);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ZoneKind {
+    /// An availability zone in the region proper, e.g. `us-east-1a`
+    Standard,
+
+    /// A Local Zone, e.g. `us-west-2-lax-1a`
+    LocalZone,
+
+    /// A Wavelength Zone, e.g. `us-east-1-wl1-bos-wlz-1`
+    Wavelength,
+
+    /// A name that isn't shaped like any of the above. Outposts report the
+    /// availability zone they're anchored to, so they show up as `Standard`
+    /// rather than here.
+    Unknown,
+}
+
+/// The kind of zone `availability_zone` names, see `ZoneKind`.
+pub fn zone_kind(availability_zone: &str) -> ZoneKind {
+    if split_standard_zone(availability_zone).is_some() {
+        ZoneKind::Standard
+    } else if split_local_zone(availability_zone).is_some() {
+        ZoneKind::LocalZone
+    } else if split_wavelength_zone(availability_zone).is_some() {
+        ZoneKind::Wavelength
+    } else {
+        ZoneKind::Unknown
+    }
+}
+
+/// The zone letter at the end of an availability zone name, e.g. `'a'` for
+/// `us-east-1a` or the Local Zone `us-west-2-lax-1a`. `None` if the name
+/// doesn't end in a letter after the zone number, as with Wavelength Zones
+/// like `us-east-1-wl1-bos-wlz-1`.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::zone_letter;
+///
+/// assert_eq!(zone_letter("us-east-1a"), Some('a'));
+/// assert_eq!(zone_letter("ap-southeast-2c"), Some('c'));
+/// assert_eq!(zone_letter("us-gov-west-1b"), Some('b'));
+/// assert_eq!(zone_letter("us-west-2-lax-1a"), Some('a'));
+/// assert_eq!(zone_letter("us-east-1-bos-1a"), Some('a'));
+/// assert_eq!(zone_letter("us-east-1-wl1-bos-wlz-1"), None);
+/// assert_eq!(zone_letter("us-east-1"), None);
+/// assert_eq!(zone_letter("unknown"), None);
+/// assert_eq!(zone_letter(""), None);
+/// ```
+pub fn zone_letter(availability_zone: &str) -> Option<char> {
+    let mut chars = availability_zone.chars().rev();
+    match (chars.next(), chars.next()) {
+        (Some(letter), Some(number)) if letter.is_ascii_lowercase() && number.is_ascii_digit() => {
+            Some(letter)
+        }
+        _ => None,
+    }
+}
+
+/// The Local Zone an availability zone is in, e.g. `"lax-1"` for
+/// `us-west-2-lax-1a`. `None` for availability zones in the region proper
+/// and for names that aren't shaped like a Local Zone: the parent region, a
+/// `-`, a location code, a `-`, a number, and the zone letter.
+///
+/// The parent region is the part before the Local Zone, which
+/// `Region::from_availability_zone` reports for known regions.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::{local_zone, zone_letter, Region};
+///
+/// let zones = [
+///     ("us-west-2-lax-1a", Region::UsWest2, "lax-1", 'a'),
+///     ("us-west-2-lax-1b", Region::UsWest2, "lax-1", 'b'),
+///     ("us-west-2-den-1a", Region::UsWest2, "den-1", 'a'),
+///     ("us-west-2-phx-2a", Region::UsWest2, "phx-2", 'a'),
+///     ("us-east-1-bos-1a", Region::UsEast1, "bos-1", 'a'),
+///     ("us-east-1-mia-1a", Region::UsEast1, "mia-1", 'a'),
+///     ("us-east-1-dfw-2a", Region::UsEast1, "dfw-2", 'a'),
+///     ("us-east-1-atl-2a", Region::UsEast1, "atl-2", 'a'),
+///     ("ap-northeast-1-tpe-1a", Region::ApNortheast1, "tpe-1", 'a'),
+///     ("ap-southeast-1-bkk-1a", Region::ApSoutheast1, "bkk-1", 'a'),
+///     ("eu-central-1-ham-1a", Region::EuCentral1, "ham-1", 'a'),
+///     ("us-gov-west-1-lax-1a", Region::UsGovWest1, "lax-1", 'a'),
+/// ];
+/// for &(zone, ref region, group, letter) in &zones {
+///     assert_eq!(Region::from_availability_zone(zone).as_ref(), Some(region), "{}", zone);
+///     assert_eq!(local_zone(zone), Some(group), "{}", zone);
+///     assert_eq!(zone_letter(zone), Some(letter), "{}", zone);
+/// }
+///
+/// assert_eq!(local_zone("us-west-2a"), None);
+/// assert_eq!(local_zone("us-gov-west-1a"), None);
+/// assert_eq!(local_zone("us-west-2-lax-1"), None);
+/// assert_eq!(local_zone("us-east-1-wl1-bos-wlz-1"), None);
+/// assert_eq!(local_zone("unknown"), None);
+/// ```
+pub fn local_zone(availability_zone: &str) -> Option<&str> {
+    split_local_zone(availability_zone).map(|(_, local_zone)| local_zone)
+}
+
+/// Split a Local Zone name into its parent region and Local Zone, e.g.
+/// `us-west-2-lax-1a` into `("us-west-2", "lax-1")`.
+fn split_local_zone(availability_zone: &str) -> Option<(&str, &str)> {
+    zone_letter(availability_zone)?;
+    let name = &availability_zone[..availability_zone.len() - 1];
+
+    let (rest, number) = name.rsplit_once('-')?;
+    let (region, location) = rest.rsplit_once('-')?;
+    if !is_number(number)
+        || location.is_empty()
+        || !location.chars().all(|c| c.is_ascii_lowercase())
+        || !is_region_name(region)
+    {
+        return None;
+    }
+
+    Some((region, &name[region.len() + 1..]))
+}
+
+/// `ZoneInfo` is an availability zone name split into its parts, see
+/// `parse_availability_zone`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ZoneInfo {
+    /// The region the zone is in, or the parent region of a Local Zone or
+    /// Wavelength Zone
+    pub region: Region,
+
+    /// The kind of zone
+    pub kind: ZoneKind,
+
+    /// The Local Zone or Wavelength Zone within the region, e.g. `"lax-1"`
+    /// or `"wl1-bos-wlz-1"` - `None` for other kinds of zone
+    pub edge_zone: Option<String>,
+
+    /// The zone letter, see `zone_letter`
+    pub letter: Option<char>,
+}
+
+/// Parse an availability zone name, such as IMDS's
+/// `placement/availability-zone` or one from an ASG event or EBS volume
+/// description, the same way `InstanceMetadataClient::get` does when IMDS
+/// doesn't serve `placement/region`.
+///
+/// Standard zones, Local Zones, and Wavelength Zones are recognised by their
+/// shape, so zones in regions this crate doesn't know about parse too, with
+/// a `Region::Other` region. Any other name starting with a known region's
+/// name followed by `-` parses with that region and `ZoneKind::Unknown`.
+/// Anything else fails with `Error::UnknownAvailabilityZone`.
+///
+/// # Examples:
+/// ```
+/// use ec2_instance_metadata::{parse_availability_zone, Error, Region, ZoneKind};
+///
+/// let zones = [
+///     // (zone, region, kind, edge zone, letter)
+///     ("us-east-1a", Region::UsEast1, ZoneKind::Standard, None, Some('a')),
+///     ("eu-west-2c", Region::EuWest2, ZoneKind::Standard, None, Some('c')),
+///     ("ap-southeast-1b", Region::ApSoutheast1, ZoneKind::Standard, None, Some('b')),
+///     ("cn-northwest-1a", Region::CnNorthwest1, ZoneKind::Standard, None, Some('a')),
+///     ("us-gov-east-1b", Region::UsGovEast1, ZoneKind::Standard, None, Some('b')),
+///     ("us-isob-east-1a", Region::UsIsobEast1, ZoneKind::Standard, None, Some('a')),
+///     ("us-west-2-lax-1a", Region::UsWest2, ZoneKind::LocalZone, Some("lax-1"), Some('a')),
+///     ("us-east-1-bos-1a", Region::UsEast1, ZoneKind::LocalZone, Some("bos-1"), Some('a')),
+///     ("us-east-1-mia-2a", Region::UsEast1, ZoneKind::LocalZone, Some("mia-2"), Some('a')),
+///     ("us-gov-west-1-lax-1a", Region::UsGovWest1, ZoneKind::LocalZone, Some("lax-1"), Some('a')),
+///     ("us-east-1-wl1-bos-wlz-1", Region::UsEast1, ZoneKind::Wavelength, Some("wl1-bos-wlz-1"), None),
+///     ("eu-west-2-wl2-man-wlz-1", Region::EuWest2, ZoneKind::Wavelength, Some("wl2-man-wlz-1"), None),
+///     ("us-east-1-outpost", Region::UsEast1, ZoneKind::Unknown, None, None),
+/// ];
+/// for &(zone, ref region, kind, edge_zone, letter) in &zones {
+///     let info = parse_availability_zone(zone).unwrap();
+///     assert_eq!(&info.region, region, "{}", zone);
+///     assert_eq!(info.kind, kind, "{}", zone);
+///     assert_eq!(info.edge_zone.as_deref(), edge_zone, "{}", zone);
+///     assert_eq!(info.letter, letter, "{}", zone);
+/// }
+///
+/// // Every known region's zones parse.
+/// for region in Region::KNOWN {
+///     let zone = format!("{}a", region);
+///     assert_eq!(&parse_availability_zone(&zone).unwrap().region, region);
+/// }
+///
+/// // Zones in regions newer than the crate.
+/// let other = |zone| parse_availability_zone(zone).map(|info| info.region);
+/// assert_eq!(other("eu-north-2b"), Ok(Region::Other("eu-north-2".to_string())));
+/// assert_eq!(other("ap-southeast-10a"), Ok(Region::Other("ap-southeast-10".to_string())));
+/// assert_eq!(other("us-gov-central-1a"), Ok(Region::Other("us-gov-central-1".to_string())));
+/// assert_eq!(other("xx-new-1-abc-1a"), Ok(Region::Other("xx-new-1".to_string())));
+/// assert_eq!(other("xx-new-1-wl1-abc-wlz-1"), Ok(Region::Other("xx-new-1".to_string())));
+///
+/// for zone in &["", "us-east-1", "useast1a", "us-east-1A", "unknown", "1a"] {
+///     assert_eq!(
+///         parse_availability_zone(zone),
+///         Err(Error::UnknownAvailabilityZone(zone.to_string())),
+///         "{:?}",
+///         zone
+///     );
+/// }
+/// ```
+pub fn parse_availability_zone(availability_zone: &str) -> Result<ZoneInfo, Error> {
+    let (region, kind, edge_zone) = if let Some(region) = split_standard_zone(availability_zone) {
+        (region_named(region), ZoneKind::Standard, None)
+    } else if let Some((region, local_zone)) = split_local_zone(availability_zone) {
+        (region_named(region), ZoneKind::LocalZone, Some(local_zone))
+    } else if let Some((region, wavelength_zone)) = split_wavelength_zone(availability_zone) {
+        (
+            region_named(region),
+            ZoneKind::Wavelength,
+            Some(wavelength_zone),
+        )
+    } else {
+        match Region::from_availability_zone(availability_zone) {
+            Some(region) => (region, ZoneKind::Unknown, None),
+            None => {
+                return Err(Error::UnknownAvailabilityZone(
+                    availability_zone.to_string(),
+                ))
+            }
+        }
+    };
+
+    Ok(ZoneInfo {
+        region,
+        kind,
+        edge_zone: edge_zone.map(str::to_string),
+        letter: zone_letter(availability_zone),
+    })
+}
+
+fn region_named(name: &str) -> Region {
+    match name.parse() {
+        Ok(region) => region,
+        Err(never) => match never {},
+    }
+}
+
+/// The region of a region name followed by a zone letter, e.g. `us-east-1`
+/// for `us-east-1a` or `eu-north-2` for `eu-north-2b`.
+fn split_standard_zone(availability_zone: &str) -> Option<&str> {
+    zone_letter(availability_zone)?;
+    let region = &availability_zone[..availability_zone.len() - 1];
+
+    if is_region_name(region) {
+        Some(region)
+    } else {
+        None
+    }
+}
+
+/// Whether `name` is shaped like a region name: at least two words of
+/// lowercase letters and a number, joined by `-`, e.g. `us-gov-west-1`.
+pub(crate) fn is_region_name(name: &str) -> bool {
+    match name.rsplit_once('-') {
+        Some((words, number)) => {
+            is_number(number)
+                && words.contains('-')
+                && words
+                    .split('-')
+                    .all(|word| !word.is_empty() && word.chars().all(|c| c.is_ascii_lowercase()))
+        }
+        None => false,
+    }
+}
+
+/// Split a Wavelength Zone name into its parent region and Wavelength Zone,
+/// e.g. `us-east-1-wl1-bos-wlz-1` into `("us-east-1", "wl1-bos-wlz-1")`.
+fn split_wavelength_zone(availability_zone: &str) -> Option<(&str, &str)> {
+    let (rest, number) = availability_zone.rsplit_once('-')?;
+    let rest = rest.strip_suffix("-wlz")?;
+    let (rest, location) = rest.rsplit_once('-')?;
+    let (region, group) = rest.rsplit_once('-')?;
+    let group_number = group.strip_prefix("wl")?;
+    if !is_number(number)
+        || !is_number(group_number)
+        || location.is_empty()
+        || !location.chars().all(|c| c.is_ascii_lowercase())
+        || !is_region_name(region)
+    {
+        return None;
+    }
+
+    Some((region, &availability_zone[region.len() + 1..]))
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
+}
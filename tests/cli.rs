@@ -0,0 +1,350 @@
+//! Runs the `fetcher` binary against `MockImds`.
+
+use ec2_instance_metadata::MockImds;
+use std::process::{Command, Output};
+
+/// The endpoint of a port nothing listens on.
+const UNREACHABLE: &str = "http://127.0.0.1:1";
+
+fn fetcher(args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_fetcher"));
+    command
+        .args(args)
+        .env_remove("AWS_EC2_METADATA_SERVICE_ENDPOINT")
+        .env_remove("AWS_EC2_METADATA_DISABLED");
+    command
+}
+
+/// Run fetcher against `mock`.
+fn run(mock: &MockImds, args: &[&str]) -> Output {
+    let endpoint = mock.endpoint();
+    let mut all = vec!["--endpoint", endpoint.as_str()];
+    all.extend_from_slice(args);
+    fetcher(&all).output().expect("Couldn't run fetcher.")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn prints_the_metadata() {
+    let mock = MockImds::start();
+    let output = run(&mock, &[]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(stdout(&output).contains(MockImds::INSTANCE_ID));
+
+    let output = run(&mock, &["field", "instance-type"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), format!("{}\n", MockImds::INSTANCE_TYPE));
+}
+
+#[test]
+fn endpoint_flag_wins_over_the_environment() {
+    let mock = MockImds::start();
+    let endpoint = mock.endpoint();
+
+    let output = fetcher(&["field", "instance-id"])
+        .env("AWS_EC2_METADATA_SERVICE_ENDPOINT", &endpoint)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), format!("{}\n", MockImds::INSTANCE_ID));
+
+    let output = fetcher(&["--endpoint", &endpoint, "field", "instance-id"])
+        .env("AWS_EC2_METADATA_SERVICE_ENDPOINT", UNREACHABLE)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), format!("{}\n", MockImds::INSTANCE_ID));
+
+    let output = fetcher(&["--endpoint", UNREACHABLE, "field", "instance-id"])
+        .env("AWS_EC2_METADATA_SERVICE_ENDPOINT", &endpoint)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn exit_statuses() {
+    let output = fetcher(&["--endpoint", UNREACHABLE]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(stderr(&output).starts_with("fetcher: "));
+    assert_eq!(stderr(&output).lines().count(), 1);
+
+    let mock = MockImds::start();
+    mock.remove("meta-data/public-hostname");
+    let output = run(&mock, &["field", "public-hostname"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+
+    let output = run(&mock, &["raw", "meta-data/no-such-path"]);
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = run(&mock, &["--json", "field", "public-hostname"]);
+    assert_eq!(output.status.code(), Some(3));
+    let error: serde_json::Value = serde_json::from_str(&stderr(&output)).unwrap();
+    assert_eq!(error["kind"], "not_found");
+
+    // Invalid arguments aren't taken for an unreachable IMDS.
+    let output = run(&mock, &["--no-such-flag"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn check_prints_nothing_when_imds_is_sane() {
+    let mock = MockImds::start();
+    let output = run(&mock, &["check"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    let output = fetcher(&["--endpoint", UNREACHABLE, "check"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+
+    let disabled = MockImds::start();
+    disabled.set_status("api/token", 403);
+    let output = run(&disabled, &["check"]);
+    assert_eq!(output.status.code(), Some(4), "{}", stderr(&output));
+
+    let proxy = MockImds::start();
+    proxy.set("meta-data/instance-id", "<html><body>Welcome</body></html>");
+    let output = run(&proxy, &["check"]);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr(&output));
+}
+
+#[test]
+fn fields_are_printed_in_order() {
+    let mock = MockImds::start();
+    let output = run(&mock, &["--field", "region", "--field", "instance-id"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        format!("{}\n{}\n", MockImds::REGION, MockImds::INSTANCE_ID)
+    );
+
+    let output = run(
+        &mock,
+        &[
+            "--field",
+            "instance-id",
+            "--field",
+            "instance-type",
+            "--separator",
+            "\\t",
+        ],
+    );
+    assert_eq!(
+        stdout(&output),
+        format!("{}\t{}\n", MockImds::INSTANCE_ID, MockImds::INSTANCE_TYPE)
+    );
+}
+
+#[test]
+fn missing_fields_fail_unless_ignored() {
+    let mock = MockImds::start();
+    mock.remove("meta-data/public-hostname");
+    let args = ["--field", "instance-id", "--field", "public-hostname"];
+
+    let output = run(&mock, &args);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output).contains("public-hostname"));
+
+    let mut ignored = args.to_vec();
+    ignored.push("--ignore-missing");
+    let output = run(&mock, &ignored);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(stdout(&output).starts_with(MockImds::INSTANCE_ID));
+}
+
+#[test]
+fn formats() {
+    let mock = MockImds::start();
+
+    let output = run(&mock, &["--format", "json"]);
+    let metadata: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(metadata["instance_id"], MockImds::INSTANCE_ID);
+    assert_eq!(metadata["region"], MockImds::REGION);
+
+    let output = run(&mock, &["--format", "shell"]);
+    let shell = stdout(&output);
+    assert!(shell.contains(&format!(
+        "export EC2_INSTANCE_ID='{}'\n",
+        MockImds::INSTANCE_ID
+    )));
+    assert!(shell.contains(&format!("export AWS_REGION='{}'\n", MockImds::REGION)));
+
+    let output = run(&mock, &["--format", "shell", "--prefix", "IMDS_"]);
+    assert!(stdout(&output).contains(&format!(
+        "export IMDS_INSTANCE_ID='{}'\n",
+        MockImds::INSTANCE_ID
+    )));
+
+    let output = run(&mock, &["--format", "yaml"]);
+    assert!(stdout(&output).contains(&format!("instance_id: {}\n", MockImds::INSTANCE_ID)));
+
+    let output = run(
+        &mock,
+        &[
+            "--format",
+            "csv",
+            "--field",
+            "instance-id",
+            "--field",
+            "region",
+        ],
+    );
+    assert_eq!(
+        stdout(&output),
+        format!(
+            "instance_id,region\n{},{}\n",
+            MockImds::INSTANCE_ID,
+            MockImds::REGION
+        )
+    );
+
+    let output = run(
+        &mock,
+        &["--format", "csv", "--no-header", "--field", "instance-id"],
+    );
+    assert_eq!(stdout(&output), format!("{}\n", MockImds::INSTANCE_ID));
+}
+
+#[test]
+fn csv_values_are_quoted() {
+    let mock = MockImds::start();
+    mock.set("meta-data/hostname", "ip-10-0-0-1, \"internal\"");
+    let output = run(
+        &mock,
+        &["--format", "csv", "--no-header", "--field", "hostname"],
+    );
+    assert_eq!(stdout(&output), "\"ip-10-0-0-1, \"\"internal\"\"\"\n");
+}
+
+#[test]
+fn watch_interruption_takes_a_nonzero_duration() {
+    let mock = MockImds::start();
+    mock.set(
+        "meta-data/spot/instance-action",
+        r#"{"action": "terminate", "time": "2023-11-14T22:13:20Z"}"#,
+    );
+
+    for interval in &["5", "5s", "500ms"] {
+        let output = run(&mock, &["watch-interruption", "--interval", interval]);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+        assert!(stdout(&output).contains("terminate"));
+    }
+
+    for interval in &["0", "0s", "5x"] {
+        let output = run(&mock, &["watch-interruption", "--interval", interval]);
+        assert_eq!(output.status.code(), Some(1), "{}", interval);
+        assert!(stderr(&output).contains("--interval"));
+    }
+}
+
+#[test]
+fn output_file_is_replaced_only_on_success() {
+    let mock = MockImds::start();
+    let dir = std::env::temp_dir().join(format!("fetcher-cli-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("metadata.json");
+    let path_arg = path.to_str().unwrap();
+
+    let output = run(&mock, &["--format", "json", "--output-file", path_arg]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+    let written = std::fs::read_to_string(&path).unwrap();
+    let metadata: serde_json::Value = serde_json::from_str(&written).unwrap();
+    assert_eq!(metadata["instance_id"], MockImds::INSTANCE_ID);
+
+    let output = fetcher(&["--endpoint", UNREACHABLE, "--output-file", path_arg])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), written);
+
+    mock.remove("meta-data/public-hostname");
+    let output = run(
+        &mock,
+        &["--field", "public-hostname", "--output-file", path_arg],
+    );
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), written);
+
+    // Nothing but the file is left behind.
+    let entries = std::fs::read_dir(&dir).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(entries, 1);
+}
+
+#[test]
+fn dump_prints_a_subtree_with_credentials_redacted() {
+    let mock = MockImds::start();
+
+    let output = run(&mock, &["dump", "network/"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let network = stdout(&output);
+    assert!(network.contains("network/interfaces/macs/"));
+    assert!(network.lines().all(|line| line.starts_with("network/")));
+    assert!(!network.contains(MockImds::INSTANCE_ID));
+
+    let output = run(&mock, &["dump", "--path", "network/"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), network);
+
+    let output = run(&mock, &["dump", "network/", "--path", "iam/"]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let credentials = format!("iam/security-credentials/{}", MockImds::ROLE_NAME);
+    for args in &[
+        &["dump"][..],
+        &["dump", "iam/"],
+        &["--format", "json", "dump"],
+    ] {
+        let output = run(&mock, args);
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+        let dumped = stdout(&output);
+        assert!(dumped.contains(&credentials), "{:?}: {}", args, dumped);
+        assert!(dumped.contains("<redacted>"));
+        assert!(!dumped.contains(MockImds::ACCESS_KEY_ID));
+        assert!(!dumped.contains("SecretAccessKey"));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn output_file_is_created_owner_only() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mock = MockImds::start();
+    let dir = std::env::temp_dir().join(format!("fetcher-cli-mode-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("credentials.json");
+    let path_arg = path.to_str().unwrap();
+    let mode =
+        |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+    let output = run(&mock, &["credentials", "--output-file", path_arg]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(std::fs::read_to_string(&path)
+        .unwrap()
+        .contains(MockImds::ACCESS_KEY_ID));
+    assert_eq!(mode(&path), 0o600);
+
+    // An existing file's permissions are kept, even when they're wider.
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    let output = run(&mock, &["credentials", "--output-file", path_arg]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(mode(&path), 0o644);
+
+    let entries = std::fs::read_dir(&dir).unwrap().count();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(entries, 1);
+}